@@ -0,0 +1,550 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use arloader::{crypto::Provider, transaction::Tag};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use crate::utils::to_hex;
+
+// signature type of the data items signed with an arweave wallet
+const ARWEAVE_SIGNATURE_TYPE: u16 = 1;
+const SIGNATURE_LENGTH: usize = 512;
+const OWNER_LENGTH: usize = 512;
+// limits of the ANS-104 spec
+const MAX_TAGS: usize = 128;
+const MAX_TAG_NAME_LENGTH: usize = 1024;
+const MAX_TAG_VALUE_LENGTH: usize = 3072;
+// files are hashed by pieces of that size
+const READ_BUFFER_SIZE: usize = 1 << 20;
+
+// tags marking a data item whose data is a bundle of nested data items
+pub const BUNDLE_FORMAT: &str = "binary";
+pub const BUNDLE_VERSION: &str = "2.0.0";
+
+// Payload of a data item, files are only ever read piece by piece
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataSource {
+    Bytes(Vec<u8>),
+    File(PathBuf),
+    // ANS-104 bundle of the items, each item keeps its own source
+    Bundle(Vec<SignedDataItem>),
+}
+
+// ANS-104 data item signed ahead of its upload. Only its header is held in memory,
+// the data is read from its source as the item is sent
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedDataItem {
+    pub id: String,
+    // everything up to the data: signature, owner, target, anchor and tags
+    #[serde(with = "base64url")]
+    header: Vec<u8>,
+    data: DataSource,
+    data_len: u64,
+}
+
+impl DataSource {
+    pub async fn len(&self) -> anyhow::Result<u64> {
+        match self {
+            DataSource::Bytes(b) => Ok(b.len() as u64),
+            DataSource::File(path) => Ok(tokio::fs::metadata(path)
+                .await
+                .context(format!("could not read metadata of {:?}", path))?
+                .len()),
+            DataSource::Bundle(items) => Ok(bundle_header(items).len() as u64
+                + items.iter().map(|item| item.len()).sum::<u64>()),
+        }
+    }
+
+    // every file the data is read from
+    pub fn files(&self) -> Vec<PathBuf> {
+        match self {
+            DataSource::Bytes(_) => vec![],
+            DataSource::File(path) => vec![path.clone()],
+            DataSource::Bundle(items) => items.iter().flat_map(|item| item.data.files()).collect(),
+        }
+    }
+
+    // size of the data and the hasher it went through, files and bundles are streamed
+    async fn digest<D: Digest + Send>(&self) -> anyhow::Result<(u64, D)> {
+        let len = self.len().await?;
+        let mut hasher = D::new();
+        match self {
+            DataSource::Bytes(b) => hasher.update(b),
+            _ => {
+                let mut offset = 0;
+                while offset < len {
+                    let read = READ_BUFFER_SIZE.min((len - offset) as usize);
+                    hasher.update(self.read_at(offset, read).await?);
+                    offset += read as u64;
+                }
+            }
+        }
+        Ok((len, hasher))
+    }
+
+    // deep hash of the data as a blob
+    async fn deep_hash(&self) -> anyhow::Result<[u8; 48]> {
+        let (len, hasher) = self.digest::<Sha384>().await?;
+        Ok(blob_hash(len, hasher.finalize().into()))
+    }
+
+    // size and hex encoded sha256 of the data, what is served under the id of its item
+    pub async fn sha256(&self) -> anyhow::Result<(u64, String)> {
+        let (len, hasher) = self.digest::<Sha256>().await?;
+        Ok((len, to_hex(&hasher.finalize())))
+    }
+
+    // len bytes of the data from the offset, boxed as bundles read their items recursively
+    fn read_at(&self, offset: u64, len: usize) -> BoxFuture<'_, anyhow::Result<Vec<u8>>> {
+        async move {
+            match self {
+                DataSource::Bytes(b) => Ok(b[offset as usize..offset as usize + len].to_vec()),
+                DataSource::File(path) => {
+                    let mut file = File::open(path)
+                        .await
+                        .context(format!("could not open {:?}", path))?;
+                    file.seek(std::io::SeekFrom::Start(offset)).await?;
+                    let mut buffer = vec![0; len];
+                    file.read_exact(&mut buffer)
+                        .await
+                        .context(format!("could not read {} bytes of {:?}", len, path))?;
+                    Ok(buffer)
+                }
+                DataSource::Bundle(items) => {
+                    let end = offset + len as u64;
+                    let header = bundle_header(items);
+                    let mut bytes = Vec::with_capacity(len);
+                    let mut start = header.len() as u64;
+                    if offset < start {
+                        bytes.extend(&header[offset as usize..end.min(start) as usize]);
+                    }
+                    for item in items {
+                        let item_end = start + item.len();
+                        if offset < item_end && end > start {
+                            let from = offset.max(start) - start;
+                            let to = end.min(item_end) - start;
+                            bytes.extend(item.read_at(from, (to - from) as usize).await?);
+                        }
+                        start = item_end;
+                    }
+                    Ok(bytes)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+impl SignedDataItem {
+    // signs the data and tags with the wallet, without target nor anchor
+    pub async fn sign(
+        crypto: &Provider,
+        data: DataSource,
+        tags: &[Tag<String>],
+    ) -> anyhow::Result<Self> {
+        let owner = crypto.keypair_modulus()?.0;
+        if owner.len() != OWNER_LENGTH {
+            return Err(anyhow!(
+                "only 4096 bits arweave wallets can sign data items, got a {} bytes modulus",
+                owner.len()
+            ));
+        }
+        let tags_bytes = serialize_tags(tags)?;
+        let data_len = data.len().await?;
+
+        let message = deep_hash_list(&[
+            deep_hash(b"dataitem"),
+            deep_hash(b"1"),
+            deep_hash(ARWEAVE_SIGNATURE_TYPE.to_string().as_bytes()),
+            deep_hash(&owner),
+            // target
+            deep_hash(&[]),
+            // anchor
+            deep_hash(&[]),
+            deep_hash(&tags_bytes),
+            data.deep_hash().await?,
+        ]);
+        let signature = crypto.sign(&message)?;
+        if signature.len() != SIGNATURE_LENGTH {
+            return Err(anyhow!("unexpected signature of {} bytes", signature.len()));
+        }
+
+        let mut header =
+            Vec::with_capacity(2 + SIGNATURE_LENGTH + OWNER_LENGTH + 18 + tags_bytes.len());
+        header.extend(ARWEAVE_SIGNATURE_TYPE.to_le_bytes());
+        header.extend(&signature);
+        header.extend(&owner);
+        // neither target nor anchor
+        header.extend([0, 0]);
+        header.extend((tags.len() as u64).to_le_bytes());
+        header.extend((tags_bytes.len() as u64).to_le_bytes());
+        header.extend(&tags_bytes);
+
+        Ok(SignedDataItem {
+            id: URL_SAFE_NO_PAD.encode(Sha256::digest(&signature)),
+            header,
+            data,
+            data_len,
+        })
+    }
+
+    // the 32 bytes the id is the base64url encoding of
+    pub fn raw_id(&self) -> Vec<u8> {
+        Sha256::digest(&self.header[2..2 + SIGNATURE_LENGTH]).to_vec()
+    }
+
+    pub fn data(&self) -> &DataSource {
+        &self.data
+    }
+
+    // size of the serialized item
+    pub fn len(&self) -> u64 {
+        self.header.len() as u64 + self.data_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // len bytes of the serialized item from the offset, or what is left of it
+    pub async fn read_at(&self, offset: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+        let end = (offset + len as u64).min(self.len());
+        if offset >= end {
+            return Ok(vec![]);
+        }
+        let header_len = self.header.len() as u64;
+        let mut bytes = Vec::with_capacity((end - offset) as usize);
+        if offset < header_len {
+            bytes.extend(&self.header[offset as usize..end.min(header_len) as usize]);
+        }
+        if end > header_len {
+            let data_offset = offset.max(header_len) - header_len;
+            let data_end = end - header_len;
+            bytes.extend(
+                self.data
+                    .read_at(data_offset, (data_end - data_offset) as usize)
+                    .await?,
+            );
+        }
+        Ok(bytes)
+    }
+
+    // the whole serialized item, only for the ones small enough to be held in memory
+    pub async fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        self.read_at(0, self.len() as usize).await
+    }
+}
+
+// number of items then the size and id of each item, as 32 bytes little endian integers
+// for the counts and sizes
+fn bundle_header(items: &[SignedDataItem]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(32 + 64 * items.len());
+    header.extend(u256_le(items.len() as u64));
+    for item in items {
+        header.extend(u256_le(item.len()));
+        header.extend(item.raw_id());
+    }
+    header
+}
+
+fn u256_le(n: u64) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&n.to_le_bytes());
+    bytes
+}
+
+// headers are saved in base64url with the upload sessions
+mod base64url {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        URL_SAFE_NO_PAD.decode(s).map_err(serde::de::Error::custom)
+    }
+}
+
+// sha384(sha384("blob" + length) + sha384(data)), from the hash of the data
+fn blob_hash(len: u64, data_hash: [u8; 48]) -> [u8; 48] {
+    let tag = Sha384::digest(format!("blob{}", len).as_bytes());
+    let mut hasher = Sha384::new();
+    hasher.update(tag);
+    hasher.update(data_hash);
+    hasher.finalize().into()
+}
+
+pub fn deep_hash(blob: &[u8]) -> [u8; 48] {
+    blob_hash(blob.len() as u64, Sha384::digest(blob).into())
+}
+
+// deep hash of a list from the deep hashes of its items
+pub fn deep_hash_list(hashes: &[[u8; 48]]) -> [u8; 48] {
+    let mut acc: [u8; 48] = Sha384::digest(format!("list{}", hashes.len()).as_bytes()).into();
+    for hash in hashes {
+        let mut hasher = Sha384::new();
+        hasher.update(acc);
+        hasher.update(hash);
+        acc = hasher.finalize().into();
+    }
+    acc
+}
+
+// avro encoding of the tags as an array of {name: bytes, value: bytes} records,
+// no tags at all are encoded as nothing
+pub fn serialize_tags(tags: &[Tag<String>]) -> anyhow::Result<Vec<u8>> {
+    if tags.len() > MAX_TAGS {
+        return Err(anyhow!(
+            "{} tags, at most {} are allowed",
+            tags.len(),
+            MAX_TAGS
+        ));
+    }
+    let mut bytes = vec![];
+    if tags.is_empty() {
+        return Ok(bytes);
+    }
+    write_avro_long(&mut bytes, tags.len() as i64);
+    for tag in tags {
+        if tag.name.is_empty()
+            || tag.name.len() > MAX_TAG_NAME_LENGTH
+            || tag.value.len() > MAX_TAG_VALUE_LENGTH
+        {
+            return Err(anyhow!("invalid tag {}", tag.name));
+        }
+        for field in [&tag.name, &tag.value] {
+            write_avro_long(&mut bytes, field.len() as i64);
+            bytes.extend(field.as_bytes());
+        }
+    }
+    // end of the array
+    write_avro_long(&mut bytes, 0);
+    Ok(bytes)
+}
+
+// zigzag variable length integer
+fn write_avro_long(bytes: &mut Vec<u8>, n: i64) {
+    let mut n = ((n << 1) ^ (n >> 63)) as u64;
+    while n & !0x7f != 0 {
+        bytes.push((n & 0x7f | 0x80) as u8);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+#[cfg(test)]
+mod test {
+    use arloader::{transaction::FromUtf8Strs, Arweave};
+    use ring::signature::{RsaPublicKeyComponents, RSA_PSS_2048_8192_SHA256};
+
+    use crate::{mock_bundlr::TEST_WALLET, utils::create_random_tmp_folder};
+
+    use super::*;
+
+    // produced by tests/fixtures/data_item.py, independently of this module
+    const FIXTURE_ITEM: &str = "tests/fixtures/data_item.bin";
+    const FIXTURE_ID: &str = "RXDfZwZiBtyWUBgB_zXONbaefGh-AeF2FFnuq_HFrD4";
+
+    fn fixture_tags() -> Vec<Tag<String>> {
+        vec![
+            Tag::<String>::from_utf8_strs("Content-Type", "text/plain").unwrap(),
+            Tag::<String>::from_utf8_strs("App-Name", "archiver-fixture").unwrap(),
+        ]
+    }
+
+    // checks the signature of a serialized arweave signed item without target nor
+    // anchor against its owner, over the deep hash recomputed from its fields
+    fn verify(item: &[u8]) -> anyhow::Result<String> {
+        assert_eq!(item[..2], ARWEAVE_SIGNATURE_TYPE.to_le_bytes());
+        let signature = &item[2..2 + SIGNATURE_LENGTH];
+        let owner = &item[2 + SIGNATURE_LENGTH..2 + SIGNATURE_LENGTH + OWNER_LENGTH];
+        let mut offset = 2 + SIGNATURE_LENGTH + OWNER_LENGTH;
+        assert_eq!(item[offset..offset + 2], [0, 0]);
+        offset += 2 + 8;
+        let tags_len = u64::from_le_bytes(item[offset..offset + 8].try_into().unwrap()) as usize;
+        offset += 8;
+        let tags = &item[offset..offset + tags_len];
+        let data = &item[offset + tags_len..];
+
+        let message = deep_hash_list(&[
+            deep_hash(b"dataitem"),
+            deep_hash(b"1"),
+            deep_hash(b"1"),
+            deep_hash(owner),
+            deep_hash(&[]),
+            deep_hash(&[]),
+            deep_hash(tags),
+            deep_hash(data),
+        ]);
+        RsaPublicKeyComponents {
+            n: owner,
+            e: &[1, 0, 1][..],
+        }
+        .verify(&RSA_PSS_2048_8192_SHA256, &message, signature)
+        .map_err(|_| anyhow!("invalid signature"))?;
+        Ok(URL_SAFE_NO_PAD.encode(Sha256::digest(signature)))
+    }
+
+    #[tokio::test]
+    async fn signs_items_with_the_wallet() {
+        let arweave = Arweave::from_keypair_path(
+            PathBuf::from(TEST_WALLET),
+            "https://arweave.net".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        let item = SignedDataItem::sign(
+            &arweave.crypto,
+            DataSource::Bytes(b"some data".to_vec()),
+            &fixture_tags(),
+        )
+        .await
+        .unwrap();
+        let bytes = item.to_bytes().await.unwrap();
+        assert_eq!(verify(&bytes).unwrap(), item.id);
+        assert_eq!(
+            bytes[2 + SIGNATURE_LENGTH..2 + SIGNATURE_LENGTH + OWNER_LENGTH],
+            arweave.crypto.keypair_modulus().unwrap().0
+        );
+
+        // any change of the signed fields breaks the signature
+        let mut tampered = bytes.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(verify(&tampered).is_err());
+    }
+
+    #[tokio::test]
+    async fn signs_like_other_implementations() {
+        let fixture = std::fs::read(FIXTURE_ITEM).unwrap();
+        assert_eq!(verify(&fixture).unwrap(), FIXTURE_ID);
+
+        // the signatures are salted, everything else of the same item is identical
+        let arweave = Arweave::from_keypair_path(
+            PathBuf::from(TEST_WALLET),
+            "https://arweave.net".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        let data = b"archived by the archiver\n".to_vec();
+        let item = SignedDataItem::sign(&arweave.crypto, DataSource::Bytes(data), &fixture_tags())
+            .await
+            .unwrap();
+        let bytes = item.to_bytes().await.unwrap();
+        assert_eq!(bytes.len(), fixture.len());
+        assert_eq!(bytes[..2], fixture[..2]);
+        assert_eq!(
+            bytes[2 + SIGNATURE_LENGTH..],
+            fixture[2 + SIGNATURE_LENGTH..]
+        );
+    }
+
+    #[test]
+    fn serializes_tags() {
+        assert!(serialize_tags(&[]).unwrap().is_empty());
+        let tags = vec![Tag::<String>::from_utf8_strs("Content-Type", "text/plain").unwrap()];
+        let mut expected = vec![2, 24];
+        expected.extend(b"Content-Type");
+        expected.push(20);
+        expected.extend(b"text/plain");
+        expected.push(0);
+        assert_eq!(serialize_tags(&tags).unwrap(), expected);
+
+        let mut bytes = vec![];
+        write_avro_long(&mut bytes, 200);
+        assert_eq!(bytes, vec![0x90, 0x03]);
+    }
+
+    #[tokio::test]
+    async fn hashes_and_reads_files_like_bytes() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("data");
+        let data = (0..READ_BUFFER_SIZE * 2 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+        std::fs::write(&path, &data).unwrap();
+
+        let file = DataSource::File(path);
+        let bytes = DataSource::Bytes(data.clone());
+        assert_eq!(file.deep_hash().await.unwrap(), deep_hash(&data));
+        assert_eq!(bytes.deep_hash().await.unwrap(), deep_hash(&data));
+        assert_eq!(
+            file.read_at(READ_BUFFER_SIZE as u64 - 3, 10).await.unwrap(),
+            bytes
+                .read_at(READ_BUFFER_SIZE as u64 - 3, 10)
+                .await
+                .unwrap()
+        );
+
+        // an item is read across its header and data
+        let item = SignedDataItem {
+            id: String::new(),
+            header: vec![1, 2, 3],
+            data: file,
+            data_len: data.len() as u64,
+        };
+        assert_eq!(item.len(), data.len() as u64 + 3);
+        assert_eq!(item.read_at(1, 4).await.unwrap(), vec![2, 3, 0, 1]);
+        assert_eq!(
+            item.read_at(item.len() - 2, 10).await.unwrap(),
+            data[data.len() - 2..]
+        );
+        assert!(item.read_at(item.len(), 10).await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_bundles_of_items() {
+        let item = |byte: u8, data: Vec<u8>| SignedDataItem {
+            id: String::new(),
+            header: vec![byte; 2 + SIGNATURE_LENGTH + 10],
+            data_len: data.len() as u64,
+            data: DataSource::Bytes(data),
+        };
+        let items = vec![item(1, vec![5; 100]), item(2, vec![6; 3])];
+        let bundle = DataSource::Bundle(items.clone());
+
+        let mut expected = u256_le(2).to_vec();
+        for item in &items {
+            expected.extend(u256_le(item.len()));
+            expected.extend(item.raw_id());
+        }
+        for item in &items {
+            expected.extend(item.to_bytes().await.unwrap());
+        }
+        let len = bundle.len().await.unwrap();
+        assert_eq!(len, expected.len() as u64);
+        assert_eq!(bundle.read_at(0, len as usize).await.unwrap(), expected);
+        // across the end of the first item and the start of the second
+        let offset = 32 + 128 + items[0].len() - 5;
+        assert_eq!(
+            bundle.read_at(offset, 10).await.unwrap(),
+            expected[offset as usize..offset as usize + 10]
+        );
+        assert_eq!(bundle.deep_hash().await.unwrap(), deep_hash(&expected));
+    }
+
+    #[test]
+    fn deep_hashes_lists() {
+        // a list of one blob hashes the list tag then the blob
+        let blob = deep_hash(b"1");
+        let mut hasher = Sha384::new();
+        hasher.update(Sha384::digest(b"list1"));
+        hasher.update(blob);
+        let expected: [u8; 48] = hasher.finalize().into();
+        assert_eq!(deep_hash_list(&[blob]), expected);
+        assert_ne!(deep_hash_list(&[blob, blob]), expected);
+    }
+}
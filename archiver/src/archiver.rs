@@ -11,17 +11,28 @@ use std::{
 };
 
 use crate::{
-    contract::Contract,
+    browser_pool::{BrowserPool, BrowserPoolOptions},
+    capture::CaptureBackend,
+    config::ArchiverConfig,
+    contract::ArchiveContract,
+    fetcher::PageFetcher,
+    politeness::{HostPoliteness, PolitenessOptions},
+    readiness::ReadinessStrategy,
+    robots::RobotsPolicy,
     runner::{Runner, RunnerOptions},
+    scope::ScopeRule,
+    spending::{SpendingLedger, SpendingLimits},
+    storage::StorageBackend,
+    submissions::PendingSubmissions,
     types::ArchiverError,
     utils::get_unix_timestamp,
 };
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use atw::state::{ArchiveOptions, ArchiveRequest, ArchiveSubmission};
 use chrono::{DateTime, NaiveDateTime, Utc};
 use cron::Schedule;
 use futures::StreamExt;
-use log::{debug, error};
+use log::{debug, error, info};
 use tokio::{sync::mpsc, sync::mpsc::Sender, time::sleep};
 
 pub struct Archiver {
@@ -43,12 +54,72 @@ pub struct ArchiverOptions {
     #[builder(default = "2")]
     url_retries: i32,
     with_upload: bool,
+    // upload a single wacz file per crawl instead of the loose warc files
+    #[builder(default = "false")]
+    with_wacz: bool,
     #[builder(default = "5")]
     min_wait_after_navigation: u64,
     #[builder(default = "7")]
     max_wait_after_navigation: u64,
     #[builder(default = "45")]
     browser_timeout: u64,
+    #[builder(default = "ReadinessStrategy::NetworkIdle")]
+    readiness_strategy: ReadinessStrategy,
+    #[builder(default = "CaptureBackend::Pywb")]
+    capture_backend: CaptureBackend,
+    #[builder(default = "RobotsPolicy::Ignore")]
+    robots_policy: RobotsPolicy,
+    #[builder(default = "false")]
+    seed_from_sitemaps: bool,
+    #[builder(default = "1000")]
+    max_sitemap_urls: usize,
+    #[builder(default = "vec![]")]
+    scope_rules: Vec<ScopeRule>,
+    #[builder(default = "None")]
+    max_pages: Option<usize>,
+    #[builder(default = "None")]
+    max_bytes: Option<u64>,
+    // in seconds
+    #[builder(default = "None")]
+    max_duration: Option<u64>,
+    // number of chrome instances shared by all crawls
+    #[builder(default = "10")]
+    browser_pool_size: usize,
+    #[builder(default = "3")]
+    tabs_per_browser: usize,
+    // pages served by a browser before it gets relaunched
+    #[builder(default = "50")]
+    max_pages_per_browser: usize,
+    // maximum number of pages of the same host browsed at once, across all crawls
+    #[builder(default = "2")]
+    max_tabs_per_host: usize,
+    // minimum time between two visits of the same host in milliseconds
+    #[builder(default = "1000")]
+    min_host_delay: u64,
+    // slow down the hosts answering 429 or 503
+    #[builder(default = "true")]
+    adaptive_backoff: bool,
+    // save the state of the crawls under <writer_dir>/checkpoints so interrupted ones
+    // continue on restart
+    #[builder(default = "true")]
+    resume_crawls: bool,
+    // where the crawls are uploaded to and how they are tagged
+    #[builder(default = "ArchiverConfig::default()")]
+    config: ArchiverConfig,
+    #[builder(default = "PathBuf::from(\".secret/wallet.json\")")]
+    arweave_key_dir: PathBuf,
+    // maximum winston spent on the upload of a crawl
+    #[builder(default = "None")]
+    max_crawl_cost: Option<u128>,
+    // maximum winston spent on uploads per UTC day, kept in <writer_dir>/spending.json
+    #[builder(default = "None")]
+    max_daily_cost: Option<u128>,
+    // where the crawls are copied to besides bundlr
+    #[builder(default = "vec![]")]
+    storage: Vec<Arc<dyn StorageBackend>>,
+    // pages are browsed with the chrome browsers of the pool unless a fetcher is given
+    #[builder(default = "None")]
+    fetcher: Option<Arc<dyn PageFetcher>>,
 }
 
 impl ArchiverOptionsBuilder {
@@ -72,7 +143,7 @@ impl Archiver {
     }
     pub async fn archive(
         &mut self,
-        contract: Arc<Contract>,
+        contract: Arc<dyn ArchiveContract>,
         wallet_address: String,
         should_terminate: Arc<AtomicBool>,
     ) -> anyhow::Result<()> {
@@ -85,6 +156,35 @@ impl Archiver {
         let st = should_terminate.clone();
         let timeout = self.options.fetch_frequency;
 
+        let browser_pool = Arc::new(BrowserPool::new(
+            BrowserPoolOptions::default_builder()
+                .size(self.options.browser_pool_size)
+                .tabs_per_browser(self.options.tabs_per_browser)
+                .max_pages_per_browser(self.options.max_pages_per_browser)
+                .browser_timeout(self.options.browser_timeout)
+                .build()?,
+        )?);
+
+        // every crawl of the process shares the same pace per host
+        let politeness = Arc::new(HostPoliteness::new(
+            PolitenessOptions::default_builder()
+                .max_tabs_per_host(self.options.max_tabs_per_host)
+                .min_host_delay(self.options.min_host_delay)
+                .adaptive(self.options.adaptive_backoff)
+                .build()?,
+        ));
+
+        // every crawl of the process counts against the same daily spending
+        let mut spending_path = self.options.writer_dir.clone().unwrap_or_default();
+        spending_path.push("spending.json");
+        let spending = Arc::new(SpendingLedger::new(
+            SpendingLimits {
+                max_crawl_cost: self.options.max_crawl_cost,
+                max_daily_cost: self.options.max_daily_cost,
+            },
+            Some(spending_path),
+        )?);
+
         tokio::spawn(async move {
             loop {
                 if should_terminate.load(Ordering::Relaxed) {
@@ -114,7 +214,16 @@ impl Archiver {
             }
         });
 
-        self.processor(ct, wa, st.clone(), archiver_rx, processed_archive_tx);
+        self.processor(
+            ct,
+            wa,
+            st.clone(),
+            browser_pool,
+            politeness,
+            spending,
+            archiver_rx,
+            processed_archive_tx,
+        );
 
         while !st.load(Ordering::Relaxed) {
             let res = work_fetcher_rx.try_recv();
@@ -151,9 +260,12 @@ impl Archiver {
 
     fn processor(
         &self,
-        contract: Arc<Contract>,
+        contract: Arc<dyn ArchiveContract>,
         wallet_address: String,
         should_terminate: Arc<AtomicBool>,
+        browser_pool: Arc<BrowserPool>,
+        politeness: Arc<HostPoliteness>,
+        spending: Arc<SpendingLedger>,
         archiver_rx: mpsc::Receiver<ArchiveRequest>,
         processed_archiver_tx: mpsc::Sender<ArchiveRequest>,
     ) {
@@ -167,11 +279,23 @@ impl Archiver {
                     let w = wallet_address.clone();
                     let tx = processed_archiver_tx.clone();
                     let options = options.clone();
+                    let browser_pool = browser_pool.clone();
+                    let politeness = politeness.clone();
+                    let spending = spending.clone();
                     async move {
                         let id = archive_request.id.clone();
                         debug!("archive running for request {:#?}", archive_request);
-                        let res =
-                            Self::run(c, w, &archive_request, options, should_terminate).await;
+                        let res = Self::run(
+                            c,
+                            w,
+                            &archive_request,
+                            options,
+                            browser_pool,
+                            politeness,
+                            spending,
+                            should_terminate,
+                        )
+                        .await;
                         debug!("{:?}", res);
                         match res {
                             Ok(_) => {
@@ -199,7 +323,7 @@ impl Archiver {
     }
 
     async fn fetch_new_work(
-        c: Arc<Contract>,
+        c: Arc<dyn ArchiveContract>,
         wallet_address: String,
         archiver_tx: Sender<ArchiveRequest>,
         should_terminate: Arc<AtomicBool>,
@@ -237,6 +361,11 @@ impl Archiver {
         }
 
         for req in valid_reqs {
+            if req.options.urls.is_empty() {
+                error!("{}", ArchiverError::EmptyRequest(req.id.clone()));
+                continue;
+            }
+
             let schedule = match Schedule::from_str(&req.frequency) {
                 Ok(sched) => sched,
                 Err(e) => {
@@ -280,17 +409,50 @@ impl Archiver {
     }
 
     async fn run(
-        contract: Arc<Contract>,
+        contract: Arc<dyn ArchiveContract>,
         wallet_address: String,
         archive_request: &ArchiveRequest,
         options: ArchiverOptions,
+        browser_pool: Arc<BrowserPool>,
+        politeness: Arc<HostPoliteness>,
+        spending: Arc<SpendingLedger>,
         should_terminate: Arc<AtomicBool>,
     ) -> anyhow::Result<()> {
+        let urls = &archive_request.options.urls;
+        if urls.is_empty() {
+            return Err(ArchiverError::EmptyRequest(archive_request.id.clone()).into());
+        }
+
+        let mut submissions_dir = options.writer_dir.clone().unwrap_or_default();
+        submissions_dir.push("submissions");
+        let submissions_path = PendingSubmissions::path(&submissions_dir, &archive_request.id);
+        // a previous run uploaded the crawl but some of its submissions were refused
+        if let Some(pending) = PendingSubmissions::load(&submissions_path)? {
+            info!(
+                "resubmitting {} archives of request {}",
+                pending.submissions.len(),
+                archive_request.id
+            );
+            return Self::submit(contract, &submissions_path, pending).await;
+        }
+
+        let checkpoint_dir = if options.resume_crawls {
+            let mut dir = options.writer_dir.clone().unwrap_or_default();
+            dir.push("checkpoints");
+            Some(dir)
+        } else {
+            None
+        };
+
         let options = RunnerOptions::default_builder()
             .writer_dir(options.writer_dir)
             .concurrent_tabs(options.concurrent_tabs)
             .url_retries(options.url_retries)
             .with_upload(options.with_upload)
+            .with_wacz(options.with_wacz)
+            .config(options.config)
+            .arweave_key_dir(options.arweave_key_dir)
+            .fetcher(options.fetcher)
             .writer_port(None)
             .writer_debug(false)
             .archive_name(None)
@@ -298,7 +460,23 @@ impl Archiver {
             .timeout(options.browser_timeout)
             .min_wait_after_navigation(options.min_wait_after_navigation)
             .max_wait_after_navigation(options.max_wait_after_navigation)
+            .readiness_strategy(options.readiness_strategy)
+            .capture_backend(options.capture_backend)
+            .robots_policy(options.robots_policy)
+            .seed_from_sitemaps(options.seed_from_sitemaps)
+            .max_sitemap_urls(options.max_sitemap_urls)
+            .scope_rules(options.scope_rules)
+            .max_pages(options.max_pages)
+            .max_bytes(options.max_bytes)
+            .max_duration(options.max_duration)
             .crawl_type(archive_request.options.crawl_type.clone())
+            .browser_pool(browser_pool)
+            .politeness(politeness)
+            .spending(Some(spending))
+            .storage(options.storage)
+            .checkpoint_dir(checkpoint_dir)
+            .request_id(Some(archive_request.id.clone()))
+            .seeds(urls.clone())
             .build()?;
 
         debug!("launching crawler with options: \n {:#?}", options);
@@ -311,19 +489,23 @@ impl Archiver {
             return Err(ArchiverError::EarlyTermination.into());
         }
 
-        let url = &archive_request.options.urls[0];
+        // no crawl is started that could not be paid for
+        r.check_upload_funds().await?;
 
         let result = r
-            .run_archiving(url)
+            .run_archiving_seeds(urls)
             .await
-            .context(format!("archiving for url {} failed", url))?;
+            .context(format!("archiving for urls {:?} failed", urls))?;
         debug!("result {:?}", result);
 
         if should_terminate.load(Ordering::Relaxed) {
             return Err(ArchiverError::EarlyTermination.into());
         }
 
-        let main_file = result.warc_files[0].clone();
+        let main_file = match &result.wacz_file {
+            Some(wacz) => wacz.clone(),
+            None => result.warc_files[0].clone(),
+        };
 
         let metadata = fs::metadata(&main_file)
             .context(format!("could not open metadata file at {:?}", &main_file))?;
@@ -336,33 +518,354 @@ impl Archiver {
 
         let title = result.title.clone();
 
-        let upload_result = r.run_upload_crawl(result).await?;
+        let stored = r.run_store_crawl(&result).await?;
+        // from now on the submissions left are what is kept for the next run
+        r.remove_checkpoint()?;
 
-        debug!("upload result {:#?}", upload_result);
+        debug!("stored crawl {:#?}", stored);
+
+        // only the crawls on arweave can be submitted
+        let upload_result = match stored.upload {
+            Some(u) => u,
+            None => {
+                info!("crawl of {:?} stored without upload, not submitted", urls);
+                return Ok(());
+            }
+        };
 
         if should_terminate.load(Ordering::Relaxed) {
             return Err(ArchiverError::EarlyTermination.into());
         }
 
-        let archive_submission = ArchiveSubmission {
-            full_url: url.into(),
-            size: size as usize,
-            uploader_address: wallet_address.clone(),
-            archive_request_id: archive_request.id.clone(),
-            timestamp: ts,
-            arweave_tx: upload_result.warc_id[0].clone(),
-            options: ArchiveOptions {
-                depth: archive_request.options.depth,
-                crawl_type: archive_request.options.crawl_type.clone(),
-                domain_only: None,
+        let arweave_tx = match &upload_result.wacz_id {
+            Some(id) => id.clone(),
+            None => upload_result.warc_id[0].clone(),
+        };
+
+        // the contract keys archives by url, so every seed gets its own submission
+        // pointing to the shared crawl files
+        let mut submissions = vec![];
+        for url in urls {
+            submissions.push(ArchiveSubmission {
+                full_url: url.into(),
+                size: size as usize,
+                uploader_address: wallet_address.clone(),
+                archive_request_id: archive_request.id.clone(),
+                timestamp: ts,
+                arweave_tx: arweave_tx.clone(),
+                options: ArchiveOptions {
+                    depth: archive_request.options.depth,
+                    crawl_type: archive_request.options.crawl_type.clone(),
+                    domain_only: None,
+                },
+                screenshot_tx: upload_result.screenshot_id.clone(),
+                title: title.clone(),
+            });
+        }
+        Self::submit(
+            contract,
+            &submissions_path,
+            PendingSubmissions::new(&archive_request.id, submissions),
+        )
+        .await
+    }
+
+    // submits every seed even when one is refused, what is left is recorded after
+    // each submission so no seed is ever submitted twice
+    async fn submit(
+        contract: Arc<dyn ArchiveContract>,
+        path: &PathBuf,
+        mut pending: PendingSubmissions,
+    ) -> anyhow::Result<()> {
+        pending.write(path)?;
+        let mut refused = vec![];
+        while !pending.submissions.is_empty() {
+            let submission = pending.submissions.remove(0);
+            if let Err(e) = contract.submit_archive(&submission).await {
+                error!("could not submit archive {:?} {}", submission, e);
+                refused.push(submission);
+            }
+            let left = refused
+                .iter()
+                .chain(pending.submissions.iter())
+                .cloned()
+                .collect();
+            PendingSubmissions::new(&pending.request_id, left).write(path)?;
+        }
+
+        if refused.is_empty() {
+            return PendingSubmissions::remove(path);
+        }
+        Err(anyhow!(
+            "{} archives of request {} were refused, they are submitted again on its next run",
+            refused.len(),
+            pending.request_id
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use atw::state::{ArchiveRequestOptions, CrawlType};
+
+    use crate::{
+        checkpoint::CrawlCheckpoint,
+        manifest::ArchiveManifest,
+        mock_bundlr::{Endpoint, MockBundlr, TEST_WALLET},
+        mock_contract::MemoryContract,
+        mock_s3::MockS3,
+        mock_site::{SiteGraph, SitePage},
+        s3::{S3Config, S3Storage},
+        spending::UploadCost,
+        storage::LocalStorage,
+        utils::create_random_tmp_folder,
+    };
+
+    use super::*;
+
+    const UPLOADER_ADDRESS: &str = "k-JI7M_U8hoezJuaaxKx8IBFJ3KRA9DGNCUw0Baltyc";
+
+    fn archive_request(url: &str) -> ArchiveRequest {
+        ArchiveRequest {
+            id: "request-1".into(),
+            options: ArchiveRequestOptions {
+                urls: vec![url.into()],
+                depth: 1,
+                crawl_type: CrawlType::DomainOnly,
             },
-            screenshot_tx: upload_result.screenshot_id,
-            title: title,
+            frequency: "0 * * * * *".into(),
+            start_timestamp: 0,
+            end_timestamp: i64::MAX,
+            latest_archived_timestamp: 0,
+            uploader_address: UPLOADER_ADDRESS.into(),
+            requested_by: UPLOADER_ADDRESS.into(),
+        }
+    }
+
+    async fn run(
+        contract: Arc<MemoryContract>,
+        request: &ArchiveRequest,
+        options: &ArchiverOptions,
+    ) -> anyhow::Result<()> {
+        Archiver::run(
+            contract,
+            UPLOADER_ADDRESS.into(),
+            request,
+            options.clone(),
+            Arc::new(BrowserPool::new(
+                BrowserPoolOptions::default_builder().build()?,
+            )?),
+            Arc::new(HostPoliteness::new(
+                PolitenessOptions::default_builder()
+                    .min_host_delay(0u64)
+                    .build()?,
+            )),
+            Arc::new(SpendingLedger::new(SpendingLimits::default(), None)?),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn archives_uploads_and_submits_requests() {
+        let node = MockBundlr::start();
+        let dir = create_random_tmp_folder().unwrap();
+        let site = SiteGraph::new()
+            .with_page(
+                "https://example.com/",
+                SitePage {
+                    title: "Example".into(),
+                    ..SitePage::new(&["https://example.com/a", "https://other.com/"])
+                },
+            )
+            .page("https://example.com/a", &[]);
+        let options = ArchiverOptionsBuilder::default_builder()
+            .writer_dir(Some(dir.clone()))
+            .with_upload(true)
+            .capture_backend(CaptureBackend::Native)
+            .config(ArchiverConfig {
+                bundlr_url: node.url(),
+                ..ArchiverConfig::default()
+            })
+            .arweave_key_dir(PathBuf::from(TEST_WALLET))
+            .fetcher(Some(Arc::new(site) as Arc<dyn PageFetcher>))
+            .build()
+            .unwrap();
+        let request = archive_request("https://example.com/");
+        let contract = Arc::new(MemoryContract::new(vec![request.clone()]));
+
+        run(contract.clone(), &request, &options).await.unwrap();
+        let submissions = contract.submissions();
+        assert_eq!(submissions.len(), 1);
+        let submission = &submissions[0];
+        assert_eq!(submission.full_url, "https://example.com/");
+        assert_eq!(submission.archive_request_id, "request-1");
+        assert_eq!(submission.uploader_address, UPLOADER_ADDRESS);
+        assert_eq!(submission.title, "Example");
+        // the files of the crawl are nested in a single bundle
+        let warc = node.nested_item(&submission.arweave_tx).unwrap();
+        assert!(warc.len() > submission.size);
+        let screenshot = node.nested_item(&submission.screenshot_tx).unwrap();
+        assert!(screenshot.ends_with(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]));
+        assert_eq!(node.item_count(), 1);
+
+        // nothing is submitted for a crawl that could not be uploaded, which is
+        // kept to be resumed
+        let checkpoint = CrawlCheckpoint::path(&dir.join("checkpoints"), &request.id);
+        assert!(!checkpoint.exists());
+        node.fail(Endpoint::Upload, 500, 1);
+        assert!(run(contract.clone(), &request, &options).await.is_err());
+        assert_eq!(contract.submissions().len(), 1);
+        assert!(checkpoint.exists());
+
+        // nor for one the node does not serve back as it was uploaded
+        node.corrupt(true);
+        assert!(run(contract.clone(), &request, &options).await.is_err());
+        assert_eq!(contract.submissions().len(), 1);
+        node.corrupt(false);
+
+        contract.fail_submissions(1);
+        assert!(run(contract.clone(), &request, &options).await.is_err());
+        assert_eq!(contract.submissions().len(), 1);
+        assert!(!checkpoint.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn submits_refused_seeds_on_the_next_run() {
+        let node = MockBundlr::start();
+        let dir = create_random_tmp_folder().unwrap();
+        let seeds = [
+            "https://example.com/",
+            "https://example.com/a",
+            "https://example.com/b",
+        ];
+        let site = SiteGraph::new()
+            .page(seeds[0], &[])
+            .page(seeds[1], &[])
+            .page(seeds[2], &[]);
+        let options = ArchiverOptionsBuilder::default_builder()
+            .writer_dir(Some(dir.clone()))
+            .with_upload(true)
+            .capture_backend(CaptureBackend::Native)
+            .config(ArchiverConfig {
+                bundlr_url: node.url(),
+                ..ArchiverConfig::default()
+            })
+            .arweave_key_dir(PathBuf::from(TEST_WALLET))
+            .fetcher(Some(Arc::new(site) as Arc<dyn PageFetcher>))
+            .build()
+            .unwrap();
+        let mut request = archive_request(seeds[0]);
+        request.options.urls = seeds.iter().map(|s| s.to_string()).collect();
+        let contract = Arc::new(MemoryContract::new(vec![request.clone()]));
+
+        // the second seed is refused, the third one is still submitted
+        contract.fail_submission_of(seeds[1]);
+        assert!(run(contract.clone(), &request, &options).await.is_err());
+        let submitted = |contract: &MemoryContract| {
+            contract
+                .submissions()
+                .into_iter()
+                .map(|s| s.full_url)
+                .collect::<Vec<String>>()
         };
-        contract
-            .submit_archive(&archive_submission)
-            .await
-            .context(format!("could not submit archive {:?}", archive_submission))?;
-        Ok(())
+        assert_eq!(submitted(&contract), vec![seeds[0], seeds[2]]);
+        assert_eq!(node.item_count(), 1);
+
+        // the next run only submits the refused seed, without crawling again
+        run(contract.clone(), &request, &options).await.unwrap();
+        assert_eq!(submitted(&contract), vec![seeds[0], seeds[2], seeds[1]]);
+        assert_eq!(node.item_count(), 1);
+        let submissions = contract.submissions();
+        assert_eq!(submissions[1].arweave_tx, submissions[2].arweave_tx);
+
+        // then the request is archived as usual
+        run(contract.clone(), &request, &options).await.unwrap();
+        assert_eq!(contract.submissions().len(), 6);
+        assert_eq!(node.item_count(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stores_crawls_with_every_backend() {
+        let node = MockBundlr::start();
+        let bucket = MockS3::start();
+        let dir = create_random_tmp_folder().unwrap();
+        let mut store = dir.clone();
+        store.push("store");
+        let wrong_credentials = S3Config {
+            secret_access_key: "wrong".into(),
+            ..bucket.config()
+        };
+        let site = SiteGraph::new().page("https://example.com/", &[]);
+        let options = ArchiverOptionsBuilder::default_builder()
+            .writer_dir(Some(dir.clone()))
+            .with_upload(true)
+            .capture_backend(CaptureBackend::Native)
+            .config(ArchiverConfig {
+                bundlr_url: node.url(),
+                ..ArchiverConfig::default()
+            })
+            .arweave_key_dir(PathBuf::from(TEST_WALLET))
+            .fetcher(Some(Arc::new(site) as Arc<dyn PageFetcher>))
+            .storage(vec![
+                Arc::new(LocalStorage::new(store.clone())) as Arc<dyn StorageBackend>,
+                Arc::new(S3Storage::new(bucket.config(), "archives", "crawls").unwrap()),
+                // refused by the bucket, the crawl is stored and submitted without it
+                Arc::new(S3Storage::new(wrong_credentials, "archives", "other").unwrap()),
+            ])
+            .build()
+            .unwrap();
+        let request = archive_request("https://example.com/");
+        let contract = Arc::new(MemoryContract::new(vec![request.clone()]));
+
+        run(contract.clone(), &request, &options).await.unwrap();
+        assert_eq!(contract.submissions().len(), 1);
+        let copy = fs::read_dir(&store)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let name = copy.file_name().unwrap().to_string_lossy().to_string();
+        let mut warc = copy.clone();
+        warc.push("archive.warc.gz");
+        assert!(warc.exists());
+        assert!(bucket
+            .object("archives", &format!("crawls/{}/archive.warc.gz", name))
+            .is_some());
+
+        // the local manifest lists where the crawl was stored
+        let mut collections = dir.clone();
+        collections.push("collections");
+        let mut manifest_file = fs::read_dir(collections)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        manifest_file.push("archive");
+        manifest_file.push(format!("{}.archive.json", name));
+        let manifest = ArchiveManifest::load(&manifest_file).unwrap();
+        let backends = manifest
+            .storage
+            .iter()
+            .map(|l| l.backend.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(backends, vec!["bundlr", "local", "s3"]);
+        assert_eq!(manifest.storage[0].verified, Some(true));
+        assert_eq!(manifest.storage[1].verified, None);
+        assert_eq!(
+            manifest.storage[0].files["archive.warc.gz"],
+            contract.submissions()[0].arweave_tx
+        );
+        assert!(UploadCost::load(&UploadCost::path(&manifest_file))
+            .unwrap()
+            .is_some());
+
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,26 +1,50 @@
 use anyhow::{Context, Result};
 use headless_chrome::{
-    browser::default_executable, protocol::cdp::Page::CaptureScreenshotFormatOption, Browser,
-    LaunchOptions, Tab,
+    browser::default_executable,
+    protocol::cdp::{types::Event, Network, Page::CaptureScreenshotFormatOption, Target},
+    Browser, LaunchOptions, Tab,
 };
-use rand::Rng;
-use std::fs;
-use std::sync::Arc;
-use std::thread::sleep;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
 
-use crate::utils::{extract_collection_name, get_tmp_screenshot_dir};
+use crate::{
+    capture::{TabRecording, WarcRecorder},
+    readiness::{NetworkActivity, PageReadiness},
+};
 
 pub struct BrowserController {
     browser: Browser,
-    min_wait_secs: u64,
-    max_wait_secs: u64,
+    // tab of the default context, used to dispose the incognito contexts of
+    // closed pages as the browser itself is not exposed to protocol calls
+    control_tab: Arc<Tab>,
     idle_browser_timeout: u64,
 }
 
+// Status of the main document of a page, hosts rate limiting us are slowed down
+#[derive(Debug, Clone)]
+pub struct PageResponse {
+    pub status: u16,
+    pub retry_after: Option<String>,
+}
+
+// What the network events of a tab are collected into while it loads a page
+struct TabNetwork {
+    response: Arc<Mutex<Option<PageResponse>>>,
+    activity: Option<Arc<NetworkActivity>>,
+    recording: Option<Arc<TabRecording>>,
+}
+
+// A tab that finished loading a page, it is left open for the links to be read
+pub struct BrowsedPage {
+    pub tab: Arc<Tab>,
+    pub response: Option<PageResponse>,
+    // png captured before the page was scrolled, when asked for
+    pub screenshot: Option<Vec<u8>>,
+}
+
 impl BrowserController {
-    pub fn new(idle_browser_timeout: u64, min_wait_secs: u64, max_wait_secs: u64) -> Result<Self> {
+    pub fn new(idle_browser_timeout: u64) -> Result<Self> {
         let is_docker = std::env::var("IN_DOCKER").is_ok();
         let options = LaunchOptions::default_builder()
             .path(Some(default_executable().unwrap()))
@@ -31,23 +55,127 @@ impl BrowserController {
             .build()
             .expect("Couldn't find appropriate Chrome binary.");
         let browser = Browser::new(options).context("browser launching error")?;
+        let control_tab = browser
+            .wait_for_initial_tab()
+            .context("could not get the initial tab of the browser")?;
 
         Ok(BrowserController {
             browser,
-            min_wait_secs,
-            max_wait_secs,
+            control_tab,
             idle_browser_timeout,
         })
     }
 
-    pub fn browse(&self, url: &str, screenshot: bool) -> anyhow::Result<Arc<Tab>> {
+    pub fn browse(
+        &self,
+        url: &str,
+        screenshot: bool,
+        readiness: &PageReadiness,
+        recorder: Option<&Arc<WarcRecorder>>,
+    ) -> anyhow::Result<BrowsedPage> {
         // we create a new incognito window to avoid leaking credentials (no context)
         let ctx = self
             .browser
             .new_context()
             .context("could not create incognito context")?;
-        let tab = ctx.new_tab().context("could not create new tab")?;
+        let tab = match ctx.new_tab() {
+            Ok(tab) => tab,
+            Err(e) => {
+                self.dispose_context(ctx.get_id().to_string());
+                return Err(e).context("could not create new tab");
+            }
+        };
+        let network = match Self::listen(&tab, readiness, recorder) {
+            Ok(n) => n,
+            Err(e) => {
+                self.close_tab(&tab);
+                return Err(e);
+            }
+        };
+
+        match self.load(&tab, url, screenshot, readiness, &network) {
+            Ok(screenshot) => {
+                let response = network.response.lock().unwrap().clone();
+                Ok(BrowsedPage {
+                    tab,
+                    response,
+                    screenshot,
+                })
+            }
+            Err(e) => {
+                self.close_tab(&tab);
+                Err(e)
+            }
+        }
+    }
+
+    // enables the network events of the tab once, a single listener feeds the
+    // response tracking, the readiness strategy and the recorder
+    fn listen(
+        tab: &Arc<Tab>,
+        readiness: &PageReadiness,
+        recorder: Option<&Arc<WarcRecorder>>,
+    ) -> anyhow::Result<TabNetwork> {
+        tab.call_method(Network::Enable {
+            max_total_buffer_size: None,
+            max_resource_buffer_size: None,
+            max_post_data_size: None,
+        })
+        .context("could not enable network events")?;
 
+        let network = TabNetwork {
+            response: Arc::new(Mutex::new(None)),
+            activity: readiness.track(),
+            recording: recorder.map(|r| r.recording(tab)),
+        };
+        let response = network.response.clone();
+        let activity = network.activity.clone();
+        let recording = network.recording.clone();
+        tab.add_event_listener(Arc::new(move |event: &Event| {
+            Self::track_response(&response, event);
+            if let Some(a) = &activity {
+                a.on_event(event);
+            }
+            if let Some(r) = &recording {
+                r.on_event(event);
+            }
+        }))
+        .context("could not listen to network events")?;
+
+        Ok(network)
+    }
+
+    // keeps the first document response of the tab, redirects aside
+    fn track_response(response: &Mutex<Option<PageResponse>>, event: &Event) {
+        if let Event::NetworkResponseReceived(ev) = event {
+            if ev.params.Type != Network::ResourceType::Document {
+                return;
+            }
+            let mut response = response.lock().unwrap();
+            if response.is_none() {
+                let retry_after = match &ev.params.response.headers.0 {
+                    Some(serde_json::Value::Object(map)) => map
+                        .iter()
+                        .find(|(k, _)| k.eq_ignore_ascii_case("retry-after"))
+                        .and_then(|(_, v)| v.as_str().map(|v| v.to_string())),
+                    _ => None,
+                };
+                *response = Some(PageResponse {
+                    status: ev.params.response.status as u16,
+                    retry_after,
+                });
+            }
+        }
+    }
+
+    fn load(
+        &self,
+        tab: &Arc<Tab>,
+        url: &str,
+        screenshot: bool,
+        readiness: &PageReadiness,
+        network: &TabNetwork,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
         let nv = match tab.navigate_to(&url) {
             Ok(t) => t,
             Err(e) => {
@@ -60,25 +188,25 @@ impl BrowserController {
             nv.wait_until_navigated()?;
         }
 
-        let rndm = {
-            let mut rng = rand::thread_rng();
-            rng.gen_range(self.min_wait_secs..self.max_wait_secs)
-        };
-        debug!("successfully navigated, sleeping for {} seconds", rndm);
-        sleep(Duration::from_secs(rndm));
+        debug!(
+            "successfully navigated, waiting for {} with strategy {}",
+            url,
+            readiness.strategy()
+        );
+        readiness.wait(tab, network.activity.as_ref())?;
+        if let Some(r) = &network.recording {
+            r.flush();
+        }
 
-        if screenshot {
-            let collection_name = extract_collection_name(&url);
+        let png = if screenshot {
             debug!("taking screenshot of {}", &url);
-
-            let _png = tab
+            let png = tab
                 .capture_screenshot(CaptureScreenshotFormatOption::Png, None, None, false)
                 .context(format!("screenshot for {} could not be captured", &url))?;
-            let filename = get_tmp_screenshot_dir(&collection_name);
-            debug!("saving temporary screenshot to {}", filename);
-
-            fs::write(filename, _png).context(format!("could not save screenshot for {}", &url))?;
-        }
+            Some(png)
+        } else {
+            None
+        };
 
         debug!("scrolling....");
         match tab.evaluate(
@@ -94,10 +222,13 @@ impl BrowserController {
                 )?;
             }
         };
-        debug!("successfully scrolled, sleeping for {} seconds", rndm);
-        sleep(Duration::from_secs(rndm));
+        debug!("successfully scrolled, waiting for lazy loaded resources");
+        readiness.wait(tab, network.activity.as_ref())?;
+        if let Some(r) = &network.recording {
+            r.flush();
+        }
 
-        Ok(tab)
+        Ok(png)
     }
 
     pub fn get_links(&self, tab: &Arc<Tab>) -> Vec<String> {
@@ -132,6 +263,51 @@ impl BrowserController {
         links
     }
 
+    // a browser whose process crashed or whose connection timed out can't
+    // answer a simple version query anymore
+    pub fn is_healthy(&self) -> bool {
+        match self.browser.get_version() {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("browser health check failed {}", e);
+                false
+            }
+        }
+    }
+
+    // closes a tab opened by browse along with its incognito context,
+    // long-lived browsers would otherwise accumulate every page they visited
+    pub fn close_tab(&self, tab: &Arc<Tab>) {
+        let context_id = match tab.get_browser_context_id() {
+            Ok(id) => id,
+            Err(e) => {
+                warn!(
+                    "could not get context of tab {} with error {}",
+                    tab.get_url(),
+                    e
+                );
+                None
+            }
+        };
+        if let Err(e) = tab.close_target() {
+            warn!("could not close tab for {} with error {}", tab.get_url(), e);
+        }
+        if let Some(id) = context_id {
+            self.dispose_context(id);
+        }
+    }
+
+    fn dispose_context(&self, browser_context_id: String) {
+        if let Err(e) = self.control_tab.call_method(Target::DisposeBrowserContext {
+            browser_context_id: browser_context_id.clone(),
+        }) {
+            warn!(
+                "could not dispose browser context {} with error {}",
+                browser_context_id, e
+            );
+        }
+    }
+
     pub fn kill(&self) -> bool {
         let pid = self
             .browser
@@ -0,0 +1,371 @@
+use std::{
+    fmt,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::browser_controller::BrowserController;
+
+// What the pool needs from the browsers it keeps
+pub trait PooledBrowserHandle: Send + Sync {
+    fn is_healthy(&self) -> bool;
+}
+
+impl PooledBrowserHandle for BrowserController {
+    fn is_healthy(&self) -> bool {
+        BrowserController::is_healthy(self)
+    }
+}
+
+type Launcher<B> = Box<dyn Fn(&BrowserPoolOptions) -> anyhow::Result<B> + Send + Sync>;
+
+// A set of long-lived Chrome instances shared between crawls. Browsers are
+// launched lazily up to `size`, each one serving up to `tabs_per_browser`
+// pages at a time, and are recycled once they served `max_pages_per_browser`
+// pages or fail a health check.
+pub struct BrowserPool<B: PooledBrowserHandle = BrowserController> {
+    state: Mutex<PoolState<B>>,
+    available: Condvar,
+    options: BrowserPoolOptions,
+    launch: Launcher<B>,
+}
+
+#[derive(Builder, Debug, Clone)]
+#[builder(setter(into))]
+pub struct BrowserPoolOptions {
+    // maximum number of browsers alive at the same time
+    #[builder(default = "3")]
+    size: usize,
+    // maximum number of pages a single browser browses concurrently
+    #[builder(default = "5")]
+    tabs_per_browser: usize,
+    // number of pages a browser serves before it is recycled
+    #[builder(default = "50")]
+    max_pages_per_browser: usize,
+    // browser timeout in seconds
+    #[builder(default = "45")]
+    browser_timeout: u64,
+}
+
+impl BrowserPoolOptions {
+    pub fn default_builder() -> BrowserPoolOptionsBuilder {
+        BrowserPoolOptionsBuilder::default()
+    }
+}
+
+struct PooledBrowser<B> {
+    id: usize,
+    controller: Arc<B>,
+    active: usize,
+    pages_served: usize,
+    retiring: bool,
+}
+
+struct PoolState<B> {
+    browsers: Vec<PooledBrowser<B>>,
+    launching: usize,
+    next_id: usize,
+}
+
+// Exclusive right to browse one page with a pooled browser, the browser
+// is handed back to the pool when the lease is dropped
+pub struct BrowserLease<B: PooledBrowserHandle = BrowserController> {
+    pool: Arc<BrowserPool<B>>,
+    id: usize,
+    controller: Arc<B>,
+    healthy: bool,
+}
+
+impl BrowserPool {
+    pub fn new(options: BrowserPoolOptions) -> anyhow::Result<Self> {
+        Self::with_launcher(
+            options,
+            Box::new(|options: &BrowserPoolOptions| {
+                BrowserController::new(options.browser_timeout)
+            }),
+        )
+    }
+}
+
+impl<B: PooledBrowserHandle> BrowserPool<B> {
+    // browsers are started with launch, which only the tests replace
+    fn with_launcher(options: BrowserPoolOptions, launch: Launcher<B>) -> anyhow::Result<Self> {
+        if options.size == 0 || options.tabs_per_browser == 0 {
+            return Err(anyhow!(
+                "browser pool size and tabs per browser must be greater than 0"
+            ));
+        }
+
+        Ok(BrowserPool {
+            state: Mutex::new(PoolState {
+                browsers: Vec::with_capacity(options.size),
+                launching: 0,
+                next_id: 0,
+            }),
+            available: Condvar::new(),
+            options,
+            launch,
+        })
+    }
+
+    pub fn options(&self) -> &BrowserPoolOptions {
+        &self.options
+    }
+
+    // Blocks until a healthy browser can take a new page. Must be called
+    // from a blocking context (e.g. within task::spawn_blocking)
+    pub fn checkout(self: &Arc<Self>) -> anyhow::Result<BrowserLease<B>> {
+        // each failed health check retires a browser, so we give up once
+        // every slot of the pool has been replaced at least once
+        for _ in 0..=self.options.size {
+            let mut lease = self.reserve()?;
+            if lease.controller.is_healthy() {
+                return Ok(lease);
+            }
+            warn!("pooled browser {} is unhealthy, recycling it", lease.id);
+            lease.mark_unhealthy();
+        }
+        Err(anyhow!("could not obtain a healthy browser from the pool"))
+    }
+
+    pub fn active_browsers(&self) -> usize {
+        self.state.lock().unwrap().browsers.len()
+    }
+
+    fn reserve(self: &Arc<Self>) -> anyhow::Result<BrowserLease<B>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let can_launch = state.browsers.len() + state.launching < self.options.size;
+
+            let candidate = state
+                .browsers
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| !b.retiring && b.active < self.options.tabs_per_browser)
+                .min_by_key(|(_, b)| b.active)
+                .map(|(i, b)| (i, b.active));
+
+            match candidate {
+                // we prefer spreading pages over new browsers while we still can launch some
+                Some((idx, active)) if active == 0 || !can_launch => {
+                    let max_pages = self.options.max_pages_per_browser;
+                    let b = &mut state.browsers[idx];
+                    b.active += 1;
+                    b.pages_served += 1;
+                    if b.pages_served >= max_pages {
+                        debug!(
+                            "browser {} served {} pages, retiring it",
+                            b.id, b.pages_served
+                        );
+                        b.retiring = true;
+                    }
+                    return Ok(BrowserLease {
+                        pool: self.clone(),
+                        id: b.id,
+                        controller: b.controller.clone(),
+                        healthy: true,
+                    });
+                }
+                _ => {}
+            }
+
+            if can_launch {
+                state.launching += 1;
+                drop(state);
+
+                debug!("launching a new pooled browser");
+                let res = (self.launch)(&self.options);
+
+                state = self.state.lock().unwrap();
+                state.launching -= 1;
+                match res {
+                    Ok(controller) => {
+                        let id = state.next_id;
+                        state.next_id += 1;
+                        state.browsers.push(PooledBrowser {
+                            id,
+                            controller: Arc::new(controller),
+                            active: 0,
+                            pages_served: 0,
+                            retiring: false,
+                        });
+                        debug!("pooled browser {} launched", id);
+                    }
+                    Err(e) => {
+                        self.available.notify_all();
+                        return Err(e).context("could not launch pooled browser");
+                    }
+                }
+                continue;
+            }
+
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    fn release(&self, id: usize, healthy: bool) {
+        let removed = {
+            let mut state = self.state.lock().unwrap();
+            let idx = match state.browsers.iter().position(|b| b.id == id) {
+                Some(idx) => idx,
+                None => return,
+            };
+            let b = &mut state.browsers[idx];
+            b.active -= 1;
+            if !healthy {
+                b.retiring = true;
+            }
+            let removed = if b.retiring && b.active == 0 {
+                Some(state.browsers.remove(idx))
+            } else {
+                None
+            };
+            self.available.notify_all();
+            removed
+        };
+
+        // dropping the last reference kills the chrome process, which we
+        // do outside of the lock
+        if let Some(b) = removed {
+            debug!("recycling browser {} after {} pages", b.id, b.pages_served);
+        }
+    }
+}
+
+impl<B: PooledBrowserHandle> fmt::Debug for BrowserPool<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BrowserPool")
+            .field("options", &self.options)
+            .field("active_browsers", &self.active_browsers())
+            .finish()
+    }
+}
+
+impl<B: PooledBrowserHandle> BrowserLease<B> {
+    pub fn browser(&self) -> Arc<B> {
+        self.controller.clone()
+    }
+
+    // flags the browser so it gets recycled once all its pages are done
+    pub fn mark_unhealthy(&mut self) {
+        self.healthy = false;
+    }
+}
+
+impl<B: PooledBrowserHandle> Drop for BrowserLease<B> {
+    fn drop(&mut self) {
+        self.pool.release(self.id, self.healthy);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    // stands in for chrome, unhealthy once told so
+    struct FakeBrowser {
+        healthy: AtomicBool,
+    }
+
+    impl PooledBrowserHandle for FakeBrowser {
+        fn is_healthy(&self) -> bool {
+            self.healthy.load(Ordering::SeqCst)
+        }
+    }
+
+    // a pool of fake browsers along with the number of browsers it launched
+    fn pool(
+        size: usize,
+        tabs_per_browser: usize,
+        max_pages_per_browser: usize,
+    ) -> (Arc<BrowserPool<FakeBrowser>>, Arc<AtomicUsize>) {
+        let options = BrowserPoolOptions::default_builder()
+            .size(size)
+            .tabs_per_browser(tabs_per_browser)
+            .max_pages_per_browser(max_pages_per_browser)
+            .build()
+            .unwrap();
+        let launched = Arc::new(AtomicUsize::new(0));
+        let counter = launched.clone();
+        let pool = BrowserPool::with_launcher(
+            options,
+            Box::new(move |_: &BrowserPoolOptions| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(FakeBrowser {
+                    healthy: AtomicBool::new(true),
+                })
+            }),
+        )
+        .unwrap();
+        (Arc::new(pool), launched)
+    }
+
+    #[test]
+    fn releases_leases_on_drop() {
+        let (pool, launched) = pool(1, 1, 50);
+        let lease = pool.checkout().unwrap();
+
+        // the only tab of the only browser is taken until the lease is dropped
+        let waiting = thread::spawn({
+            let pool = pool.clone();
+            move || pool.checkout().map(|_| ())
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        drop(lease);
+        waiting.join().unwrap().unwrap();
+        assert_eq!(launched.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.active_browsers(), 1);
+    }
+
+    #[test]
+    fn retires_browsers_after_their_max_pages() {
+        let (pool, launched) = pool(1, 2, 2);
+        let first = pool.checkout().unwrap();
+        let second = pool.checkout().unwrap();
+        assert!(Arc::ptr_eq(&first.browser(), &second.browser()));
+        drop(first);
+        // still serving the second page
+        assert_eq!(pool.active_browsers(), 1);
+        drop(second);
+        assert_eq!(pool.active_browsers(), 0);
+
+        let _third = pool.checkout().unwrap();
+        assert_eq!(launched.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn recycles_unhealthy_browsers() {
+        let (pool, launched) = pool(1, 1, 50);
+        let mut lease = pool.checkout().unwrap();
+        lease.mark_unhealthy();
+        drop(lease);
+        assert_eq!(pool.active_browsers(), 0);
+        pool.checkout().unwrap();
+        assert_eq!(launched.load(Ordering::SeqCst), 2);
+
+        // browsers failing their health check are replaced before being leased
+        let lease = pool.checkout().unwrap();
+        lease.browser().healthy.store(false, Ordering::SeqCst);
+        drop(lease);
+        let lease = pool.checkout().unwrap();
+        assert!(lease.browser().is_healthy());
+        assert_eq!(launched.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn rejects_empty_pool() {
+        let options = BrowserPoolOptions::default_builder()
+            .size(0usize)
+            .build()
+            .unwrap();
+        assert!(BrowserPool::new(options).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    fmt, fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+// The budget that ended a crawl early, its archive then only holds part of the site
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BudgetLimit {
+    MaxPages,
+    MaxBytes,
+    MaxDuration,
+}
+
+impl fmt::Display for BudgetLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BudgetLimit::MaxPages => "max-pages",
+            BudgetLimit::MaxBytes => "max-bytes",
+            BudgetLimit::MaxDuration => "max-duration",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// Hard limits of a crawl on top of its depth, none are set by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrawlBudget {
+    // pages visited
+    pub max_pages: Option<usize>,
+    // bytes of the warc files written so far
+    pub max_bytes: Option<u64>,
+    // wall-clock time since the crawl started
+    pub max_duration: Option<Duration>,
+}
+
+impl CrawlBudget {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_pages.is_none() && self.max_bytes.is_none() && self.max_duration.is_none()
+    }
+
+    // the warc files are only measured when a byte budget is set
+    pub fn exceeded(
+        &self,
+        pages: usize,
+        started_at: Instant,
+        archive_dir: &PathBuf,
+    ) -> Option<BudgetLimit> {
+        if let Some(max) = self.max_pages {
+            if pages >= max {
+                return Some(BudgetLimit::MaxPages);
+            }
+        }
+        if let Some(max) = self.max_duration {
+            if started_at.elapsed() >= max {
+                return Some(BudgetLimit::MaxDuration);
+            }
+        }
+        if let Some(max) = self.max_bytes {
+            if unprocessed_warc_bytes(archive_dir) >= max {
+                return Some(BudgetLimit::MaxBytes);
+            }
+        }
+        None
+    }
+}
+
+// size of the warc files of the ongoing crawl, the ones of previous crawls were renamed
+pub fn unprocessed_warc_bytes(archive_dir: &PathBuf) -> u64 {
+    let dir = match fs::read_dir(archive_dir) {
+        Ok(d) => d,
+        Err(e) => {
+            warn!("could not read_dir {:?} {}", archive_dir, e);
+            return 0;
+        }
+    };
+    dir.filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.contains("<unprocessed>") && name.contains(".warc")
+        })
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::create_random_tmp_folder;
+
+    use super::*;
+
+    #[test]
+    fn reports_the_exceeded_budget() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut warc = dir.clone();
+        warc.push("<unprocessed>-archiver-20230125160157993364-abcdefgh.warc.gz");
+        fs::write(&warc, vec![0; 100]).unwrap();
+        let mut renamed = dir.clone();
+        renamed.push("archiver_20230125160157_example.com_1.warc.gz");
+        fs::write(&renamed, vec![0; 1000]).unwrap();
+
+        assert_eq!(unprocessed_warc_bytes(&dir), 100);
+
+        let started_at = Instant::now();
+        assert_eq!(
+            CrawlBudget::default().exceeded(1000, started_at, &dir),
+            None
+        );
+
+        let budget = CrawlBudget {
+            max_pages: Some(10),
+            max_bytes: Some(200),
+            max_duration: None,
+        };
+        assert_eq!(budget.exceeded(9, started_at, &dir), None);
+        assert_eq!(
+            budget.exceeded(10, started_at, &dir),
+            Some(BudgetLimit::MaxPages)
+        );
+
+        fs::write(&warc, vec![0; 200]).unwrap();
+        assert_eq!(
+            budget.exceeded(0, started_at, &dir),
+            Some(BudgetLimit::MaxBytes)
+        );
+
+        let budget = CrawlBudget {
+            max_duration: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(
+            budget.exceeded(0, started_at, &dir),
+            Some(BudgetLimit::MaxDuration)
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+};
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose, Engine as _};
+use chrono::Utc;
+use headless_chrome::{
+    protocol::cdp::{types::Event, Network},
+    Tab,
+};
+use reqwest::Url;
+
+use crate::{
+    utils::{get_random_string, APP_NAME, APP_VERSION},
+    warc::{digest, WarcFile, WarcRecord},
+};
+
+// headers that describe the transfer rather than the payload, the body we get
+// from the browser is already decoded so these would be wrong in the archive
+const TRANSFER_HEADERS: [&str; 3] = ["content-encoding", "transfer-encoding", "content-length"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureBackend {
+    // pywb/wayback running in record mode as a proxy
+    Pywb,
+    // in-process capture of the browser traffic through CDP
+    Native,
+}
+
+impl FromStr for CaptureBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pywb" => Ok(CaptureBackend::Pywb),
+            "native" => Ok(CaptureBackend::Native),
+            _ => Err(anyhow!(
+                "invalid capture backend {}, expected one of pywb, native",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CaptureBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            CaptureBackend::Pywb => "pywb",
+            CaptureBackend::Native => "native",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// A request/response pair as seen on the wire
+#[derive(Debug, Clone)]
+pub struct HttpExchange {
+    pub url: String,
+    pub method: String,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: Vec<u8>,
+    pub status: u16,
+    pub status_text: String,
+    pub response_headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub remote_ip: Option<String>,
+}
+
+// Writes the traffic of the tabs it is attached to into a single WARC file.
+// The file follows the same naming as the pywb recorder so both backends
+// go through the same renaming and packaging steps.
+pub struct WarcRecorder {
+    archive_name: String,
+    file: Mutex<Option<WarcFile>>,
+    // url and payload digest of the responses already written, same as pywb's skip policy
+    seen: Mutex<HashSet<(String, String)>>,
+}
+
+struct PendingExchange {
+    request: Network::Request,
+    response: Option<Network::Response>,
+}
+
+// The exchanges of a tab, fed with its network events. The bodies of the
+// finished ones are fetched outside of the event listener which must not
+// block on the browser
+pub struct TabRecording {
+    recorder: Arc<WarcRecorder>,
+    tab: Weak<Tab>,
+    pending: Mutex<HashMap<String, PendingExchange>>,
+    finished: Mutex<Vec<(String, Network::Request, Network::Response)>>,
+}
+
+impl WarcRecorder {
+    pub fn new(archive_dir: &Path, archive_name: &str) -> anyhow::Result<Self> {
+        let filename = format!(
+            "<unprocessed>-archiver-{}-{}.warc.gz",
+            Utc::now().format("%Y%m%d%H%M%S%6f"),
+            get_random_string(8)
+        );
+        let path = archive_dir.join(&filename);
+
+        let mut file = WarcFile::create(&path)?;
+        let software = format!("{}/{}", APP_NAME.as_str(), APP_VERSION.as_str());
+        file.write(&WarcRecord::warcinfo(
+            &filename,
+            &[
+                ("software", &software),
+                ("format", "WARC File Format 1.1"),
+                (
+                    "conformsTo",
+                    "http://iipc.github.io/warc-specifications/specifications/warc-format/warc-1.1/",
+                ),
+            ],
+        ))?;
+        debug!("native warc recorder writing to {:?}", path);
+
+        Ok(WarcRecorder {
+            archive_name: archive_name.into(),
+            file: Mutex::new(Some(file)),
+            seen: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn archive_name(&self) -> String {
+        self.archive_name.clone()
+    }
+
+    // records every exchange of the tab once fed with its network events,
+    // which must start before navigating. The recording has to be flushed
+    // before the tab is closed
+    pub fn recording(self: &Arc<Self>, tab: &Arc<Tab>) -> Arc<TabRecording> {
+        Arc::new(TabRecording {
+            recorder: self.clone(),
+            // the tab owns its listeners, a strong reference would keep it alive forever
+            tab: Arc::downgrade(tab),
+            pending: Mutex::new(HashMap::new()),
+            finished: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn record(&self, exchange: &HttpExchange) -> anyhow::Result<()> {
+        if !exchange.url.starts_with("http://") && !exchange.url.starts_with("https://") {
+            return Ok(());
+        }
+
+        let key = (exchange.url.clone(), digest(&exchange.body));
+        if !self.seen.lock().unwrap().insert(key) {
+            debug!("skipping duplicate response for {}", exchange.url);
+            return Ok(());
+        }
+
+        let mut response = WarcRecord::response(
+            &exchange.url,
+            &Self::response_head(exchange),
+            &exchange.body,
+        );
+        if let Some(ip) = &exchange.remote_ip {
+            response.add_header("WARC-IP-Address", ip);
+        }
+        let mut request = WarcRecord::request(
+            &exchange.url,
+            &Self::request_head(exchange)?,
+            &exchange.request_body,
+        );
+        request.add_header("WARC-Concurrent-To", &response.id());
+
+        self.write(&[response, request])
+    }
+
+    // outlinks and title of a page, written once the page has been browsed
+    pub fn record_page_metadata(&self, url: &str, title: Option<&str>, outlinks: &[String]) {
+        let mut fields: Vec<(&str, &str)> = vec![];
+        if let Some(t) = title {
+            fields.push(("title", t));
+        }
+        for link in outlinks {
+            fields.push(("outlink", link));
+        }
+        if let Err(e) = self.write(&[WarcRecord::metadata(url, &fields)]) {
+            warn!("could not write metadata for {} {}", url, e);
+        }
+    }
+
+    // records a response fetched outside of the browser
+    pub fn record_blocking_response(&self, res: reqwest::blocking::Response) -> anyhow::Result<()> {
+        let url = res.url().to_string();
+        let status = res.status();
+        let remote_ip = res.remote_addr().map(|a| a.ip().to_string());
+        let response_headers = res
+            .headers()
+            .iter()
+            .map(|(k, v)| (k.to_string(), String::from_utf8_lossy(v.as_bytes()).into()))
+            .collect();
+        let body = res
+            .bytes()
+            .context(format!("could not read body of {}", url))?
+            .to_vec();
+
+        self.record(&HttpExchange {
+            url,
+            method: "GET".into(),
+            request_headers: vec![("Accept".into(), "*/*".into())],
+            request_body: Vec::new(),
+            status: status.as_u16(),
+            status_text: status.canonical_reason().unwrap_or("").into(),
+            response_headers,
+            body,
+            remote_ip,
+        })
+    }
+
+    // flushes and closes the warc file, nothing is recorded afterwards
+    pub fn finish(&self) -> anyhow::Result<Option<PathBuf>> {
+        match self.file.lock().unwrap().take() {
+            Some(mut file) => {
+                file.flush()?;
+                Ok(Some(file.path()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn write(&self, records: &[WarcRecord]) -> anyhow::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let file = match file.as_mut() {
+            Some(f) => f,
+            None => return Err(anyhow!("warc recorder already finished")),
+        };
+        for record in records {
+            file.write(record)?;
+        }
+        Ok(())
+    }
+
+    fn record_cdp(&self, request: &Network::Request, response: &Network::Response, body: Vec<u8>) {
+        let exchange = HttpExchange {
+            url: response.url.clone(),
+            method: request.method.clone(),
+            request_headers: Self::cdp_headers(&request.headers),
+            request_body: request
+                .post_data
+                .clone()
+                .map(|d| d.into_bytes())
+                .unwrap_or_default(),
+            status: response.status as u16,
+            status_text: response.status_text.clone(),
+            response_headers: Self::cdp_headers(&response.headers),
+            body,
+            remote_ip: response.remote_ip_address.clone(),
+        };
+        if let Err(e) = self.record(&exchange) {
+            warn!("could not record {} {}", exchange.url, e);
+        }
+    }
+
+    fn record_cdp_body(
+        &self,
+        tab: &Arc<Tab>,
+        request_id: String,
+        request: &Network::Request,
+        response: &Network::Response,
+    ) {
+        let body = match tab.call_method(Network::GetResponseBody { request_id }) {
+            Ok(b) if b.base_64_encoded => {
+                general_purpose::STANDARD.decode(b.body).unwrap_or_default()
+            }
+            Ok(b) => b.body.into_bytes(),
+            Err(e) => {
+                debug!("no body for {} {}", request.url, e);
+                Vec::new()
+            }
+        };
+        self.record_cdp(request, response, body);
+    }
+
+    // CDP merges repeated headers into a single value separated by new lines
+    fn cdp_headers(headers: &Network::Headers) -> Vec<(String, String)> {
+        match &headers.0 {
+            Some(serde_json::Value::Object(map)) => map
+                .iter()
+                .flat_map(|(k, v)| {
+                    v.as_str()
+                        .unwrap_or_default()
+                        .split('\n')
+                        .map(|v| (k.clone(), v.to_string()))
+                        .collect::<Vec<(String, String)>>()
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn request_head(exchange: &HttpExchange) -> anyhow::Result<String> {
+        let url = Url::parse(&exchange.url)?;
+        let mut path = url.path().to_string();
+        if let Some(q) = url.query() {
+            path.push('?');
+            path.push_str(q);
+        }
+
+        let mut head = format!("{} {} HTTP/1.1", exchange.method, path);
+        if !exchange
+            .request_headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case("host"))
+        {
+            if let Some(host) = url.host_str() {
+                head.push_str(&format!("\r\nHost: {}", host));
+            }
+        }
+        for (k, v) in &exchange.request_headers {
+            // HTTP/2 pseudo headers have no HTTP/1.1 equivalent
+            if k.starts_with(':') {
+                continue;
+            }
+            head.push_str(&format!("\r\n{}: {}", k, v));
+        }
+        Ok(head)
+    }
+
+    fn response_head(exchange: &HttpExchange) -> String {
+        let mut head = format!("HTTP/1.1 {} {}", exchange.status, exchange.status_text);
+        for (k, v) in &exchange.response_headers {
+            if TRANSFER_HEADERS.contains(&k.to_lowercase().as_str()) {
+                continue;
+            }
+            head.push_str(&format!("\r\n{}: {}", k, v));
+        }
+        head.push_str(&format!("\r\nContent-Length: {}", exchange.body.len()));
+        head
+    }
+}
+
+impl TabRecording {
+    pub fn on_event(&self, event: &Event) {
+        match event {
+            Event::NetworkRequestWillBeSent(ev) => {
+                let params = &ev.params;
+                let mut pending = self.pending.lock().unwrap();
+                // redirects reuse the request id, the previous hop is complete
+                if let Some(redirect) = &params.redirect_response {
+                    if let Some(previous) = pending.remove(&params.request_id) {
+                        self.recorder
+                            .record_cdp(&previous.request, redirect, Vec::new());
+                    }
+                }
+                pending.insert(
+                    params.request_id.clone(),
+                    PendingExchange {
+                        request: params.request.clone(),
+                        response: None,
+                    },
+                );
+            }
+            Event::NetworkResponseReceived(ev) => {
+                if let Some(p) = self.pending.lock().unwrap().get_mut(&ev.params.request_id) {
+                    p.response = Some(ev.params.response.clone());
+                }
+            }
+            Event::NetworkLoadingFinished(ev) => {
+                let request_id = ev.params.request_id.clone();
+                let exchange = match self.pending.lock().unwrap().remove(&request_id) {
+                    Some(e) => e,
+                    None => return,
+                };
+                if let Some(response) = exchange.response {
+                    self.finished
+                        .lock()
+                        .unwrap()
+                        .push((request_id, exchange.request, response));
+                }
+            }
+            Event::NetworkLoadingFailed(ev) => {
+                self.pending.lock().unwrap().remove(&ev.params.request_id);
+            }
+            _ => {}
+        }
+    }
+
+    // fetches the bodies of the exchanges finished so far and writes them
+    pub fn flush(&self) {
+        let finished = std::mem::take(&mut *self.finished.lock().unwrap());
+        let tab = match self.tab.upgrade() {
+            Some(t) => t,
+            None => return,
+        };
+        for (request_id, request, response) in finished {
+            self.recorder
+                .record_cdp_body(&tab, request_id, &request, &response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::Read};
+
+    use flate2::read::MultiGzDecoder;
+
+    use crate::utils::create_random_tmp_folder;
+
+    use super::*;
+
+    fn exchange(url: &str, body: &str) -> HttpExchange {
+        HttpExchange {
+            url: url.into(),
+            method: "GET".into(),
+            request_headers: vec![("Accept".into(), "*/*".into())],
+            request_body: vec![],
+            status: 200,
+            status_text: "OK".into(),
+            response_headers: vec![
+                ("Content-Type".into(), "text/html".into()),
+                ("Content-Encoding".into(), "br".into()),
+            ],
+            body: body.as_bytes().to_vec(),
+            remote_ip: Some("127.0.0.1".into()),
+        }
+    }
+
+    #[test]
+    fn records_exchanges_natively() {
+        let dir = create_random_tmp_folder().unwrap();
+        let recorder = WarcRecorder::new(&dir, "test").unwrap();
+
+        recorder
+            .record(&exchange("https://example.com/a?b=c", "<html></html>"))
+            .unwrap();
+        // same url and payload is skipped
+        recorder
+            .record(&exchange("https://example.com/a?b=c", "<html></html>"))
+            .unwrap();
+        let path = recorder.finish().unwrap().unwrap();
+        assert!(recorder
+            .record(&exchange("https://example.com", ""))
+            .is_err());
+
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("<unprocessed>-archiver-"));
+
+        let mut content = String::new();
+        MultiGzDecoder::new(fs::File::open(&path).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content.matches("WARC-Type: warcinfo").count(), 1);
+        assert_eq!(content.matches("WARC-Type: response").count(), 1);
+        assert_eq!(content.matches("WARC-Type: request").count(), 1);
+        assert!(content.contains("GET /a?b=c HTTP/1.1\r\nHost: example.com\r\nAccept: */*"));
+        assert!(
+            content.contains("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 13")
+        );
+        assert!(!content.contains("Content-Encoding: br"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parses_backends() {
+        assert_eq!(
+            CaptureBackend::from_str("native").unwrap(),
+            CaptureBackend::Native
+        );
+        assert_eq!(CaptureBackend::Pywb.to_string(), "pywb");
+        assert!(CaptureBackend::from_str("wayback").is_err());
+    }
+}
@@ -0,0 +1,570 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read},
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use flate2::bufread::GzDecoder;
+use reqwest::Url;
+use serde_json::{json, Value};
+
+use crate::{utils::FORMAT_STRING, warc::digest};
+
+// record types that replay tools look up by url
+const INDEXED_RECORD_TYPES: [&str; 3] = ["response", "revisit", "resource"];
+
+// A single line of a CDXJ index, pointing to a record within a WARC file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdxjRecord {
+    pub surt: String,
+    // 14 digits timestamp, e.g. 20230125160157
+    pub timestamp: String,
+    pub url: String,
+    pub mime: String,
+    pub status: Option<String>,
+    pub digest: String,
+    // offset and length of the compressed record within the WARC file
+    pub offset: u64,
+    pub length: u64,
+    pub filename: String,
+}
+
+impl CdxjRecord {
+    pub fn to_line(&self) -> String {
+        let mut fields = json!({
+            "url": self.url,
+            "mime": self.mime,
+            "digest": self.digest,
+            "length": self.length.to_string(),
+            "offset": self.offset.to_string(),
+            "filename": self.filename,
+        });
+        if let Some(status) = &self.status {
+            fields["status"] = json!(status);
+        }
+        format!("{} {} {}", self.surt, self.timestamp, fields)
+    }
+
+    pub fn from_line(line: &str) -> anyhow::Result<Self> {
+        let (surt, rest) = match line.trim().split_once(' ') {
+            Some(s) => s,
+            None => return Err(anyhow!("invalid cdxj line {}", line)),
+        };
+        let (timestamp, fields) = match rest.split_once(' ') {
+            Some(s) => s,
+            None => return Err(anyhow!("invalid cdxj line {}", line)),
+        };
+        let fields: Value =
+            serde_json::from_str(fields).context(format!("invalid cdxj fields {}", fields))?;
+
+        let string = |name: &str| -> anyhow::Result<String> {
+            match &fields[name] {
+                Value::String(s) => Ok(s.clone()),
+                Value::Number(n) => Ok(n.to_string()),
+                _ => Err(anyhow!("cdxj line without {}: {}", name, line)),
+            }
+        };
+
+        Ok(CdxjRecord {
+            surt: surt.into(),
+            timestamp: timestamp.into(),
+            url: string("url")?,
+            mime: string("mime").unwrap_or_else(|_| "unk".into()),
+            status: string("status").ok(),
+            digest: string("digest")?,
+            offset: string("offset")?.parse()?,
+            length: string("length")?.parse()?,
+            filename: string("filename")?,
+        })
+    }
+}
+
+// A sorted CDXJ index kept in memory, used to look up captures and to
+// avoid storing the same payload twice without going through redis
+#[derive(Debug, Clone, Default)]
+pub struct CdxjIndex {
+    records: Vec<CdxjRecord>,
+}
+
+impl CdxjIndex {
+    pub fn new(mut records: Vec<CdxjRecord>) -> Self {
+        sort_records(&mut records);
+        CdxjIndex { records }
+    }
+
+    pub fn load(path: &PathBuf) -> anyhow::Result<Self> {
+        let content =
+            fs::read_to_string(path).context(format!("could not read index {:?}", path))?;
+        let records = content
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(CdxjRecord::from_line)
+            .collect::<anyhow::Result<Vec<CdxjRecord>>>()?;
+        Ok(Self::new(records))
+    }
+
+    // merges the indexes of several archives, e.g. all the indexes of a collection
+    pub fn load_all(paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut records = vec![];
+        for path in paths {
+            records.append(&mut Self::load(path)?.records);
+        }
+        Ok(Self::new(records))
+    }
+
+    pub fn records(&self) -> &[CdxjRecord] {
+        &self.records
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    // every capture of a url, oldest first
+    pub fn lookup(&self, url: &str) -> anyhow::Result<&[CdxjRecord]> {
+        let key = surt(url)?;
+        let start = self.records.partition_point(|r| r.surt < key);
+        let end = start + self.records[start..].partition_point(|r| r.surt == key);
+        Ok(&self.records[start..end])
+    }
+
+    // the capture closest to a 14 digits timestamp
+    pub fn closest(&self, url: &str, timestamp: &str) -> anyhow::Result<Option<&CdxjRecord>> {
+        let target = timestamp.parse::<i64>().unwrap_or(0);
+        Ok(self.lookup(url)?.iter().min_by_key(|r| {
+            let ts = r.timestamp.parse::<i64>().unwrap_or(0);
+            (ts - target).abs()
+        }))
+    }
+
+    // whether the same payload was already captured for this url
+    pub fn is_duplicate(&self, url: &str, digest: &str) -> bool {
+        match self.lookup(url) {
+            Ok(records) => records.iter().any(|r| r.digest == digest),
+            Err(_) => false,
+        }
+    }
+
+    pub fn write(&self, path: &PathBuf) -> anyhow::Result<()> {
+        fs::write(path, to_cdxj(&self.records))
+            .context(format!("could not write index {:?}", path))?;
+        Ok(())
+    }
+}
+
+// Sort-friendly URI Reordering Transform, the key pywb and other replay
+// tools use to look up urls: https://www.example.com/A?b=1&a=2 -> com,example)/a?a=2&b=1
+pub fn surt(url: &str) -> anyhow::Result<String> {
+    let u = Url::parse(url).context(format!("invalid url {}", url))?;
+    let host = match u.host_str() {
+        Some(h) => h.to_lowercase(),
+        None => return Err(anyhow!("url {} has no host", url)),
+    };
+
+    let mut key = if host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+        host
+    } else {
+        let host = host.strip_prefix("www.").unwrap_or(&host);
+        host.split('.').rev().collect::<Vec<&str>>().join(",")
+    };
+    // default ports are already omitted by the url parser
+    if let Some(port) = u.port() {
+        key.push_str(&format!(":{}", port));
+    }
+    key.push(')');
+    key.push_str(&u.path().to_lowercase());
+    if let Some(query) = u.query() {
+        let mut args = query.split('&').collect::<Vec<&str>>();
+        args.sort();
+        key.push('?');
+        key.push_str(&args.join("&").to_lowercase());
+    }
+    Ok(key)
+}
+
+// Indexes every response, revisit and resource record of a .warc.gz file,
+// sorted by surt and timestamp
+pub fn index_warc(path: &PathBuf) -> anyhow::Result<Vec<CdxjRecord>> {
+    let filename = match path.file_name() {
+        Some(n) => n.to_string_lossy().to_string(),
+        None => return Err(anyhow!("invalid warc path {:?}", path)),
+    };
+    let file = File::open(path).context(format!("could not open warc file {:?}", path))?;
+    let mut reader = CountingReader::new(BufReader::new(file));
+
+    let mut records = vec![];
+    loop {
+        if reader.fill_buf()?.is_empty() {
+            break;
+        }
+        // every record is its own gzip member, which gives us its offset and length
+        let offset = reader.position();
+        let mut member = vec![];
+        GzDecoder::new(&mut reader)
+            .read_to_end(&mut member)
+            .context(format!(
+                "could not decompress record at {} in {:?}",
+                offset, path
+            ))?;
+        let length = reader.position() - offset;
+
+        for record in parse_records(&member)? {
+            if let Some(r) = record.to_cdxj(&filename, offset, length) {
+                records.push(r);
+            }
+        }
+    }
+
+    sort_records(&mut records);
+    Ok(records)
+}
+
+// Indexes several WARC files into a single sorted index
+pub fn index_warcs(paths: &[PathBuf]) -> anyhow::Result<Vec<CdxjRecord>> {
+    let mut records = vec![];
+    for path in paths {
+        records.append(&mut index_warc(path)?);
+    }
+    sort_records(&mut records);
+    Ok(records)
+}
+
+// Writes the index of a .warc.gz file next to it, archive.warc.gz -> archive.cdxj
+pub fn write_warc_index(path: &PathBuf) -> anyhow::Result<PathBuf> {
+    let index = CdxjIndex::new(index_warc(path)?);
+    let dest = index_path(path);
+    index.write(&dest)?;
+    debug!("{} records indexed to {:?}", index.len(), dest);
+    Ok(dest)
+}
+
+pub fn index_path(warc: &Path) -> PathBuf {
+    let name = warc
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = name
+        .trim_end_matches(".gz")
+        .trim_end_matches(".warc")
+        .to_string();
+    warc.with_file_name(format!("{}.cdxj", stem))
+}
+
+pub fn to_cdxj(records: &[CdxjRecord]) -> String {
+    records
+        .iter()
+        .map(|r| format!("{}\n", r.to_line()))
+        .collect::<String>()
+}
+
+fn sort_records(records: &mut [CdxjRecord]) {
+    records.sort_by(|a, b| {
+        (&a.surt, &a.timestamp, &a.filename, a.offset).cmp(&(
+            &b.surt,
+            &b.timestamp,
+            &b.filename,
+            b.offset,
+        ))
+    });
+}
+
+struct ParsedRecord {
+    headers: Vec<(String, String)>,
+    block: Vec<u8>,
+}
+
+impl ParsedRecord {
+    fn header(&self, name: &str) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.clone())
+    }
+
+    fn to_cdxj(&self, filename: &str, offset: u64, length: u64) -> Option<CdxjRecord> {
+        let record_type = self.header("WARC-Type")?;
+        if !INDEXED_RECORD_TYPES.contains(&record_type.as_str()) {
+            return None;
+        }
+        let url = self.header("WARC-Target-URI")?;
+        let surt = match surt(&url) {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("not indexing {}: {}", url, e);
+                return None;
+            }
+        };
+        let timestamp = DateTime::parse_from_rfc3339(&self.header("WARC-Date")?)
+            .ok()?
+            .with_timezone(&Utc)
+            .format(FORMAT_STRING)
+            .to_string();
+
+        let (status, http_mime, payload) = match record_type.as_str() {
+            "resource" => (None, None, self.block.as_slice()),
+            _ => parse_http_response(&self.block),
+        };
+        let mime = match record_type.as_str() {
+            "revisit" => "warc/revisit".to_string(),
+            "resource" => self.header("Content-Type").unwrap_or_else(|| "unk".into()),
+            _ => http_mime.unwrap_or_else(|| "unk".into()),
+        };
+        let digest = self
+            .header("WARC-Payload-Digest")
+            .unwrap_or_else(|| digest(payload));
+
+        Some(CdxjRecord {
+            surt,
+            timestamp,
+            url,
+            mime,
+            status,
+            digest,
+            offset,
+            length,
+            filename: filename.into(),
+        })
+    }
+}
+
+fn parse_records(data: &[u8]) -> anyhow::Result<Vec<ParsedRecord>> {
+    let mut records = vec![];
+    let mut rest = data;
+    while !rest.is_empty() {
+        // records are separated by two CRLF, we skip them along with any padding
+        let start = rest
+            .iter()
+            .position(|b| *b != b'\r' && *b != b'\n')
+            .unwrap_or(rest.len());
+        rest = &rest[start..];
+        if rest.is_empty() {
+            break;
+        }
+
+        let header_end = match find(rest, b"\r\n\r\n") {
+            Some(i) => i,
+            None => return Err(anyhow!("truncated warc record headers")),
+        };
+        let head = String::from_utf8_lossy(&rest[..header_end]);
+        let mut lines = head.split("\r\n");
+        match lines.next() {
+            Some(v) if v.starts_with("WARC/") => {}
+            v => return Err(anyhow!("invalid warc record version {:?}", v)),
+        }
+        let headers = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect::<Vec<(String, String)>>();
+
+        let content_length: usize = match headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Length"))
+        {
+            Some((_, v)) => v.parse()?,
+            None => return Err(anyhow!("warc record without Content-Length")),
+        };
+        let block_start = header_end + 4;
+        let block_end = (block_start + content_length).min(rest.len());
+
+        records.push(ParsedRecord {
+            headers,
+            block: rest[block_start..block_end].to_vec(),
+        });
+        rest = &rest[block_end..];
+    }
+    Ok(records)
+}
+
+// status, mime type and payload of an http response block
+fn parse_http_response(block: &[u8]) -> (Option<String>, Option<String>, &[u8]) {
+    let head_end = match find(block, b"\r\n\r\n") {
+        Some(i) => i,
+        None => return (None, None, &block[block.len()..]),
+    };
+    let head = String::from_utf8_lossy(&block[..head_end]);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .map(|s| s.to_string());
+    let mime = lines
+        .filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("Content-Type"))
+        .map(|(_, v)| v.split(';').next().unwrap_or("").trim().to_lowercase());
+    (status, mime, &block[head_end + 4..])
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// Keeps track of how many bytes were consumed from the underlying reader
+struct CountingReader<R> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: BufRead> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        CountingReader { inner, position: 0 }
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt as u64;
+        self.inner.consume(amt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        utils::create_random_tmp_folder,
+        warc::{WarcFile, WarcRecord},
+    };
+
+    use super::*;
+
+    #[test]
+    fn computes_surts() {
+        let tests = vec![
+            (
+                "https://www.Example.com/A?b=2&a=1",
+                "com,example)/a?a=1&b=2",
+            ),
+            ("http://example.com", "com,example)/"),
+            (
+                "https://sub.example.co.uk:8443/path",
+                "uk,co,example,sub:8443)/path",
+            ),
+            ("http://127.0.0.1:8080/", "127.0.0.1:8080)/"),
+        ];
+        for (url, expected) in tests {
+            assert_eq!(surt(url).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn indexes_warc_records() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("test.warc.gz");
+
+        let mut file = WarcFile::create(&path).unwrap();
+        file.write(&WarcRecord::warcinfo(
+            "test.warc.gz",
+            &[("software", "test")],
+        ))
+        .unwrap();
+        let (offset, length) = file
+            .write(&WarcRecord::response(
+                "https://example.com/b",
+                "HTTP/1.1 404 Not Found\r\nContent-Type: text/html; charset=utf-8",
+                b"missing",
+            ))
+            .unwrap();
+        file.write(&WarcRecord::request(
+            "https://example.com/b",
+            "GET /b HTTP/1.1",
+            b"",
+        ))
+        .unwrap();
+        file.write(&WarcRecord::response(
+            "https://example.com/a",
+            "HTTP/1.1 200 OK\r\nContent-Type: image/png",
+            b"png",
+        ))
+        .unwrap();
+        file.flush().unwrap();
+
+        let records = index_warc(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].url, "https://example.com/a");
+        assert_eq!(records[0].mime, "image/png");
+
+        let missing = &records[1];
+        assert_eq!(missing.surt, "com,example)/b");
+        assert_eq!(missing.status, Some("404".into()));
+        assert_eq!(missing.mime, "text/html");
+        assert_eq!(missing.digest, digest(b"missing"));
+        assert_eq!((missing.offset, missing.length), (offset, length));
+        assert_eq!(missing.filename, "test.warc.gz");
+        assert!(missing
+            .to_line()
+            .starts_with(&format!("com,example)/b {} {{", missing.timestamp)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writes_and_looks_up_indexes() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("archiver_20230125160157_example.com_1.warc.gz");
+
+        let mut file = WarcFile::create(&path).unwrap();
+        for (url, body) in [
+            ("https://example.com/a", "a"),
+            ("https://example.com/b", "b"),
+            ("https://www.example.com/a", "a2"),
+        ] {
+            file.write(&WarcRecord::response(
+                url,
+                "HTTP/1.1 200 OK",
+                body.as_bytes(),
+            ))
+            .unwrap();
+        }
+        file.flush().unwrap();
+
+        let index_file = write_warc_index(&path).unwrap();
+        assert_eq!(
+            index_file.file_name().unwrap(),
+            "archiver_20230125160157_example.com_1.cdxj"
+        );
+
+        let index = CdxjIndex::load(&index_file).unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.records(), index_warc(&path).unwrap().as_slice());
+
+        let captures = index.lookup("https://example.com/a").unwrap();
+        assert_eq!(captures.len(), 2);
+        assert!(index.is_duplicate("https://example.com/a", &digest(b"a2")));
+        assert!(!index.is_duplicate("https://example.com/b", &digest(b"a")));
+        assert!(index.lookup("https://example.com/c").unwrap().is_empty());
+        assert!(index
+            .closest("https://example.com/b", "20230125160157")
+            .unwrap()
+            .is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
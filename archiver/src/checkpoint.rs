@@ -0,0 +1,140 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use atw::state::CrawlType;
+use serde::{Deserialize, Serialize};
+use urlencoding::encode;
+
+use crate::utils::{get_unix_timestamp, load_json, remove_json, write_json};
+
+pub const CHECKPOINT_VERSION: u8 = 1;
+
+// A url queued or being browsed when the checkpoint was taken
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FrontierEntry {
+    pub url: String,
+    pub depth: i32,
+}
+
+// State of an ongoing crawl, written regularly and when the crawl is interrupted so a
+// restarted runner continues in the same collection instead of starting over
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CrawlCheckpoint {
+    pub version: u8,
+    // archive request the crawl belongs to, if any
+    pub request_id: Option<String>,
+    // collection the warc files are written to
+    pub archive_name: String,
+    pub seeds: Vec<String>,
+    // unix timestamp in seconds of the first start of the crawl
+    pub started_at: i64,
+    // unix timestamp in seconds of the checkpoint
+    pub updated_at: i64,
+    pub depth: i32,
+    pub crawl_type: CrawlType,
+    pub url_retries: i32,
+    pub main_title: String,
+    pub frontier: Vec<FrontierEntry>,
+    pub visited: BTreeSet<String>,
+    // number of retries per failed url
+    pub failed: BTreeMap<String, i32>,
+    pub skipped: BTreeSet<String>,
+    pub out_of_scope: BTreeSet<String>,
+}
+
+impl CrawlCheckpoint {
+    pub fn new(
+        request_id: Option<String>,
+        archive_name: &str,
+        seeds: &[String],
+        started_at: i64,
+        depth: i32,
+        crawl_type: CrawlType,
+        url_retries: i32,
+    ) -> Self {
+        CrawlCheckpoint {
+            version: CHECKPOINT_VERSION,
+            request_id,
+            archive_name: archive_name.into(),
+            seeds: seeds.to_vec(),
+            started_at,
+            updated_at: started_at,
+            depth,
+            crawl_type,
+            url_retries,
+            main_title: String::new(),
+            frontier: vec![],
+            visited: BTreeSet::new(),
+            failed: BTreeMap::new(),
+            skipped: BTreeSet::new(),
+            out_of_scope: BTreeSet::new(),
+        }
+    }
+
+    // <checkpoint dir>/<request id or collection name>.json
+    pub fn path(dir: &Path, key: &str) -> PathBuf {
+        dir.join(format!("{}.json", encode(key)))
+    }
+
+    // a missing checkpoint means there is nothing to resume
+    pub fn load(path: &PathBuf) -> anyhow::Result<Option<Self>> {
+        load_json(path, "checkpoint", Some(CHECKPOINT_VERSION))
+    }
+
+    pub fn write(&mut self, path: &PathBuf) -> anyhow::Result<()> {
+        self.updated_at = get_unix_timestamp().as_secs() as i64;
+        write_json(path, "checkpoint", self)
+    }
+
+    pub fn remove(path: &PathBuf) -> anyhow::Result<()> {
+        remove_json(path, "checkpoint")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::utils::create_random_tmp_folder;
+
+    use super::*;
+
+    #[test]
+    fn writes_and_loads_checkpoints() {
+        let dir = create_random_tmp_folder().unwrap();
+        let path = CrawlCheckpoint::path(&dir, "request/1");
+        assert_eq!(path.file_name().unwrap(), "request%2F1.json");
+        assert!(CrawlCheckpoint::load(&path).unwrap().is_none());
+
+        let mut checkpoint = CrawlCheckpoint::new(
+            Some("request/1".into()),
+            "abcdefghijk",
+            &["https://example.com".into()],
+            1674662517,
+            2,
+            CrawlType::DomainOnly,
+            3,
+        );
+        checkpoint.frontier.push(FrontierEntry {
+            url: "https://example.com/b".into(),
+            depth: 1,
+        });
+        checkpoint.visited.insert("https://example.com".into());
+        checkpoint.failed.insert("https://example.com/c".into(), 1);
+        checkpoint.write(&path).unwrap();
+        assert!(checkpoint.updated_at > checkpoint.started_at);
+
+        let loaded = CrawlCheckpoint::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.request_id, checkpoint.request_id);
+        assert_eq!(loaded.frontier, checkpoint.frontier);
+        assert_eq!(loaded.visited, checkpoint.visited);
+        assert_eq!(loaded.failed, checkpoint.failed);
+
+        CrawlCheckpoint::remove(&path).unwrap();
+        assert!(CrawlCheckpoint::load(&path).unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
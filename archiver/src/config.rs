@@ -0,0 +1,277 @@
+use std::{fmt, fs, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::VERSION;
+
+pub const DEFAULT_BUNDLR_URL: &str = "https://node1.bundlr.network";
+pub const DEFAULT_GATEWAY_URL: &str = "https://arweave.net";
+const PRODUCTION_CONTRACT_ADDRESS: &str = "dD1DuvgM_Vigtnv4vl2H1IYn9CgLvYuhbEWPOL-_4Mw";
+const DEVELOPMENT_CONTRACT_ADDRESS: &str = "51M3s8hKonwD1-7NKnpyf2gcwha9ql1wCz2BOnxRGwI";
+const CONTRACT_NETWORKS: [&str; 2] = ["mainnet", "testnet"];
+
+// Picks the defaults of the configuration, production archives are tagged and
+// submitted apart from the development ones
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Development,
+    Production,
+}
+
+impl FromStr for Environment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "development" | "" => Ok(Environment::Development),
+            "production" => Ok(Environment::Production),
+            // anything but production always meant development, e.g. ENVIRONMENT=staging
+            _ => {
+                warn!(
+                    "unknown environment {}, expected one of development, production, using development",
+                    s
+                );
+                Ok(Environment::Development)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Environment::Development => "development",
+            Environment::Production => "production",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// Where the archiver uploads to and submits its archives, and how it tags them
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArchiverConfig {
+    pub environment: Environment,
+    // bundlr node the data items are posted to
+    pub bundlr_url: String,
+    // arweave gateway the wallet and the contract interactions go through
+    pub gateway_url: String,
+    pub contract_address: String,
+    // warp network the contract lives on, mainnet or testnet
+    pub contract_network: String,
+    // App-Name tag of the uploads, what the archives are looked up by
+    pub app_name: String,
+    pub app_version: String,
+    // node or gateway the uploaded data items are fetched back from to be verified,
+    // the bundlr node when unset
+    pub verify_url: Option<String>,
+}
+
+// Part of a configuration, as written in a config file or given on the command line.
+// Unset values fall back to the defaults of the environment
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PartialConfig {
+    pub environment: Option<Environment>,
+    pub bundlr_url: Option<String>,
+    pub gateway_url: Option<String>,
+    pub contract_address: Option<String>,
+    pub contract_network: Option<String>,
+    pub app_name: Option<String>,
+    pub app_version: Option<String>,
+    pub verify_url: Option<String>,
+}
+
+impl ArchiverConfig {
+    pub fn for_environment(environment: Environment) -> Self {
+        let (contract_address, app_name, app_version) = match environment {
+            Environment::Production => (
+                PRODUCTION_CONTRACT_ADDRESS,
+                "archivetheweb",
+                VERSION.to_string(),
+            ),
+            Environment::Development => (
+                DEVELOPMENT_CONTRACT_ADDRESS,
+                "atw",
+                format!("{}_dev", VERSION),
+            ),
+        };
+        ArchiverConfig {
+            environment,
+            bundlr_url: DEFAULT_BUNDLR_URL.into(),
+            gateway_url: DEFAULT_GATEWAY_URL.into(),
+            contract_address: contract_address.into(),
+            contract_network: "mainnet".into(),
+            app_name: app_name.into(),
+            app_version,
+            verify_url: None,
+        }
+    }
+
+    // the overrides win over the config file, which wins over the defaults
+    pub fn resolve(file: Option<&PathBuf>, overrides: PartialConfig) -> anyhow::Result<Self> {
+        let file = match file {
+            Some(path) => PartialConfig::load(path)?,
+            None => PartialConfig::default(),
+        };
+        let environment = overrides
+            .environment
+            .or(file.environment)
+            .unwrap_or_default();
+
+        let mut config = ArchiverConfig::for_environment(environment);
+        config.apply(file);
+        config.apply(overrides);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&mut self, partial: PartialConfig) {
+        if let Some(v) = partial.bundlr_url {
+            self.bundlr_url = v;
+        }
+        if let Some(v) = partial.gateway_url {
+            self.gateway_url = v;
+        }
+        if let Some(v) = partial.contract_address {
+            self.contract_address = v;
+        }
+        if let Some(v) = partial.contract_network {
+            self.contract_network = v;
+        }
+        if let Some(v) = partial.app_name {
+            self.app_name = v;
+        }
+        if let Some(v) = partial.app_version {
+            self.app_version = v;
+        }
+        if let Some(v) = partial.verify_url {
+            self.verify_url = Some(v);
+        }
+    }
+
+    pub fn verify_url(&self) -> &str {
+        self.verify_url.as_deref().unwrap_or(&self.bundlr_url)
+    }
+
+    fn validate(&mut self) -> anyhow::Result<()> {
+        for (name, url) in [
+            ("bundlr_url", &mut self.bundlr_url),
+            ("gateway_url", &mut self.gateway_url),
+        ] {
+            reqwest::Url::parse(url).context(format!("invalid {} {}", name, url))?;
+            *url = url.trim_end_matches('/').into();
+        }
+        if let Some(url) = &mut self.verify_url {
+            reqwest::Url::parse(url).context(format!("invalid verify_url {}", url))?;
+            *url = url.trim_end_matches('/').into();
+        }
+        if !CONTRACT_NETWORKS.contains(&self.contract_network.as_str()) {
+            return Err(anyhow!(
+                "invalid contract network {}, expected one of {}",
+                self.contract_network,
+                CONTRACT_NETWORKS.join(", ")
+            ));
+        }
+        if self.contract_address.is_empty() || self.app_name.is_empty() {
+            return Err(anyhow!("the contract address and app name can't be empty"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ArchiverConfig {
+    fn default() -> Self {
+        ArchiverConfig::for_environment(Environment::default())
+    }
+}
+
+impl PartialConfig {
+    // a json file with any of the fields of the configuration
+    pub fn load(path: &PathBuf) -> anyhow::Result<Self> {
+        let data = fs::read(path).context(format!("could not read config file {:?}", path))?;
+        serde_json::from_slice(&data).context(format!("invalid config file {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::create_random_tmp_folder;
+
+    use super::*;
+
+    #[test]
+    fn falls_back_to_development() {
+        assert_eq!(
+            Environment::from_str("production").unwrap(),
+            Environment::Production
+        );
+        assert_eq!(
+            Environment::from_str("staging").unwrap(),
+            Environment::Development
+        );
+    }
+
+    #[test]
+    fn resolves_the_configuration() {
+        let config = ArchiverConfig::resolve(None, PartialConfig::default()).unwrap();
+        assert_eq!(config, ArchiverConfig::default());
+        assert_eq!(config.contract_address, DEVELOPMENT_CONTRACT_ADDRESS);
+        assert_eq!(config.app_name, "atw");
+
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("archiver.json");
+        fs::write(
+            &path,
+            r#"{"environment": "production", "bundlr_url": "https://node2.bundlr.network/", "contract_network": "testnet"}"#,
+        )
+        .unwrap();
+
+        // the environment of the file picks the defaults
+        let config = ArchiverConfig::resolve(Some(&path), PartialConfig::default()).unwrap();
+        assert_eq!(config.environment, Environment::Production);
+        assert_eq!(config.contract_address, PRODUCTION_CONTRACT_ADDRESS);
+        assert_eq!(config.app_name, "archivetheweb");
+        assert_eq!(config.bundlr_url, "https://node2.bundlr.network");
+        assert_eq!(config.contract_network, "testnet");
+        assert_eq!(config.gateway_url, DEFAULT_GATEWAY_URL);
+
+        let config = ArchiverConfig::resolve(
+            Some(&path),
+            PartialConfig {
+                environment: Some(Environment::Development),
+                bundlr_url: Some("http://127.0.0.1:1984".into()),
+                ..PartialConfig::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(config.contract_address, DEVELOPMENT_CONTRACT_ADDRESS);
+        assert_eq!(config.bundlr_url, "http://127.0.0.1:1984");
+        assert_eq!(config.contract_network, "testnet");
+        assert_eq!(config.verify_url(), "http://127.0.0.1:1984");
+
+        let config = ArchiverConfig::resolve(
+            None,
+            PartialConfig {
+                verify_url: Some("https://arweave.net/".into()),
+                ..PartialConfig::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(config.verify_url(), "https://arweave.net");
+
+        let invalid = PartialConfig {
+            contract_network: Some("devnet".into()),
+            ..PartialConfig::default()
+        };
+        assert!(ArchiverConfig::resolve(None, invalid).is_err());
+
+        fs::write(&path, r#"{"bundler_url": "https://node2.bundlr.network"}"#).unwrap();
+        assert!(ArchiverConfig::resolve(Some(&path), PartialConfig::default()).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
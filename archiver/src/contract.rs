@@ -2,6 +2,7 @@ use std::collections::{BTreeMap, HashMap};
 
 use anyhow::Context;
 use arloader::Arweave;
+use async_trait::async_trait;
 use atw::{
     action::{DeleteArchiveRequest, RegisterUploader},
     state::{ArchiveRequest, ArchiveSubmission, State, Uploader},
@@ -12,20 +13,32 @@ use warp_dre::{
     warp_dre::{WarpDRE, WarpDREOptionsBuilder},
 };
 
+use crate::config::ArchiverConfig;
+
 pub struct Contract {
     contract_id: String,
-    environment: String,
+    // warp network, mainnet or testnet
+    network: String,
     interactor: Interactor,
     reader: WarpDRE,
 }
 
 const FUNCTION: &str = "function";
 
+// What the archiver needs from the contract: the requests it should archive and
+// somewhere to submit what it archived
+#[async_trait]
+pub trait ArchiveContract: Send + Sync {
+    async fn archiving_requests_for(&self, address: &str) -> anyhow::Result<Vec<ArchiveRequest>>;
+    async fn submit_archive(&self, archive: &ArchiveSubmission) -> anyhow::Result<()>;
+    async fn delete_archive_request(&self, archive_id: &str) -> anyhow::Result<()>;
+}
+
 impl Contract {
-    pub fn new(contract_id: &str, environment: &str, arweave: Arweave) -> anyhow::Result<Self> {
+    pub fn new(config: &ArchiverConfig, arweave: Arweave) -> anyhow::Result<Self> {
         let interactor = Interactor::new(
             InteractorOptionsBuilder::default()
-                .contract_address(contract_id)
+                .contract_address(config.contract_address.as_str())
                 .build()?,
             arweave,
         )?;
@@ -33,8 +46,8 @@ impl Contract {
         let reader = WarpDRE::new(WarpDREOptionsBuilder::default().build()?);
 
         return Ok(Contract {
-            contract_id: contract_id.into(),
-            environment: environment.into(),
+            contract_id: config.contract_address.clone(),
+            network: config.contract_network.clone(),
             interactor,
             reader,
         });
@@ -197,17 +210,31 @@ impl Contract {
 
     fn prepare_query(&self) -> HashMap<String, String> {
         let mut q = HashMap::new();
-        match self.environment.as_str() {
-            "testnet" => {
-                q.insert("network".to_owned(), "testnet".to_owned());
-            }
-            _ => {}
+        if self.network == "testnet" {
+            q.insert("network".to_owned(), "testnet".to_owned());
         }
 
         q
     }
 }
 
+#[async_trait]
+impl ArchiveContract for Contract {
+    async fn archiving_requests_for(&self, address: &str) -> anyhow::Result<Vec<ArchiveRequest>> {
+        Contract::archiving_requests_for(self, address).await
+    }
+
+    async fn submit_archive(&self, archive: &ArchiveSubmission) -> anyhow::Result<()> {
+        Contract::submit_archive(self, archive).await?;
+        Ok(())
+    }
+
+    async fn delete_archive_request(&self, archive_id: &str) -> anyhow::Result<()> {
+        Contract::delete_archive_request(self, archive_id).await?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -216,7 +243,7 @@ mod test {
     use atw::state::ArchiveOptions;
     use reqwest::Url;
 
-    use crate::utils::{get_unix_timestamp, CONTRACT_ADDRESS};
+    use crate::utils::get_unix_timestamp;
 
     use super::*;
 
@@ -230,7 +257,7 @@ mod test {
         ))
         .unwrap();
 
-        let c = Contract::new(&CONTRACT_ADDRESS, "mainnet", arweave).unwrap();
+        let c = Contract::new(&ArchiverConfig::default(), arweave).unwrap();
 
         let s = tokio_test::block_on(c.state()).unwrap();
         println!("{:#?}", s);
@@ -244,7 +271,7 @@ mod test {
         ))
         .unwrap();
 
-        let c = Contract::new(&CONTRACT_ADDRESS, "mainnet", arweave).unwrap();
+        let c = Contract::new(&ArchiverConfig::default(), arweave).unwrap();
 
         let s = tokio_test::block_on(c.uploaders()).unwrap();
         println!("{:#?}", s);
@@ -258,7 +285,7 @@ mod test {
         ))
         .unwrap();
 
-        let c = Contract::new(&CONTRACT_ADDRESS, "mainnet", arweave).unwrap();
+        let c = Contract::new(&ArchiverConfig::default(), arweave).unwrap();
 
         let s = tokio_test::block_on(c.archiving_requests_for(UPLOADER_ADDRESS)).unwrap();
         println!("{:#?}", s);
@@ -272,7 +299,7 @@ mod test {
         ))
         .unwrap();
 
-        let c = Contract::new(&CONTRACT_ADDRESS, "mainnet", arweave).unwrap();
+        let c = Contract::new(&ArchiverConfig::default(), arweave).unwrap();
 
         let s = tokio_test::block_on(c.archives_by_url("example.com", 10)).unwrap();
         println!("{:#?}", s);
@@ -288,7 +315,7 @@ mod test {
         ))
         .unwrap();
 
-        let c = Contract::new(&CONTRACT_ADDRESS, "mainnet", arweave).unwrap();
+        let c = Contract::new(&ArchiverConfig::default(), arweave).unwrap();
 
         let uploader = RegisterUploader {
             friendly_name: "alice".into(),
@@ -306,7 +333,7 @@ mod test {
         ))
         .unwrap();
 
-        let c = Contract::new(&CONTRACT_ADDRESS, "mainnet", arweave).unwrap();
+        let c = Contract::new(&ArchiverConfig::default(), arweave).unwrap();
 
         let s = tokio_test::block_on(
             c.delete_archive_request("ol2dKXgntbxj5PFtbWvgmftCLibrqkjIrraQYzcweFU".into()),
@@ -323,7 +350,7 @@ mod test {
         ))
         .unwrap();
 
-        let c = Contract::new(&CONTRACT_ADDRESS, "mainnet", arweave).unwrap();
+        let c = Contract::new(&ArchiverConfig::default(), arweave).unwrap();
 
         let archive = ArchiveSubmission {
             full_url: "https://example.com?hi".into(),
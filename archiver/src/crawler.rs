@@ -1,210 +1,354 @@
+use anyhow::Context;
 use atw::state::CrawlType;
 use futures::StreamExt;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    path::PathBuf,
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::mpsc,
+    task,
+    time::{interval, MissedTickBehavior},
 };
-use tokio::{sync::mpsc, task, time::sleep};
 
 use crate::{
-    browser_controller::BrowserController,
+    budget::{BudgetLimit, CrawlBudget},
+    capture::WarcRecorder,
+    checkpoint::{CrawlCheckpoint, FrontierEntry},
+    fetcher::PageFetcher,
+    politeness::HostPoliteness,
+    robots::{RobotsCache, RobotsPolicy},
+    scope::ScopeRules,
+    sitemap::SitemapFetcher,
     types::{BrowsingResult, CrawlRequest, CrawlResult, PageCrawlResult, UrlInfo},
-    utils::{extract_url, get_domain, normalize_url_map},
+    utils::{
+        extract_collection_name, extract_url, get_domain, get_origin, get_tmp_screenshot_dir,
+        normalize_url_map,
+    },
 };
 
 pub struct Crawler {
     visited: HashSet<String>,
-    visiting: HashSet<String>,
+    // urls queued or being browsed, with their depth
+    visiting: HashMap<String, i32>,
     failed: HashMap<String, i32>,
     depth: i32,
     crawl_type: CrawlType,
     base_url: String,
+    // first seed, its title and screenshot represent the whole crawl
     url: String,
-    original_url: String,
+    // every seed of the crawl, they share the visited set and the warc collection
+    seeds: Vec<String>,
+    original_urls: Vec<String>,
     concurrent_tabs: i32,
     url_retries: i32,
     main_title: Arc<tokio::sync::Mutex<String>>,
-    timeout: u64,
-    min_wait_secs: u64,
-    max_wait_secs: u64,
+    fetcher: Arc<dyn PageFetcher>,
+    // set when capturing natively instead of through the pywb proxy
+    recorder: Option<Arc<WarcRecorder>>,
+    // per host pacing, shared with the other crawls of the process
+    politeness: Arc<HostPoliteness>,
+    robots_policy: RobotsPolicy,
+    robots: RobotsCache,
+    // urls disallowed by robots.txt
+    skipped: HashSet<String>,
+    scope: ScopeRules,
+    // urls rejected by the scope rules, so they are only counted once
+    out_of_scope: HashSet<String>,
+    // queue the urls listed in the sitemaps of the seeds
+    seed_from_sitemaps: bool,
+    max_sitemap_urls: usize,
+    budget: CrawlBudget,
+    // where the warc files of the crawl are written, to measure them
+    archive_dir: PathBuf,
+    budget_exceeded: Option<BudgetLimit>,
+    // set once a budget is exceeded, queued pages are then dropped
+    stopping: Arc<AtomicBool>,
+    // where the state of the crawl is saved, along with what identifies it
+    checkpoint: Option<(PathBuf, CrawlCheckpoint)>,
+    // urls left to crawl by a previous run of the crawl
+    resumed_frontier: Option<Vec<FrontierEntry>>,
 }
 
+// Browses a single page, told whether it is the first seed, and returns its links
+type PageVisitor = Arc<dyn Fn(&str, bool) -> BrowsingResult + Send + Sync>;
+
+// What became of a request sent to the processor, each one comes back exactly once
+enum PageOutcome {
+    Visited(PageCrawlResult),
+    Failed(CrawlRequest),
+    // not browsed because the crawl ran out of budget
+    Dropped(CrawlRequest),
+}
+
+// measuring the warc files means listing the archive dir
+const BUDGET_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+// the loop is woken up by pages, the tick only bounds how late it notices a signal
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
 impl Crawler {
     pub fn new(
         base_url: &str,
-        full_url: &str,
-        original_url: &str,
+        full_urls: &[String],
+        original_urls: &[String],
         depth: i32,
         crawl_type: CrawlType,
         concurrent_tabs: i32,
         url_retries: i32,
-        timeout: u64,
-        min_wait_secs: u64,
-        max_wait_secs: u64,
+        fetcher: Arc<dyn PageFetcher>,
+        recorder: Option<Arc<WarcRecorder>>,
+        politeness: Arc<HostPoliteness>,
+        robots_policy: RobotsPolicy,
+        seed_from_sitemaps: bool,
+        max_sitemap_urls: usize,
+        scope: ScopeRules,
+        budget: CrawlBudget,
+        archive_dir: PathBuf,
     ) -> Crawler {
         Crawler {
             visited: HashSet::new(),
-            visiting: HashSet::new(),
+            visiting: HashMap::new(),
             failed: HashMap::new(),
             base_url: base_url.into(),
             crawl_type,
             depth,
-            url: full_url.into(),
-            original_url: original_url.into(),
+            url: full_urls.first().cloned().unwrap_or_default(),
+            seeds: full_urls.to_vec(),
+            original_urls: original_urls.to_vec(),
             concurrent_tabs,
             url_retries,
             main_title: Arc::new(tokio::sync::Mutex::new(String::from(""))),
-            timeout,
-            min_wait_secs,
-            max_wait_secs,
+            fetcher,
+            recorder,
+            robots_policy,
+            robots: RobotsCache::new(),
+            politeness,
+            skipped: HashSet::new(),
+            seed_from_sitemaps,
+            max_sitemap_urls,
+            scope,
+            out_of_scope: HashSet::new(),
+            budget,
+            archive_dir,
+            budget_exceeded: None,
+            stopping: Arc::new(AtomicBool::new(false)),
+            checkpoint: None,
+            resumed_frontier: None,
         }
     }
 
+    // saves the state of the crawl to the path regularly and when it is interrupted
+    pub fn checkpoint_to(&mut self, path: PathBuf, checkpoint: CrawlCheckpoint) {
+        self.checkpoint = Some((path, checkpoint));
+    }
+
+    // picks up where an interrupted crawl stopped, its frontier replaces the seeds.
+    // Checkpoints hold the archived urls, the proxy may listen on another port by now
+    pub fn resume(&mut self, checkpoint: &CrawlCheckpoint) {
+        self.visited = checkpoint
+            .visited
+            .iter()
+            .map(|u| self.record_url(u))
+            .collect();
+        self.failed = checkpoint
+            .failed
+            .iter()
+            .map(|(u, count)| (self.record_url(u), *count))
+            .collect();
+        self.skipped = checkpoint
+            .skipped
+            .iter()
+            .map(|u| self.record_url(u))
+            .collect();
+        self.out_of_scope = checkpoint
+            .out_of_scope
+            .iter()
+            .map(|u| self.record_url(u))
+            .collect();
+        self.main_title = Arc::new(tokio::sync::Mutex::new(checkpoint.main_title.clone()));
+        self.resumed_frontier = Some(
+            checkpoint
+                .frontier
+                .iter()
+                .map(|e| FrontierEntry {
+                    url: self.record_url(&e.url),
+                    depth: e.depth,
+                })
+                .collect(),
+        );
+    }
+
     pub async fn crawl(
         &mut self,
         should_terminate: Arc<AtomicBool>,
     ) -> anyhow::Result<CrawlResult> {
-        let (scraped_urls_tx, mut scraped_urls_rx) =
-            mpsc::channel::<PageCrawlResult>(self.concurrent_tabs as usize + 10);
+        if self.seeds.is_empty() {
+            return Err(anyhow::anyhow!("a crawl needs at least one seed url"));
+        }
 
-        let (visit_url_tx, visit_url_rx) = mpsc::channel::<CrawlRequest>(1000);
-        let (failed_url_tx, mut failed_url_rx) = mpsc::channel::<CrawlRequest>(1000);
+        // the processor only holds what it can browse at once, the rest waits in the queue
+        // so the frontier stays in one place and the loop never blocks on a full channel
+        let (visit_url_tx, visit_url_rx) =
+            mpsc::channel::<CrawlRequest>(self.concurrent_tabs.max(1) as usize);
+        let (outcome_tx, mut outcome_rx) =
+            mpsc::channel::<PageOutcome>(self.concurrent_tabs.max(1) as usize + 10);
 
-        let active_tabs = Arc::new(AtomicUsize::new(0));
+        self.processor(self.visitor(), visit_url_rx, outcome_tx);
 
-        self.processor(
-            scraped_urls_tx.clone(),
-            visit_url_rx,
-            failed_url_tx,
-            active_tabs.clone(),
-        );
+        let mut queue = VecDeque::new();
+        let resumed_frontier = self.resumed_frontier.take();
+        if let Some(frontier) = &resumed_frontier {
+            for entry in frontier {
+                self.enqueue(
+                    &mut queue,
+                    CrawlRequest::new(entry.url.clone(), entry.depth),
+                );
+            }
+        }
 
-        visit_url_tx
-            .send(CrawlRequest::new(self.url.clone(), 0))
-            .await
-            .unwrap();
+        for seed in self.seeds.clone() {
+            if resumed_frontier.is_some() {
+                break;
+            }
+            if self.visiting.contains_key(&seed) {
+                continue;
+            }
+            if self.robots_policy.obeys()
+                && !self.robots_allowed(&seed).await
+                && self.robots_policy.checks_seeds()
+            {
+                info!("seed {} is disallowed by robots.txt", extract_url(&seed));
+                self.skipped.insert(seed);
+                continue;
+            }
+            self.enqueue(&mut queue, CrawlRequest::new(seed, 0));
+        }
+
+        let mut domains = HashSet::new();
+        for original_url in self.original_urls.iter() {
+            domains.insert(get_domain(original_url)?);
+        }
+
+        // sitemap urls are crawled as if the seeds linked to them, a resumed crawl
+        // already has them in its frontier
+        if self.seed_from_sitemaps && resumed_frontier.is_none() {
+            if self.depth < 1 {
+                info!("sitemaps are not read for crawls of depth 0");
+            } else {
+                for url in self.sitemap_urls(&domains).await {
+                    self.enqueue(&mut queue, CrawlRequest::new(url, 1));
+                }
+            }
+        }
+
+        let started_at = Instant::now();
+        let mut last_budget_check = Instant::now();
+        let mut last_checkpoint = Instant::now();
+        let mut ticker = interval(TICK_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-        let domain = get_domain(&self.original_url).unwrap();
+        // requests handed to the processor that did not come back yet, every one of them
+        // comes back exactly once so the crawl is over when none are left and nothing is
+        // queued
+        let mut pending = 0usize;
 
         while !should_terminate.load(Ordering::Relaxed) {
-            let res = scraped_urls_rx.try_recv();
-
-            if res.is_ok() {
-                let crawl_result = res.unwrap();
-                let visited_url = crawl_result.visited_url();
-                let depth = crawl_result.depth();
-                debug!(
-                    "adding {} as a visited url at depth {}",
-                    &visited_url,
-                    crawl_result.depth()
-                );
-                self.visited.insert(visited_url.clone());
-                self.visiting.remove(&visited_url);
-                let new_urls: HashSet<UrlInfo> = HashSet::from_iter(crawl_result.links());
-                for new_url in new_urls.iter() {
-                    if !self.visited.contains(&new_url.url)
-                        && !self.visiting.contains(&new_url.url)
-                        && depth < self.depth
-                    {
-                        match self.crawl_type {
-                            CrawlType::DomainOnly => {
-                                if new_url.domain != domain {
-                                    continue;
-                                }
-                            }
-                            CrawlType::DomainWithPageLinks => {
-                                if get_domain(&extract_url(&visited_url)).unwrap() != domain {
-                                    continue;
-                                }
-                            }
-                            CrawlType::DomainAndLinks => {}
-                        }
+            if pending == 0 && queue.is_empty() {
+                break;
+            }
+
+            // max_pages is a hard cap, no page is handed out that could go past it
+            // while the other budgets are only checked on a timer
+            let pages_left = match self.budget.max_pages {
+                Some(max) => self.visited.len() + pending < max,
+                None => true,
+            };
 
-                        debug!("adding {} to the queue", &new_url.url);
-                        self.visiting.insert(new_url.url.clone());
-                        match visit_url_tx
-                            .send(CrawlRequest::new(new_url.url.to_string(), depth + 1))
-                            .await
-                        {
-                            Ok(_) => {}
-                            Err(e) => {
-                                error!(
-                                    "could not send new_url:{} to visit_url_tx {}",
-                                    new_url.url, e
-                                )
-                            }
-                        };
+            tokio::select! {
+                permit = visit_url_tx.reserve(), if !queue.is_empty() && pages_left => {
+                    let permit = permit.context("the page processor stopped")?;
+                    if let Some(request) = queue.pop_front() {
+                        permit.send(request);
+                        pending += 1;
                     }
                 }
-            }
-
-            if self.url_retries > 0 {
-                match failed_url_rx.try_recv() {
-                    Ok(crawl_request) => {
-                        let url = crawl_request.url();
-                        let depth = crawl_request.depth();
-                        self.visiting.remove(&url);
-                        match self.failed.get_mut(&url.to_string()) {
-                            Some(count) if count <= &mut self.url_retries => {
-                                warn!(
-                                    "retrying url {} at d={}, retried {} so far",
-                                    url, depth, count
-                                );
-                                match visit_url_tx
-                                    .send(CrawlRequest::new(url.clone(), depth))
-                                    .await
-                                {
-                                    Ok(_) => {}
-                                    Err(e) => {
-                                        error!(
-                                            "could not send url {} to visit_url_tx for retry {} {}",
-                                            url, count, e
-                                        )
-                                    }
-                                };
-                                *count = *count + 1;
-                            }
-                            None => {
-                                warn!("first retry of url {} at d={}", url, depth);
-                                self.failed.insert(url.to_string(), 0);
-                                match visit_url_tx
-                                    .send(CrawlRequest::new(url.clone(), depth))
-                                    .await
-                                {
-                                    Ok(_) => {}
-                                    Err(e) => {
-                                        error!("could not send url {} to visit_url_tx for first try {}", url, e)
-                                    }
-                                };
-                            }
-                            _ => {
-                                error!("url {} could not be retrieved", url);
-                            }
+                outcome = outcome_rx.recv() => {
+                    let outcome = match outcome {
+                        Some(o) => o,
+                        None => return Err(anyhow::anyhow!("the page processor stopped")),
+                    };
+                    pending -= 1;
+                    match outcome {
+                        PageOutcome::Visited(result) => {
+                            self.follow_links(result, &domains, &mut queue).await
+                        }
+                        PageOutcome::Failed(request) => self.retry(request, &mut queue),
+                        PageOutcome::Dropped(request) => {
+                            self.visiting.remove(&request.url());
                         }
                     }
-                    Err(_) => {}
                 }
+                _ = ticker.tick() => {}
             }
 
-            // if all of our channels are at capacity and we have no active tabs, we are done
-            if scraped_urls_tx.capacity() == scraped_urls_tx.max_capacity()
-                && visit_url_tx.capacity() == visit_url_tx.max_capacity()
-                && active_tabs.load(Ordering::SeqCst) == 0
+            let pages_reached = match self.budget.max_pages {
+                Some(max) => self.visited.len() >= max,
+                None => false,
+            };
+            if self.budget_exceeded.is_none()
+                && !self.budget.is_unlimited()
+                && (pages_reached || last_budget_check.elapsed() >= BUDGET_CHECK_INTERVAL)
             {
-                break;
+                last_budget_check = Instant::now();
+                if let Some(limit) =
+                    self.budget
+                        .exceeded(self.visited.len(), started_at, &self.archive_dir)
+                {
+                    info!(
+                        "{} budget of the crawl of {} reached, waiting for the open tabs",
+                        limit,
+                        extract_url(&self.url)
+                    );
+                    self.budget_exceeded = Some(limit);
+                    self.stopping.store(true, Ordering::SeqCst);
+                    for request in queue.drain(..) {
+                        self.visiting.remove(&request.url());
+                    }
+                }
+            }
+
+            if self.checkpoint.is_some() && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                last_checkpoint = Instant::now();
+                self.save_checkpoint().await;
             }
+        }
 
-            sleep(Duration::from_millis(10)).await;
+        // the pages still being browsed are part of the frontier, they are crawled again.
+        // A completed crawl is saved as well, it is resumed as is until it is stored
+        if self.checkpoint.is_some() {
+            self.save_checkpoint().await;
+            if should_terminate.load(Ordering::Relaxed) {
+                info!(
+                    "crawl of {} interrupted with {} pages left, its state was saved",
+                    extract_url(&self.url),
+                    self.visiting.len()
+                );
+            }
         }
+
         let failed = self
             .failed
             .iter()
             .filter_map(|x| {
-                if x.1 >= &self.url_retries {
+                // pages that made it on a later retry are not failures
+                if x.1 >= &self.url_retries && !self.visited.contains(x.0) {
                     return Some(x.0.to_owned());
                 }
                 None
@@ -217,10 +361,19 @@ impl Crawler {
             failed.len()
         );
 
-        if failed.len() > 0 {
+        if !failed.is_empty() {
             debug!("Failed urls: {:#?}", failed);
         }
 
+        if !self.skipped.is_empty() {
+            debug!("Urls disallowed by robots.txt: {:#?}", self.skipped);
+        }
+
+        let scope = self.scope.report();
+        if scope.total() > 0 {
+            debug!("Urls out of scope: {:#?}", scope);
+        }
+
         let url = extract_url(&self.url);
         info!("crawl of {} completed successfully", extract_url(&self.url));
 
@@ -229,191 +382,742 @@ impl Crawler {
             self.main_title.lock().await.to_string(),
             self.visited.clone(),
             failed,
+            self.skipped.clone(),
+            scope,
+            self.budget_exceeded.clone(),
         ))
     }
 
+    // queued urls count as being visited, so they are never queued twice
+    fn enqueue(&mut self, queue: &mut VecDeque<CrawlRequest>, request: CrawlRequest) {
+        self.visiting.insert(request.url(), request.depth());
+        queue.push_back(request);
+    }
+
+    async fn follow_links(
+        &mut self,
+        crawl_result: PageCrawlResult,
+        domains: &HashSet<String>,
+        queue: &mut VecDeque<CrawlRequest>,
+    ) {
+        let visited_url = crawl_result.visited_url();
+        let depth = crawl_result.depth();
+        debug!(
+            "adding {} as a visited url at depth {}",
+            &visited_url,
+            crawl_result.depth()
+        );
+        self.visited.insert(visited_url.clone());
+        self.visiting.remove(&visited_url);
+        let new_urls: HashSet<UrlInfo> = HashSet::from_iter(crawl_result.links());
+        let mut followed = vec![];
+        for new_url in new_urls.iter() {
+            if !self.visited.contains(&new_url.url)
+                && !self.visiting.contains_key(&new_url.url)
+                && !self.skipped.contains(&new_url.url)
+                && !self.out_of_scope.contains(&new_url.url)
+                && depth < self.depth
+                && self.budget_exceeded.is_none()
+            {
+                match self.crawl_type {
+                    CrawlType::DomainOnly => {
+                        if !domains.contains(&new_url.domain) {
+                            continue;
+                        }
+                    }
+                    CrawlType::DomainWithPageLinks => {
+                        match get_domain(&extract_url(&visited_url)) {
+                            Ok(d) if domains.contains(&d) => {}
+                            _ => continue,
+                        }
+                    }
+                    CrawlType::DomainAndLinks => {}
+                }
+
+                if !self.in_scope(&new_url.url) {
+                    continue;
+                }
+
+                followed.push(new_url.url.clone());
+            }
+        }
+
+        // robots.txt of new origins are fetched together rather than one link at a time
+        if self.robots_policy.obeys() {
+            self.robots.prefetch(&followed).await;
+        }
+        for url in followed {
+            if self.robots_policy.obeys() && !self.robots_allowed(&url).await {
+                debug!("{} is disallowed by robots.txt", &url);
+                self.skipped.insert(url);
+                continue;
+            }
+
+            debug!("adding {} to the queue", &url);
+            self.enqueue(queue, CrawlRequest::new(url, depth + 1));
+        }
+    }
+
+    fn retry(&mut self, crawl_request: CrawlRequest, queue: &mut VecDeque<CrawlRequest>) {
+        let url = crawl_request.url();
+        let depth = crawl_request.depth();
+        // once out of budget, failed pages are not retried
+        if self.url_retries <= 0 || self.budget_exceeded.is_some() {
+            error!("url {} could not be retrieved", url);
+            self.failed.insert(url.clone(), self.url_retries);
+            self.visiting.remove(&url);
+            return;
+        }
+
+        // the count is the number of retries made so far
+        let count = self.failed.entry(url.clone()).or_insert(0);
+        if *count < self.url_retries {
+            *count += 1;
+            warn!("retry {} of url {} at d={}", count, url, depth);
+            queue.push_back(crawl_request);
+        } else {
+            error!("url {} could not be retrieved", url);
+            self.visiting.remove(&url);
+        }
+    }
+
+    fn in_scope(&mut self, url: &str) -> bool {
+        if self.scope.is_empty() || self.scope.allows(&extract_url(url)) {
+            return true;
+        }
+        debug!("{} is out of scope", url);
+        self.out_of_scope.insert(url.into());
+        false
+    }
+
+    // reads the sitemaps of every seed origin and keeps the urls the crawl type allows
+    async fn sitemap_urls(&mut self, domains: &HashSet<String>) -> Vec<String> {
+        let fetcher = SitemapFetcher::new(self.max_sitemap_urls);
+        let origins = self
+            .original_urls
+            .iter()
+            .filter_map(|u| get_origin(u).ok())
+            .collect::<HashSet<String>>();
+
+        let mut urls = vec![];
+        for origin in origins {
+            let robots_sitemaps = match self.robots.get(&origin).await {
+                Ok(robots) => robots.sitemaps(),
+                Err(_) => vec![],
+            };
+            for url in fetcher.urls(&origin, &robots_sitemaps).await {
+                if urls.len() >= self.max_sitemap_urls {
+                    break;
+                }
+                let domain = match get_domain(&url) {
+                    Ok(d) => d,
+                    Err(_) => continue,
+                };
+                match self.crawl_type {
+                    CrawlType::DomainOnly | CrawlType::DomainWithPageLinks => {
+                        if !domains.contains(&domain) {
+                            continue;
+                        }
+                    }
+                    CrawlType::DomainAndLinks => {}
+                }
+
+                let url = self.record_url(&url);
+                if self.visiting.contains_key(&url)
+                    || self.skipped.contains(&url)
+                    || self.out_of_scope.contains(&url)
+                    || !self.in_scope(&url)
+                {
+                    continue;
+                }
+                if self.robots_policy.obeys() && !self.robots_allowed(&url).await {
+                    debug!("{} is disallowed by robots.txt", &url);
+                    self.skipped.insert(url);
+                    continue;
+                }
+                urls.push(url);
+            }
+        }
+        info!("{} urls queued from sitemaps", urls.len());
+        urls
+    }
+
+    async fn save_checkpoint(&mut self) {
+        let main_title = self.main_title.lock().await.to_string();
+        let (path, checkpoint) = match &mut self.checkpoint {
+            Some(c) => c,
+            None => return,
+        };
+
+        let mut frontier = self
+            .visiting
+            .iter()
+            .map(|(url, depth)| FrontierEntry {
+                url: extract_url(url),
+                depth: *depth,
+            })
+            .collect::<Vec<FrontierEntry>>();
+        frontier.sort();
+
+        checkpoint.main_title = main_title;
+        checkpoint.frontier = frontier;
+        checkpoint.visited = self.visited.iter().map(|u| extract_url(u)).collect();
+        checkpoint.failed = self
+            .failed
+            .iter()
+            .map(|(u, count)| (extract_url(u), *count))
+            .collect();
+        checkpoint.skipped = self.skipped.iter().map(|u| extract_url(u)).collect();
+        checkpoint.out_of_scope = self.out_of_scope.iter().map(|u| extract_url(u)).collect();
+
+        match checkpoint.write(path) {
+            Ok(_) => debug!("crawl state saved to {:?}", path),
+            Err(e) => warn!("could not save crawl state {}", e),
+        }
+    }
+
+    // sitemap urls go through the same recording proxy as the seeds
+    fn record_url(&self, url: &str) -> String {
+        match self
+            .original_urls
+            .first()
+            .and_then(|o| self.url.strip_suffix(o.as_str()))
+        {
+            Some(prefix) => format!("{}{}", prefix, url),
+            None => url.into(),
+        }
+    }
+
+    // fetches the robots.txt of the url's origin once and registers its crawl delay
+    async fn robots_allowed(&mut self, url: &str) -> bool {
+        let url = extract_url(url);
+        let robots = match self.robots.get(&url).await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("could not get robots.txt for {} {}", url, e);
+                return false;
+            }
+        };
+        if let Some(delay) = robots.crawl_delay() {
+            self.politeness.set_crawl_delay(&url, delay);
+        }
+        robots.is_allowed(&url)
+    }
+
+    // loads the pages with the fetcher and keeps the links to follow
+    fn visitor(&self) -> PageVisitor {
+        let fetcher = self.fetcher.clone();
+        let base_url = self.base_url.clone();
+        let title_mutex = self.main_title.clone();
+        let recorder = self.recorder.clone();
+        let politeness = self.politeness.clone();
+        Arc::new(move |u: &str, is_first_url: bool| {
+            let page = match fetcher.navigate(u, is_first_url) {
+                Ok(p) => p,
+                Err(e) => return BrowsingResult::new(Vec::new(), Some(e.into())),
+            };
+
+            if let Some(response) = page.response() {
+                politeness.record_response(
+                    &extract_url(u),
+                    response.status,
+                    response.retry_after.as_deref(),
+                );
+                // rate limited pages are retried once the host had some rest
+                if response.status == 429 || response.status == 503 {
+                    return BrowsingResult::new(
+                        Vec::new(),
+                        Some(anyhow::anyhow!("{} answered {}", u, response.status).into()),
+                    );
+                }
+            }
+
+            if is_first_url {
+                match page.title() {
+                    Ok(title) => {
+                        let mut main_title = title_mutex.blocking_lock();
+                        *main_title = title;
+                    }
+                    Err(e) => {
+                        warn!("could not get title {:?}", e);
+                    }
+                };
+                if let Some(png) = page.screenshot() {
+                    // natively captured urls are not prefixed by the collection name
+                    let collection_name = match &recorder {
+                        Some(r) => r.archive_name(),
+                        None => extract_collection_name(u),
+                    };
+                    let filename = get_tmp_screenshot_dir(&collection_name);
+                    debug!("saving temporary screenshot to {}", filename);
+                    if let Err(e) = fs::write(&filename, png) {
+                        return BrowsingResult::new(
+                            Vec::new(),
+                            Some(
+                                anyhow::anyhow!("could not save screenshot for {} {}", u, e).into(),
+                            ),
+                        );
+                    }
+                }
+            }
+
+            let hrefs = page.links();
+            // without the proxy, relative links are relative to the page itself
+            let base_url = match &recorder {
+                Some(_) => get_origin(u).unwrap_or(base_url.clone()),
+                None => base_url.clone(),
+            };
+            let links = hrefs
+                .iter()
+                .filter_map(normalize_url_map(base_url))
+                .collect::<Vec<UrlInfo>>();
+            if let Some(r) = &recorder {
+                let outlinks = links.iter().map(|l| l.url.clone()).collect::<Vec<String>>();
+                r.record_page_metadata(u, page.title().ok().as_deref(), &outlinks);
+            }
+
+            BrowsingResult::new(links, None)
+        })
+    }
+
+    // browses the requests it receives and sends back one outcome for each of them
     fn processor(
         &self,
-        scraped_urls_tx: mpsc::Sender<PageCrawlResult>,
+        visitor: PageVisitor,
         visit_url_rx: mpsc::Receiver<CrawlRequest>,
-        failed_url_tx: mpsc::Sender<CrawlRequest>,
-        active_tabs: Arc<AtomicUsize>,
+        outcome_tx: mpsc::Sender<PageOutcome>,
     ) {
-        let concurrent_tabs = self.concurrent_tabs;
-        let base_url = self.base_url.clone();
+        let concurrent_tabs = self.concurrent_tabs.max(1);
         let original_url = self.url.clone();
-        let title = self.main_title.clone();
-        let min_wait = self.min_wait_secs;
-        let max_wait = self.max_wait_secs;
-        let timeout = self.timeout;
+        let politeness = self.politeness.clone();
+        let stopping = self.stopping.clone();
         tokio::spawn(async move {
             tokio_stream::wrappers::ReceiverStream::new(visit_url_rx)
                 .for_each_concurrent(concurrent_tabs as usize, |crawl_request| {
-                    let url = crawl_request.url();
-                    let depth = crawl_request.depth();
-                    debug!("crawling {} at depth {}", url, depth);
-
-                    let at = active_tabs.clone();
-                    let scraped_urls_tx = scraped_urls_tx.clone();
-                    let failed_url_tx = failed_url_tx.clone();
-                    let u = url.clone();
-                    let base_url = base_url.clone();
-                    let is_first_url = original_url == u;
-                    let title_mutex = title.clone();
+                    let outcome_tx = outcome_tx.clone();
+                    let visitor = visitor.clone();
+                    let is_first_url = original_url == crawl_request.url();
+                    let stopping = stopping.clone();
+                    let politeness = politeness.clone();
 
                     async move {
-                        at.fetch_add(1, Ordering::SeqCst);
-
-                        let links = task::spawn_blocking(move || {
-                            // headless chrome can't handle pdfs, so we make a direct request for it
-                            if u.as_str().ends_with(".pdf") {
-                                match Self::fetch_pdf(u.clone()) {
-                                    Ok(_) => return BrowsingResult::new(Vec::new(), None),
-                                    Err(e) => return BrowsingResult::new(Vec::new(), Some(e.into())),
-                                }
-                            }
-
-                            let browser = match BrowserController::new(timeout, min_wait, max_wait)
-                            {
-                                Ok(b) => b,
-                                Err(e) => return BrowsingResult::new(Vec::new(), Some(e.into())),
-                            };
-
-                            let tab = browser.browse(u.as_str(), is_first_url);
-
-                            if tab.is_err() {
-                                let c = reqwest::blocking::Client::new();
-                                let head = c.head(&u).send();
-
-                                if head.is_err() {
-                                    let err = head.err().unwrap();
-                                    warn!(
-                                        "error browsing for {} with tab err {}, head err {}",
-                                        &u,
-                                        tab.err().unwrap(),
-                                        err
-                                    );
-                                    // we return an empty list of links, and flag as errored out
-                                    return BrowsingResult::new(Vec::new(), Some(err.into()));
-                                } else {
-                                    let head = head.unwrap();
-                                    let content_type = head.headers().get("Content-Type");
-                                    if content_type.is_some()
-                                        && content_type
-                                            .unwrap()
-                                            .to_str()
-                                            .unwrap()
-                                            .contains("application/pdf")
-                                    {
-                                        if Self::fetch_pdf(u.clone()).is_ok() {
-                                            return BrowsingResult::new(Vec::new(), None);
-                                        } else {
-                                            return BrowsingResult::new(
-                                                Vec::new(),
-                                                Some(anyhow::anyhow!("could not fetch pdf").into()),
-                                            );
-                                        }
-                                    } else {
-                                        warn!(
-                                            "error browsing for {} with tab err {}",
-                                            &u,
-                                            tab.err().unwrap(),
-                                        );
-                                        // we return an empty list of links, and flag as errored out
-                                        return BrowsingResult::new(
-                                            Vec::new(),
-                                            Some(anyhow::anyhow!("not a pdf").into()),
-                                        );
-                                    }
-                                }
-                            }
-                            let tab = tab.unwrap();
-                            if is_first_url {
-                                match tab.get_title() {
-                                    Ok(title) => {
-                                        let mut main_title = title_mutex.blocking_lock();
-                                        *main_title = title;
-                                    }
-                                    Err(e) => {
-                                        warn!("could not get title {:?}", e);
-                                    }
-                                };
-                            }
-
-                            return BrowsingResult::new(
-                                browser
-                                    .get_links(&tab)
-                                    .iter()
-                                    .filter_map(normalize_url_map(base_url.into()))
-                                    .collect::<Vec<UrlInfo>>(),
-                                None,
-                            );
-                        })
-                        .await;
-
-                        let links = match links {
-                            Ok(l) => l,
-                            Err(e) => {
-                                error!("problem spawning a blocking thread {}", e);
-                                at.fetch_sub(1, Ordering::SeqCst);
-                                failed_url_tx
-                                    .send(CrawlRequest::new(url, depth))
-                                    .await
-                                    .unwrap();
-                                return;
-                            }
-                        };
-
-                        // the boolean in the second element of the tuple
-                        // tells us whether there was an error or not
-                        // if so, we send the url to the failed url channel
-                        if links.error.is_some() {
-                            warn!(
-                                "error browsing for {}, error: {}",
-                                url,
-                                links.error.unwrap()
-                            );
-                            match failed_url_tx.send(CrawlRequest::new(url, depth)).await {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    error!("could not send to failed_url_tx {}", e)
-                                }
-                            };
-                        } else {
-                            match scraped_urls_tx
-                                .send(PageCrawlResult::new(url, links.links(), depth))
-                                .await
-                            {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    error!("could not send to scraped_urls_tx {}", e)
-                                }
-                            };
+                        let outcome =
+                            Self::visit(crawl_request, visitor, is_first_url, politeness, stopping)
+                                .await;
+                        if let Err(e) = outcome_tx.send(outcome).await {
+                            error!("could not send page outcome {}", e);
                         }
-                        at.fetch_sub(1, Ordering::SeqCst);
                     }
                 })
                 .await;
-
-            return;
         });
     }
 
+    async fn visit(
+        crawl_request: CrawlRequest,
+        visitor: PageVisitor,
+        is_first_url: bool,
+        politeness: Arc<HostPoliteness>,
+        stopping: Arc<AtomicBool>,
+    ) -> PageOutcome {
+        let url = crawl_request.url();
+        let depth = crawl_request.depth();
+        // the crawl ran out of budget, queued pages are dropped
+        if stopping.load(Ordering::SeqCst) {
+            return PageOutcome::Dropped(crawl_request);
+        }
+        // held until the page is browsed
+        let _permit = politeness.acquire(&extract_url(&url)).await;
+        if stopping.load(Ordering::SeqCst) {
+            return PageOutcome::Dropped(crawl_request);
+        }
+        debug!("crawling {} at depth {}", url, depth);
+
+        let u = url.clone();
+        let links = match task::spawn_blocking(move || visitor(&u, is_first_url)).await {
+            Ok(l) => l,
+            Err(e) => {
+                error!("problem spawning a blocking thread {}", e);
+                return PageOutcome::Failed(crawl_request);
+            }
+        };
+
+        match links.error {
+            Some(e) => {
+                warn!("error browsing for {}, error: {}", url, e);
+                PageOutcome::Failed(crawl_request)
+            }
+            None => PageOutcome::Visited(PageCrawlResult::new(url, links.links(), depth)),
+        }
+    }
+
     pub fn url(&self) -> String {
         self.url.to_string()
     }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{
+        mock_site::{SiteGraph, SitePage},
+        politeness::PolitenessOptions,
+        scope::ScopeRule,
+    };
+
+    use super::*;
+
+    fn page_url(i: usize) -> String {
+        format!("https://example.com/{}", i)
+    }
+
+    fn crawler(fetcher: Arc<SiteGraph>, seed: &str, depth: i32, url_retries: i32) -> Crawler {
+        let seeds = vec![seed.to_string()];
+        Crawler::new(
+            "https://example.com",
+            &seeds,
+            &seeds,
+            depth,
+            CrawlType::DomainAndLinks,
+            4,
+            url_retries,
+            fetcher,
+            None,
+            Arc::new(HostPoliteness::new(
+                PolitenessOptions::default_builder()
+                    .max_tabs_per_host(100usize)
+                    .min_host_delay(0u64)
+                    .build()
+                    .unwrap(),
+            )),
+            RobotsPolicy::Ignore,
+            false,
+            0,
+            ScopeRules::default(),
+            CrawlBudget::default(),
+            PathBuf::new(),
+        )
+    }
+
+    async fn crawl(crawler: &mut Crawler) -> CrawlResult {
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            crawler.crawl(Arc::new(AtomicBool::new(false))),
+        )
+        .await
+        .expect("the crawl never completed")
+        .unwrap();
+        let _ = fs::remove_file(get_tmp_screenshot_dir(&extract_collection_name(
+            &crawler.url(),
+        )));
+        result
+    }
+
+    fn urls(urls: &[&str]) -> HashSet<String> {
+        urls.iter().map(|u| u.to_string()).collect()
+    }
+
+    // A random site where each page links to others by index. Broken pages always
+    // fail, flaky ones only on their first visit
+    struct RandomSite {
+        links: Vec<Vec<usize>>,
+        broken: HashSet<usize>,
+        flaky: HashSet<usize>,
+    }
 
-    fn fetch_pdf(url: String) -> anyhow::Result<()> {
-        match reqwest::blocking::get(url.as_str()) {
-            Ok(res) => {
-                debug!("fetching pdf at {}", url.as_str());
-                let _r = res.text();
-                return Ok(());
+    impl RandomSite {
+        fn new(rng: &mut StdRng) -> Self {
+            let pages = rng.gen_range(1..40);
+            let links = (0..pages)
+                .map(|_| {
+                    let count = rng.gen_range(0..6);
+                    (0..count).map(|_| rng.gen_range(0..pages)).collect()
+                })
+                .collect();
+            let broken = (1..pages).filter(|_| rng.gen_bool(0.1)).collect();
+            let flaky = (1..pages).filter(|_| rng.gen_bool(0.1)).collect();
+            RandomSite {
+                links,
+                broken,
+                flaky,
             }
-            Err(e) => {
-                warn!("error downloading pdf err: {}", e);
-                return Err(anyhow::anyhow!(e));
+        }
+
+        fn graph(&self) -> SiteGraph {
+            let mut graph = SiteGraph::new();
+            for (i, links) in self.links.iter().enumerate() {
+                let links = links.iter().map(|l| page_url(*l)).collect::<Vec<String>>();
+                let mut page =
+                    SitePage::new(&links.iter().map(|l| l.as_str()).collect::<Vec<&str>>());
+                if self.broken.contains(&i) {
+                    page.failures = usize::MAX;
+                } else if self.flaky.contains(&i) {
+                    page.failures = 1;
+                }
+                graph = graph.with_page(&page_url(i), page);
+            }
+            graph
+        }
+
+        // pages reachable from the first one within the depth, broken pages have no links
+        fn reachable(&self, depth: i32) -> HashSet<usize> {
+            let mut seen = HashSet::from([0]);
+            let mut frontier = vec![0];
+            for _ in 0..depth {
+                let mut next = vec![];
+                for page in frontier {
+                    if self.broken.contains(&page) {
+                        continue;
+                    }
+                    for link in &self.links[page] {
+                        if seen.insert(*link) {
+                            next.push(*link);
+                        }
+                    }
+                }
+                frontier = next;
+            }
+            seen
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn completes_crawls_of_random_site_graphs() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for i in 0..3000 {
+            let site = RandomSite::new(&mut rng);
+            let depth = rng.gen_range(0..6);
+            let url_retries = rng.gen_range(1..3);
+
+            let mut crawler = crawler(Arc::new(site.graph()), &page_url(0), depth, url_retries);
+            crawler.concurrent_tabs = rng.gen_range(1..9);
+            let result = crawl(&mut crawler).await;
+
+            let reachable = site.reachable(depth);
+            let visited = result.visited();
+            let failed = result.failed();
+            for url in visited.iter().chain(failed.iter()) {
+                let page = url.rsplit('/').next().unwrap().parse::<usize>().unwrap();
+                assert!(reachable.contains(&page), "graph {} visited {}", i, url);
             }
+            for page in &site.broken {
+                assert!(!visited.contains(&page_url(*page)), "graph {}", i);
+            }
+            // without a depth limit every reachable page is either visited or failed
+            if depth as usize >= site.links.len() {
+                let expected = reachable
+                    .iter()
+                    .filter(|p| !site.broken.contains(p))
+                    .map(|p| page_url(*p))
+                    .collect::<HashSet<String>>();
+                assert_eq!(visited, expected, "graph {}", i);
+                let expected_failed = reachable
+                    .iter()
+                    .filter(|p| site.broken.contains(p))
+                    .map(|p| page_url(*p))
+                    .collect::<HashSet<String>>();
+                assert_eq!(failed, expected_failed, "graph {}", i);
+            }
+            assert!(crawler.visiting.is_empty(), "graph {}", i);
         }
     }
+
+    #[tokio::test]
+    async fn stops_when_terminated() {
+        let graph = Arc::new(SiteGraph::new().page("https://example.com/", &["/a"]));
+        let mut crawler = crawler(graph, "https://example.com/", 10, 1);
+        let result = crawler
+            .crawl(Arc::new(AtomicBool::new(true)))
+            .await
+            .unwrap();
+        assert!(result.visited().is_empty());
+    }
+
+    #[tokio::test]
+    async fn follows_links_up_to_the_depth() {
+        let graph = Arc::new(
+            SiteGraph::new()
+                .page("https://example.com/", &["/a", "https://example.com/b"])
+                .page("https://example.com/a", &["/c"])
+                .page("https://example.com/b", &["/"])
+                .page("https://example.com/c", &["/d"])
+                .page("https://example.com/d", &[]),
+        );
+        let mut c = crawler(graph.clone(), "https://example.com/", 2, 1);
+        let result = crawl(&mut c).await;
+        assert_eq!(
+            result.visited(),
+            urls(&[
+                "https://example.com/",
+                "https://example.com/a",
+                "https://example.com/b",
+                "https://example.com/c",
+            ])
+        );
+
+        let mut c = crawler(graph, "https://example.com/", 0, 1);
+        let result = crawl(&mut c).await;
+        assert_eq!(result.visited(), urls(&["https://example.com/"]));
+    }
+
+    #[tokio::test]
+    async fn keeps_to_the_crawl_type() {
+        let graph = Arc::new(
+            SiteGraph::new()
+                .page("https://example.com/", &["/a", "https://other.com/"])
+                .page("https://example.com/a", &[])
+                .page("https://other.com/", &["https://other.com/b"])
+                .page("https://other.com/b", &[]),
+        );
+        let crawl_type_visits = |crawl_type: CrawlType| {
+            let graph = graph.clone();
+            async move {
+                let mut c = crawler(graph, "https://example.com/", 3, 1);
+                c.crawl_type = crawl_type;
+                crawl(&mut c).await.visited()
+            }
+        };
+
+        assert_eq!(
+            crawl_type_visits(CrawlType::DomainOnly).await,
+            urls(&["https://example.com/", "https://example.com/a"])
+        );
+        // the pages of the site are crawled with the pages they link to
+        assert_eq!(
+            crawl_type_visits(CrawlType::DomainWithPageLinks).await,
+            urls(&[
+                "https://example.com/",
+                "https://example.com/a",
+                "https://other.com/",
+            ])
+        );
+        assert_eq!(crawl_type_visits(CrawlType::DomainAndLinks).await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn applies_scope_rules() {
+        let graph = Arc::new(
+            SiteGraph::new()
+                .page("https://example.com/", &["/blog/1", "/logout", "/about"])
+                .page("https://example.com/blog/1", &["/blog/2"])
+                .page("https://example.com/blog/2", &[])
+                .page("https://example.com/logout", &[])
+                .page("https://example.com/about", &[]),
+        );
+        let mut c = crawler(graph.clone(), "https://example.com/", 3, 1);
+        c.scope = ScopeRules::new(vec![
+            "exclude:glob:/logout".parse::<ScopeRule>().unwrap(),
+            "include:glob:/blog/**".parse::<ScopeRule>().unwrap(),
+        ]);
+        let result = crawl(&mut c).await;
+        assert_eq!(
+            result.visited(),
+            urls(&[
+                "https://example.com/",
+                "https://example.com/blog/1",
+                "https://example.com/blog/2",
+            ])
+        );
+        assert_eq!(result.scope().total(), 2);
+        assert_eq!(graph.navigations("https://example.com/logout"), 0);
+    }
+
+    #[tokio::test]
+    async fn retries_failed_pages() {
+        let graph = Arc::new(
+            SiteGraph::new()
+                .page("https://example.com/", &["/flaky", "/broken"])
+                .with_page(
+                    "https://example.com/flaky",
+                    SitePage {
+                        failures: 2,
+                        ..SitePage::new(&[])
+                    },
+                )
+                .broken("https://example.com/broken"),
+        );
+        let mut c = crawler(graph.clone(), "https://example.com/", 1, 2);
+        let result = crawl(&mut c).await;
+        assert_eq!(
+            result.visited(),
+            urls(&["https://example.com/", "https://example.com/flaky"])
+        );
+        assert_eq!(result.failed(), urls(&["https://example.com/broken"]));
+        assert_eq!(graph.navigations("https://example.com/flaky"), 3);
+        // the first navigation and exactly url_retries retries
+        assert_eq!(graph.navigations("https://example.com/broken"), 3);
+
+        // pages that fail without retries are reported right away
+        let mut c = crawler(graph.clone(), "https://example.com/", 1, 0);
+        let result = crawl(&mut c).await;
+        assert_eq!(result.failed(), urls(&["https://example.com/broken"]));
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_max_pages() {
+        let links = (1..20).map(|i| format!("/{}", i)).collect::<Vec<String>>();
+        let links = links.iter().map(|l| l.as_str()).collect::<Vec<&str>>();
+        let mut graph = SiteGraph::new().page("https://example.com/", &links);
+        for i in 1..20 {
+            graph = graph.page(&page_url(i), &[]);
+        }
+        let graph = Arc::new(graph);
+        let mut c = crawler(graph.clone(), "https://example.com/", 1, 1);
+        c.budget = CrawlBudget {
+            max_pages: Some(5),
+            ..CrawlBudget::default()
+        };
+        let result = crawl(&mut c).await;
+        // the 4 tabs never browse more pages than the budget allows
+        assert_eq!(result.visited().len(), 5);
+        assert_eq!(result.budget_exceeded(), Some(BudgetLimit::MaxPages));
+        let navigations: usize = (1..20).map(|i| graph.navigations(&page_url(i))).sum();
+        assert_eq!(navigations, 4);
+    }
+
+    #[tokio::test]
+    async fn visits_pages_once() {
+        let graph = Arc::new(
+            SiteGraph::new()
+                .page("https://example.com/", &["/a", "/b", "/a#top"])
+                .page(
+                    "https://example.com/a",
+                    &["/b", "/", "https://example.com/a"],
+                )
+                .page("https://example.com/b", &["/a", "/"]),
+        );
+        let mut c = crawler(graph.clone(), "https://example.com/", 5, 1);
+        let result = crawl(&mut c).await;
+        assert_eq!(result.visited().len(), 3);
+        for url in result.visited() {
+            assert_eq!(graph.navigations(&url), 1, "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_title_of_the_first_seed() {
+        let graph = Arc::new(
+            SiteGraph::new()
+                .with_page(
+                    "https://example.com/home",
+                    SitePage {
+                        title: "Home".into(),
+                        ..SitePage::new(&["/a"])
+                    },
+                )
+                .with_page(
+                    "https://example.com/a",
+                    SitePage {
+                        title: "A".into(),
+                        ..SitePage::new(&[])
+                    },
+                ),
+        );
+        let mut c = crawler(graph, "https://example.com/home", 1, 1);
+        let screenshot = get_tmp_screenshot_dir("home");
+        let result = c.crawl(Arc::new(AtomicBool::new(false))).await.unwrap();
+        assert_eq!(result.main_title(), "Home");
+        assert!(fs::remove_file(screenshot).is_ok());
+    }
 }
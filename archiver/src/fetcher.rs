@@ -0,0 +1,190 @@
+use std::{fmt, sync::Arc};
+
+use anyhow::{anyhow, Context};
+use headless_chrome::Tab;
+
+use crate::{
+    browser_controller::{BrowserController, PageResponse},
+    browser_pool::{BrowserLease, BrowserPool},
+    capture::WarcRecorder,
+    readiness::PageReadiness,
+};
+
+// Loads the pages of a crawl, the crawler only deals with what they link to
+pub trait PageFetcher: Send + Sync {
+    // loads the page and waits until it is ready to be read, the screenshot is only
+    // taken when asked for
+    fn navigate(&self, url: &str, screenshot: bool) -> anyhow::Result<Box<dyn FetchedPage>>;
+}
+
+impl fmt::Debug for dyn PageFetcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PageFetcher")
+    }
+}
+
+// A page loaded by a fetcher, its resources are freed when it is dropped
+pub trait FetchedPage: Send {
+    // status of the main document, when the fetcher knows it
+    fn response(&self) -> Option<PageResponse>;
+    // href of every link, as written in the page
+    fn links(&self) -> Vec<String>;
+    fn title(&self) -> anyhow::Result<String>;
+    // png of the page as it was loaded, if it was asked for
+    fn screenshot(&self) -> Option<Vec<u8>>;
+}
+
+// Browses the pages with the chrome browsers of a pool, pdfs are downloaded
+pub struct ChromeFetcher {
+    browser_pool: Arc<BrowserPool>,
+    readiness: PageReadiness,
+    // set when capturing natively instead of through the pywb proxy
+    recorder: Option<Arc<WarcRecorder>>,
+}
+
+struct ChromePage {
+    tab: Arc<Tab>,
+    browser: Arc<BrowserController>,
+    response: Option<PageResponse>,
+    screenshot: Option<Vec<u8>>,
+    // keeps the browser busy with this page until it is closed
+    _lease: BrowserLease,
+}
+
+// a file chrome could not render, recorded by downloading it
+struct DownloadedPage;
+
+impl ChromeFetcher {
+    pub fn new(
+        browser_pool: Arc<BrowserPool>,
+        readiness: PageReadiness,
+        recorder: Option<Arc<WarcRecorder>>,
+    ) -> Self {
+        ChromeFetcher {
+            browser_pool,
+            readiness,
+            recorder,
+        }
+    }
+
+    fn fetch_pdf(&self, url: &str) -> anyhow::Result<()> {
+        match reqwest::blocking::get(url) {
+            Ok(res) => {
+                debug!("fetching pdf at {}", url);
+                // through the proxy, fetching the pdf is enough for it to be recorded
+                match &self.recorder {
+                    Some(r) => r.record_blocking_response(res)?,
+                    None => {
+                        let _r = res.text();
+                    }
+                }
+                Ok(())
+            }
+            Err(e) => {
+                warn!("error downloading pdf err: {}", e);
+                Err(anyhow!(e))
+            }
+        }
+    }
+
+    // chrome fails on pages that are pdfs without the extension, we check what it was
+    fn fetch_unbrowsable(&self, url: &str, browse_err: anyhow::Error) -> anyhow::Result<()> {
+        let c = reqwest::blocking::Client::new();
+        let head = match c.head(url).send() {
+            Ok(h) => h,
+            Err(e) => {
+                warn!(
+                    "error browsing for {} with tab err {}, head err {}",
+                    url, browse_err, e
+                );
+                return Err(e.into());
+            }
+        };
+
+        let is_pdf = match head.headers().get("Content-Type") {
+            Some(content_type) => content_type
+                .to_str()
+                .unwrap_or_default()
+                .contains("application/pdf"),
+            None => false,
+        };
+        if !is_pdf {
+            warn!("error browsing for {} with tab err {}", url, browse_err);
+            return Err(anyhow!("not a pdf"));
+        }
+        self.fetch_pdf(url).context("could not fetch pdf")
+    }
+}
+
+impl PageFetcher for ChromeFetcher {
+    fn navigate(&self, url: &str, screenshot: bool) -> anyhow::Result<Box<dyn FetchedPage>> {
+        // headless chrome can't handle pdfs, so we make a direct request for it
+        if url.ends_with(".pdf") {
+            self.fetch_pdf(url)?;
+            return Ok(Box::new(DownloadedPage));
+        }
+
+        let mut lease = self.browser_pool.checkout()?;
+        let browser = lease.browser();
+        match browser.browse(url, screenshot, &self.readiness, self.recorder.as_ref()) {
+            Ok(page) => Ok(Box::new(ChromePage {
+                tab: page.tab,
+                browser,
+                response: page.response,
+                screenshot: page.screenshot,
+                _lease: lease,
+            })),
+            Err(e) => {
+                // a crashed browser is recycled by the pool once released
+                if !browser.is_healthy() {
+                    lease.mark_unhealthy();
+                }
+                drop(lease);
+                self.fetch_unbrowsable(url, e)?;
+                Ok(Box::new(DownloadedPage))
+            }
+        }
+    }
+}
+
+impl FetchedPage for ChromePage {
+    fn response(&self) -> Option<PageResponse> {
+        self.response.clone()
+    }
+
+    fn links(&self) -> Vec<String> {
+        self.browser.get_links(&self.tab)
+    }
+
+    fn title(&self) -> anyhow::Result<String> {
+        self.tab.get_title()
+    }
+
+    fn screenshot(&self) -> Option<Vec<u8>> {
+        self.screenshot.clone()
+    }
+}
+
+impl Drop for ChromePage {
+    fn drop(&mut self) {
+        self.browser.close_tab(&self.tab);
+    }
+}
+
+impl FetchedPage for DownloadedPage {
+    fn response(&self) -> Option<PageResponse> {
+        None
+    }
+
+    fn links(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn title(&self) -> anyhow::Result<String> {
+        Err(anyhow!("downloaded files have no title"))
+    }
+
+    fn screenshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
@@ -5,12 +5,42 @@ extern crate derive_builder;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod ans104;
 pub mod archiver;
 pub mod browser_controller;
+pub mod browser_pool;
+pub mod budget;
+pub mod capture;
+pub mod cdxj;
+pub mod checkpoint;
+pub mod config;
 pub mod contract;
 pub mod crawler;
+pub mod fetcher;
+pub mod manifest;
+#[cfg(test)]
+mod mock_bundlr;
+#[cfg(test)]
+mod mock_contract;
+#[cfg(test)]
+mod mock_s3;
+#[cfg(test)]
+mod mock_site;
+pub mod path_manifest;
+pub mod politeness;
+pub mod readiness;
+pub mod robots;
 pub mod runner;
+pub mod s3;
+pub mod scope;
+pub mod sitemap;
+pub mod spending;
+pub mod storage;
+pub mod submissions;
 pub mod types;
+pub mod upload_session;
 pub mod uploader;
 pub mod utils;
+pub mod wacz;
+pub mod warc;
 pub mod warc_writer;
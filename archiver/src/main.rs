@@ -8,9 +8,15 @@ use std::{
 use anyhow::{anyhow, Context};
 use archiver::{
     archiver::{Archiver, ArchiverOptionsBuilder},
+    capture::CaptureBackend,
+    config::{ArchiverConfig, Environment, PartialConfig},
     contract::Contract,
-    types::BundlrBalance,
-    utils::{BUNDLR_URL, CONTRACT_ADDRESS},
+    readiness::ReadinessStrategy,
+    robots::RobotsPolicy,
+    s3::{S3Config, DEFAULT_S3_ENDPOINT, DEFAULT_S3_REGION},
+    scope::ScopeRule,
+    storage::{StorageBackend, StorageTarget},
+    uploader::Uploader,
 };
 use arloader::Arweave;
 use clap::Parser;
@@ -33,15 +39,86 @@ struct Args {
     /// Whether to upload the crawls or not
     #[arg(short = 'u', long, default_value_t = true)]
     with_upload: bool,
+    /// Package each crawl as a single WACZ file and upload it instead of the WARC files
+    #[arg(long)]
+    wacz: bool,
     /// Minimum time in seconds to wait after a tab navigates to a page
     #[arg(long, default_value_t = 5)]
     min_wait_after_navigation: u64,
     /// Maximum time in seconds to wait after a tab navigates to a page
     #[arg(long, default_value_t = 7)]
     max_wait_after_navigation: u64,
+    /// How a page is deemed ready to be captured: fixed, network-idle or dom-stable.
+    /// The min and max waits after navigation bound every strategy
+    #[arg(long, default_value_t = ReadinessStrategy::NetworkIdle)]
+    readiness_strategy: ReadinessStrategy,
+    /// How pages are written to WARC files: pywb (recording proxy) or native (in-process)
+    #[arg(long, default_value_t = CaptureBackend::Pywb)]
+    capture_backend: CaptureBackend,
+    /// Whether robots.txt is respected: ignore, obey or obey-but-archive-seed.
+    /// The last one always archives the requested urls but obeys for the links found on them
+    #[arg(long, default_value_t = RobotsPolicy::Ignore)]
+    robots_policy: RobotsPolicy,
+    /// Also crawl the urls listed in the sitemaps of the requested sites
+    #[arg(long)]
+    seed_from_sitemaps: bool,
+    /// Maximum number of urls taken from sitemaps per crawl
+    #[arg(long, default_value_t = 1000)]
+    max_sitemap_urls: usize,
+    /// Include or exclude urls from crawls, as <include|exclude>:<glob|regex|surt>:<pattern>.
+    /// Can be repeated, the first matching rule decides
+    #[arg(long = "scope-rule")]
+    scope_rules: Vec<ScopeRule>,
+    /// Stop a crawl, keeping what was archived so far, after visiting that many pages
+    #[arg(long)]
+    max_pages: Option<usize>,
+    /// Stop a crawl once its WARC files reach that many bytes
+    #[arg(long)]
+    max_bytes: Option<u64>,
+    /// Stop a crawl after that many seconds
+    #[arg(long)]
+    max_duration: Option<u64>,
     /// Maximum time the browser will wait for an event before timing out
     #[arg(long, default_value_t = 45)]
     browser_timeout: u64,
+    /// Number of Chrome instances kept alive and shared by all crawls
+    #[arg(long, default_value_t = 10)]
+    browser_pool_size: usize,
+    /// Maximum number of pages browsed concurrently by a single Chrome instance
+    #[arg(long, default_value_t = 3)]
+    tabs_per_browser: usize,
+    /// Number of pages a Chrome instance browses before it is relaunched
+    #[arg(long, default_value_t = 50)]
+    max_pages_per_browser: usize,
+    /// Maximum number of pages of the same host browsed at once, across all crawls
+    #[arg(long, default_value_t = 2)]
+    max_tabs_per_host: usize,
+    /// Minimum time in milliseconds between two visits of the same host
+    #[arg(long, default_value_t = 1000)]
+    min_host_delay: u64,
+    /// Keep the same pace for hosts answering 429 or 503 instead of backing off
+    #[arg(long)]
+    no_adaptive_backoff: bool,
+    /// Start interrupted crawls over instead of resuming them from their saved state
+    #[arg(long)]
+    no_resume: bool,
+    /// Refuse to upload a crawl estimated to cost more than that many winston
+    #[arg(long, env = "MAX_CRAWL_COST")]
+    max_crawl_cost: Option<u128>,
+    /// Stop uploading once that many winston were spent in the UTC day
+    #[arg(long, env = "MAX_DAILY_COST")]
+    max_daily_cost: Option<u128>,
+    /// Also store every crawl in s3://<bucket>/<prefix> or in a local directory.
+    /// Can be repeated
+    #[arg(long = "store")]
+    storage: Vec<StorageTarget>,
+    /// Endpoint of the s3 compatible storage, such as http://localhost:9000 for minio
+    #[arg(long, env = "S3_ENDPOINT", default_value = DEFAULT_S3_ENDPOINT)]
+    s3_endpoint: String,
+    /// Region of the s3 buckets. Their credentials are read from AWS_ACCESS_KEY_ID and
+    /// AWS_SECRET_ACCESS_KEY
+    #[arg(long, env = "S3_REGION", default_value = DEFAULT_S3_REGION)]
+    s3_region: String,
     /// Maximum time in seconds to wait after a tab navigates to a page
     #[arg(short = 'd', long)]
     writer_directory: Option<PathBuf>,
@@ -51,6 +128,33 @@ struct Args {
     /// Maximum time in seconds to wait after a tab navigates to a page
     #[arg(short = 'b', long)]
     balance: bool,
+    /// JSON file with any of environment, bundlr_url, gateway_url, contract_address,
+    /// contract_network, app_name, app_version and verify_url. Flags and environment
+    /// variables win over it
+    #[arg(long, env = "ARCHIVER_CONFIG")]
+    config: Option<PathBuf>,
+    /// Development or production, picks the default contract address and app name
+    #[arg(long, env = "ENVIRONMENT")]
+    environment: Option<Environment>,
+    /// Bundlr node the archives are uploaded to
+    #[arg(long, env = "BUNDLR_URL")]
+    bundlr_url: Option<String>,
+    /// Arweave gateway used to sign and interact with the contract
+    #[arg(long, env = "ARWEAVE_GATEWAY")]
+    gateway_url: Option<String>,
+    /// Address of the contract the archive requests are read from and submitted to
+    #[arg(long, env = "CONTRACT_ADDRESS")]
+    contract_address: Option<String>,
+    /// Warp network of the contract: mainnet or testnet
+    #[arg(long, env = "CONTRACT_NETWORK")]
+    contract_network: Option<String>,
+    /// App-Name tag of the uploads
+    #[arg(long, env = "APP_NAME")]
+    app_name: Option<String>,
+    /// Node or gateway the uploads are fetched back from to be verified before being
+    /// submitted, the Bundlr node by default
+    #[arg(long, env = "VERIFY_URL")]
+    verify_url: Option<String>,
 }
 
 #[tokio::main]
@@ -71,8 +175,24 @@ async fn main() -> anyhow::Result<()> {
         _ => {}
     }
 
+    let config = ArchiverConfig::resolve(
+        args.config.as_ref(),
+        PartialConfig {
+            environment: args.environment,
+            bundlr_url: args.bundlr_url.clone(),
+            gateway_url: args.gateway_url.clone(),
+            contract_address: args.contract_address.clone(),
+            contract_network: args.contract_network.clone(),
+            app_name: args.app_name.clone(),
+            app_version: None,
+            verify_url: args.verify_url.clone(),
+        },
+    )
+    .context("invalid configuration")?;
+    debug!("configuration {:#?}", config);
+
     let path = PathBuf::from(".secret/wallet.json");
-    let arweave = Arweave::from_keypair_path(path.clone(), Url::from_str("https://arweave.net")?)
+    let arweave = Arweave::from_keypair_path(path.clone(), Url::from_str(&config.gateway_url)?)
         .await
         .context(format!(
             "could not open arweave wallet from path {:?}",
@@ -84,40 +204,30 @@ async fn main() -> anyhow::Result<()> {
     debug!("arweave wallet {} loaded", wallet_address);
 
     // check if we have funds in bundlr
-    let res = match reqwest::get(format!(
-        "{}/account/balance/arweave?address={}",
-        BUNDLR_URL, &wallet_address
-    ))
-    .await
-    {
-        Ok(res) => res,
-        Err(e) => return Err(anyhow!(e.to_string())),
-    };
-    let res = res.json::<BundlrBalance>().await?;
+    let uploader = Uploader::new(path.clone(), "arweave", &config)
+        .await
+        .context("could not instantiate uploader")?;
+    let balance = uploader.balance_winston().await.context(format!(
+        "could not fetch bundlr balance of {}",
+        &wallet_address
+    ))?;
 
-    if res.balance == "0" {
+    if balance == 0 {
         return Err(anyhow!("no funds in bundlr address {} ", &wallet_address));
     }
     if args.balance {
-        let b = match res.balance.parse::<f64>() {
-            Ok(num) => num,
-            Err(e) => panic!("couldn't parse balance {}", e),
-        };
-
         println!(
             "balance: {} winston or {:.12} AR for address {}",
-            res.balance,
-            b.div(1000000000000.0),
+            balance,
+            (balance as f64).div(1000000000000.0),
             &wallet_address,
         );
         return Ok(());
     }
 
-    let environment = "mainnet";
-    let contract = Contract::new(&CONTRACT_ADDRESS, environment, arweave).context(format!(
-        "could not initiate contract with address {} on env {}",
-        CONTRACT_ADDRESS.as_str(),
-        environment
+    let contract = Contract::new(&config, arweave).context(format!(
+        "could not initiate contract with address {} on {}",
+        config.contract_address, config.contract_network
     ))?;
 
     let uploaders = contract
@@ -135,6 +245,18 @@ async fn main() -> anyhow::Result<()> {
 
     debug!("starting uploader with args {:#?}", args.clone());
 
+    let s3 = S3Config {
+        endpoint: args.s3_endpoint,
+        region: args.s3_region,
+        access_key_id: std::env::var("AWS_ACCESS_KEY_ID").unwrap_or_default(),
+        secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY").unwrap_or_default(),
+    };
+    let storage = args
+        .storage
+        .iter()
+        .map(|target| target.backend(&s3).map(Arc::from))
+        .collect::<anyhow::Result<Vec<Arc<dyn StorageBackend>>>>()?;
+
     let archive_options = ArchiverOptionsBuilder::default_builder()
         .writer_dir(args.writer_directory)
         .concurrent_crawlers(args.concurrent_crawlers)
@@ -142,9 +264,30 @@ async fn main() -> anyhow::Result<()> {
         .fetch_frequency(args.fetching_frequency)
         .url_retries(args.retries)
         .with_upload(args.with_upload)
+        .with_wacz(args.wacz)
         .browser_timeout(args.browser_timeout)
         .min_wait_after_navigation(args.min_wait_after_navigation)
         .max_wait_after_navigation(args.max_wait_after_navigation)
+        .readiness_strategy(args.readiness_strategy)
+        .capture_backend(args.capture_backend)
+        .robots_policy(args.robots_policy)
+        .seed_from_sitemaps(args.seed_from_sitemaps)
+        .max_sitemap_urls(args.max_sitemap_urls)
+        .scope_rules(args.scope_rules)
+        .max_pages(args.max_pages)
+        .max_bytes(args.max_bytes)
+        .max_duration(args.max_duration)
+        .browser_pool_size(args.browser_pool_size)
+        .tabs_per_browser(args.tabs_per_browser)
+        .max_pages_per_browser(args.max_pages_per_browser)
+        .max_tabs_per_host(args.max_tabs_per_host)
+        .min_host_delay(args.min_host_delay)
+        .adaptive_backoff(!args.no_adaptive_backoff)
+        .resume_crawls(!args.no_resume)
+        .max_crawl_cost(args.max_crawl_cost)
+        .max_daily_cost(args.max_daily_cost)
+        .storage(storage)
+        .config(config)
         .build()?;
 
     let mut archiver = Archiver::new(archive_options);
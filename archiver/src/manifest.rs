@@ -0,0 +1,210 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{budget::BudgetLimit, storage::StorageLocation};
+
+pub const MANIFEST_VERSION: u8 = 1;
+pub const MANIFEST_SUFFIX: &str = "archive.json";
+pub const PAGES_SUFFIX: &str = "pages.jsonl";
+pub const COST_SUFFIX: &str = "cost.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFileKind {
+    Warc,
+    Index,
+    Screenshot,
+    Wacz,
+    // pages.jsonl listing the visited pages
+    Pages,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveFile {
+    // file name, relative to the manifest
+    pub name: String,
+    pub kind: ArchiveFileKind,
+    pub size: u64,
+    // hex encoded
+    pub sha256: String,
+}
+
+// Sidecar manifest describing a crawl and the files it produced, written
+// next to the WARC files so we never have to guess from file names
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveManifest {
+    pub version: u8,
+    // domain the archive files are named after
+    pub url: String,
+    pub original_url: String,
+    // every url the crawl started from, manifests written before multi-seed crawls have none
+    #[serde(default)]
+    pub seeds: Vec<String>,
+    // unix timestamp in seconds of the crawl
+    pub timestamp: i64,
+    pub depth: u8,
+    pub crawl_type: String,
+    pub title: String,
+    pub files: Vec<ArchiveFile>,
+    // the budget that cut the crawl short, the archive is then partial
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_exceeded: Option<BudgetLimit>,
+    // every backend the crawl was stored with, only known to the local copy of the manifest
+    // as the stored ones are written before
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub storage: Vec<StorageLocation>,
+}
+
+impl ArchiveManifest {
+    pub fn new(
+        url: &str,
+        original_url: &str,
+        timestamp: i64,
+        depth: u8,
+        crawl_type: &str,
+        title: &str,
+    ) -> Self {
+        ArchiveManifest {
+            version: MANIFEST_VERSION,
+            url: url.into(),
+            original_url: original_url.into(),
+            seeds: vec![original_url.into()],
+            timestamp,
+            depth,
+            crawl_type: crawl_type.into(),
+            title: title.into(),
+            files: vec![],
+            budget_exceeded: None,
+            storage: vec![],
+        }
+    }
+
+    // archiver_<ts>_<url>_<depth>.warc.gz -> archiver_<ts>_<url>_<depth>.archive.json
+    pub fn sidecar_path(warc: &Path) -> PathBuf {
+        sidecar(warc, MANIFEST_SUFFIX)
+    }
+
+    pub fn add_file(&mut self, path: &PathBuf, kind: ArchiveFileKind) -> anyhow::Result<()> {
+        let name = match path.file_name() {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => return Err(anyhow!("invalid file path {:?}", path)),
+        };
+        let mut file = File::open(path).context(format!("could not open {:?}", path))?;
+        let mut hasher = Sha256::new();
+        let size =
+            io::copy(&mut file, &mut hasher).context(format!("could not hash {:?}", path))?;
+
+        self.files.push(ArchiveFile {
+            name,
+            kind,
+            size,
+            sha256: hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        });
+        Ok(())
+    }
+
+    pub fn files_of_kind(&self, kind: ArchiveFileKind) -> Vec<&ArchiveFile> {
+        self.files.iter().filter(|f| f.kind == kind).collect()
+    }
+
+    pub fn write(&self, path: &PathBuf) -> anyhow::Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        fs::write(path, data).context(format!("could not write manifest {:?}", path))?;
+        Ok(())
+    }
+
+    pub fn load(path: &PathBuf) -> anyhow::Result<Self> {
+        let data = fs::read(path).context(format!("could not read manifest {:?}", path))?;
+        let manifest: ArchiveManifest =
+            serde_json::from_slice(&data).context(format!("invalid manifest {:?}", path))?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(anyhow!(
+                "unsupported manifest version {} in {:?}",
+                manifest.version,
+                path
+            ));
+        }
+        Ok(manifest)
+    }
+}
+
+fn sidecar(warc: &Path, suffix: &str) -> PathBuf {
+    let name = warc
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = name.trim_end_matches(".gz").trim_end_matches(".warc");
+    warc.with_file_name(format!("{}.{}", stem, suffix))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::create_random_tmp_folder;
+
+    use super::*;
+
+    #[test]
+    fn writes_and_loads_manifests() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut warc = dir.clone();
+        warc.push("archiver_20230125160157_my_domain.com_2.warc.gz");
+        fs::write(&warc, b"warc").unwrap();
+
+        let mut manifest = ArchiveManifest::new(
+            "my_domain.com",
+            "https://my_domain.com/",
+            1674662517,
+            2,
+            "DomainOnly",
+            "title",
+        );
+        manifest.add_file(&warc, ArchiveFileKind::Warc).unwrap();
+
+        let path = ArchiveManifest::sidecar_path(&warc);
+        assert_eq!(
+            path.file_name().unwrap(),
+            "archiver_20230125160157_my_domain.com_2.archive.json"
+        );
+        manifest.write(&path).unwrap();
+
+        let loaded = ArchiveManifest::load(&path).unwrap();
+        assert_eq!(loaded, manifest);
+        let warcs = loaded.files_of_kind(ArchiveFileKind::Warc);
+        assert_eq!(warcs[0].size, 4);
+        assert_eq!(
+            warcs[0].sha256,
+            "ba9354696d17bcfb57e7e2e6f0b89044f840a9978ee7e579356ea731478d60f4"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn loads_manifests_without_seeds() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("archiver_20230125160157_my_domain.com_2.archive.json");
+        fs::write(
+            &path,
+            r#"{"version":1,"url":"my_domain.com","original_url":"https://my_domain.com/","timestamp":1674662517,"depth":2,"crawl_type":"DomainOnly","title":"title","files":[]}"#,
+        )
+        .unwrap();
+
+        let loaded = ArchiveManifest::load(&path).unwrap();
+        assert!(loaded.seeds.is_empty());
+        assert_eq!(loaded.original_url, "https://my_domain.com/");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
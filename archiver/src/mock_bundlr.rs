@@ -0,0 +1,584 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::utils::get_unix_timestamp;
+
+// throwaway wallet signing the data items of the tests, it holds nothing
+pub const TEST_WALLET: &str = "tests/fixtures/test_wallet.json";
+
+// ANS-104 arweave signatures are 512 bytes, right after the 2 bytes of the signature type
+const SIGNATURE_LENGTH: usize = 512;
+
+// Endpoints of the node a failure can be injected in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    // POST /tx/arweave
+    Upload,
+    // GET /chunks/arweave/-1/-1
+    UploadId,
+    // POST /chunks/arweave/<upload id>/<offset>
+    Chunk,
+    // POST /chunks/arweave/<upload id>/-1
+    Finalize,
+    // GET /chunks/arweave/<upload id>/-2
+    Received,
+    // GET /account/balance/arweave
+    Balance,
+    // GET /price/arweave/<bytes>
+    Price,
+    // GET /<id>, the data of an item
+    Data,
+    // POST /graphql, the tags of items
+    Graphql,
+}
+
+#[derive(Default)]
+struct NodeState {
+    balance: u128,
+    // winston charged per byte of a data item
+    price_per_byte: u128,
+    min_chunk: usize,
+    max_chunk: usize,
+    // data items by id, the items nested in bundles are not served
+    items: HashMap<String, Vec<u8>>,
+    // chunks of the ongoing uploads by offset
+    uploads: HashMap<String, BTreeMap<usize, Vec<u8>>>,
+    // status answered and number of requests left to fail
+    failures: HashMap<Endpoint, (u16, usize)>,
+    // number of requests left to fail by chunk offset
+    chunk_failures: HashMap<usize, usize>,
+    // serve the data of the items with their last byte changed
+    corrupt: bool,
+    requests: Vec<(Endpoint, String)>,
+}
+
+// Stand-in Bundlr node on 127.0.0.1, keeping the uploaded data items in memory.
+// It speaks the subset of the api the uploader uses and can be told to fail
+pub struct MockBundlr {
+    url: String,
+    state: Arc<Mutex<NodeState>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockBundlr {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock bundlr node");
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(NodeState {
+            balance: 1_000_000_000_000,
+            price_per_byte: 1,
+            min_chunk: 500_000,
+            max_chunk: 95_000_000,
+            ..NodeState::default()
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let state = state.clone();
+                            thread::spawn(move || {
+                                if let Err(e) = serve(stream, &state) {
+                                    warn!("mock bundlr node error {}", e);
+                                }
+                            });
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(5)),
+                    }
+                }
+            })
+        };
+
+        MockBundlr {
+            url,
+            state,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    // in winston
+    pub fn set_balance(&self, balance: u128) {
+        self.state.lock().unwrap().balance = balance;
+    }
+
+    // in winston, uploads are charged the price of their size
+    pub fn set_price_per_byte(&self, price: u128) {
+        self.state.lock().unwrap().price_per_byte = price;
+    }
+
+    pub fn balance(&self) -> u128 {
+        self.state.lock().unwrap().balance
+    }
+
+    // chunk sizes announced with the upload id
+    pub fn set_chunk_range(&self, min: usize, max: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.min_chunk = min;
+        state.max_chunk = max;
+    }
+
+    // the next requests to the endpoint are answered with the status
+    pub fn fail(&self, endpoint: Endpoint, status: u16, times: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .insert(endpoint, (status, times));
+    }
+
+    // the next chunks sent at the offset are refused
+    pub fn fail_chunk(&self, offset: usize, times: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .chunk_failures
+            .insert(offset, times);
+    }
+
+    // the data served for the items no longer is what was uploaded
+    pub fn corrupt(&self, corrupt: bool) {
+        self.state.lock().unwrap().corrupt = corrupt;
+    }
+
+    // the data item uploaded with the id
+    pub fn item(&self, id: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().items.get(id).cloned()
+    }
+
+    // the data item nested in one of the uploaded bundles, as the node only serves
+    // the bundles it is only read here
+    pub fn nested_item(&self, id: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state.items.values().find_map(|item| {
+            let nested = unbundle(&item[data_offset(item).ok()?..])?;
+            nested
+                .into_iter()
+                .find(|(nested_id, _)| nested_id == id)
+                .map(|(_, item)| item)
+        })
+    }
+
+    // number of data items uploaded, the nested ones aside
+    pub fn item_count(&self) -> usize {
+        self.state.lock().unwrap().items.len()
+    }
+
+    // number of requests received by the endpoint, failed ones included
+    pub fn requests(&self, endpoint: Endpoint) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|(e, _)| *e == endpoint)
+            .count()
+    }
+}
+
+impl Drop for MockBundlr {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// id of a data item, the base64url sha256 of its signature
+pub fn data_item_id(item: &[u8]) -> anyhow::Result<String> {
+    if item.len() < 2 + SIGNATURE_LENGTH {
+        return Err(anyhow::anyhow!(
+            "data item of {} bytes is too short",
+            item.len()
+        ));
+    }
+    if u16::from_le_bytes([item[0], item[1]]) != 1 {
+        return Err(anyhow::anyhow!("only arweave signatures are supported"));
+    }
+    let signature = &item[2..2 + SIGNATURE_LENGTH];
+    Ok(URL_SAFE_NO_PAD.encode(Sha256::digest(signature)))
+}
+
+// offset of the data of a data item, past its signature, owner, target, anchor and tags
+pub fn data_offset(item: &[u8]) -> anyhow::Result<usize> {
+    data_item_id(item)?;
+    let mut offset = 2 + SIGNATURE_LENGTH + 512;
+    for _ in 0..2 {
+        // target then anchor, 32 bytes when present
+        match item.get(offset) {
+            Some(0) => offset += 1,
+            Some(1) => offset += 33,
+            _ => return Err(anyhow::anyhow!("invalid data item")),
+        }
+    }
+    let tags_len = item
+        .get(offset + 8..offset + 16)
+        .ok_or_else(|| anyhow::anyhow!("invalid data item"))?;
+    offset += 16 + u64::from_le_bytes(tags_len.try_into()?) as usize;
+    match offset <= item.len() {
+        true => Ok(offset),
+        false => Err(anyhow::anyhow!("invalid data item")),
+    }
+}
+
+// the tags of a data item, decoded from their avro encoding
+pub fn item_tags(item: &[u8]) -> Option<Vec<(String, String)>> {
+    let mut offset = 2 + SIGNATURE_LENGTH + 512;
+    for _ in 0..2 {
+        match item.get(offset)? {
+            0 => offset += 1,
+            1 => offset += 33,
+            _ => return None,
+        }
+    }
+    let count = u64::from_le_bytes(item.get(offset..offset + 8)?.try_into().ok()?);
+    let mut tags = item.get(offset + 16..)?;
+    let read_long = |tags: &mut &[u8]| {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = tags.split_first()?;
+            *tags = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        // zigzag
+        Some((value >> 1) as i64 ^ -((value & 1) as i64))
+    };
+    let mut decoded = vec![];
+    while (decoded.len() as u64) < count {
+        let block = read_long(&mut tags)?;
+        if block == 0 {
+            break;
+        }
+        for _ in 0..block.unsigned_abs() {
+            let mut field = || {
+                let len = read_long(&mut tags)? as usize;
+                let (bytes, rest) = (tags.get(..len)?, tags.get(len..)?);
+                tags = rest;
+                Some(String::from_utf8_lossy(bytes).to_string())
+            };
+            let name = field()?;
+            let value = field()?;
+            decoded.push((name, value));
+        }
+    }
+    Some(decoded)
+}
+
+// the data items nested in the data of a bundle by id, none if the data is not a bundle
+pub fn unbundle(data: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    let u256 = |bytes: &[u8]| match bytes[8..32].iter().all(|b| *b == 0) {
+        true => Some(u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize),
+        false => None,
+    };
+    let count = u256(data.get(..32)?)?;
+    let mut offset = 32 + 64 * count;
+    let entries = data.get(32..offset)?;
+    let mut items = vec![];
+    for entry in entries.chunks(64) {
+        let size = u256(&entry[..32])?;
+        let item = data.get(offset..offset + size)?;
+        let id = data_item_id(item).ok()?;
+        if id != URL_SAFE_NO_PAD.encode(&entry[32..]) {
+            return None;
+        }
+        items.push((id, item.to_vec()));
+        offset += size;
+    }
+    match offset == data.len() {
+        true => Some(items),
+        false => None,
+    }
+}
+
+fn serve(stream: TcpStream, state: &Mutex<NodeState>) -> anyhow::Result<()> {
+    let request = read_request(&stream)?;
+    let (status, response) = handle(state, &request.method, &request.target, request.body);
+    respond(stream, status, &response)
+}
+
+// A request received by a stand-in node, header names in lower case
+pub struct MockRequest {
+    pub method: String,
+    pub target: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub fn read_request(stream: &TcpStream) -> anyhow::Result<MockRequest> {
+    stream.set_nonblocking(false)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    let content_length = match headers.get("content-length") {
+        Some(length) => length.parse()?,
+        None => 0,
+    };
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(MockRequest {
+        method,
+        target,
+        headers,
+        body,
+    })
+}
+
+fn handle(state: &Mutex<NodeState>, method: &str, target: &str, body: Vec<u8>) -> (u16, Vec<u8>) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments = path.trim_matches('/').split('/').collect::<Vec<&str>>();
+    let endpoint = match (method, segments.as_slice()) {
+        ("POST", ["tx", "arweave"]) => Endpoint::Upload,
+        ("GET", ["chunks", "arweave", "-1", "-1"]) => Endpoint::UploadId,
+        ("POST", ["chunks", "arweave", _, "-1"]) => Endpoint::Finalize,
+        ("GET", ["chunks", "arweave", _, "-2"]) => Endpoint::Received,
+        ("POST", ["chunks", "arweave", _, _]) => Endpoint::Chunk,
+        ("GET", ["account", "balance", "arweave"]) => Endpoint::Balance,
+        ("GET", ["price", "arweave", _]) => Endpoint::Price,
+        ("POST", ["graphql"]) => Endpoint::Graphql,
+        ("GET", [_]) => Endpoint::Data,
+        _ => return (404, b"not found".to_vec()),
+    };
+
+    let mut state = state.lock().unwrap();
+    state.requests.push((endpoint, target.into()));
+    if let Some((status, times)) = state.failures.get_mut(&endpoint) {
+        if *times > 0 {
+            *times -= 1;
+            return (*status, b"injected failure".to_vec());
+        }
+    }
+
+    let (status, response) = match endpoint {
+        Endpoint::Data => return item_data(&state, segments[0]),
+        Endpoint::Upload => store_item(&mut state, body),
+        Endpoint::UploadId => {
+            let id = URL_SAFE_NO_PAD.encode(Sha256::digest(
+                format!(
+                    "{}-{}",
+                    state.uploads.len(),
+                    get_unix_timestamp().as_nanos()
+                )
+                .as_bytes(),
+            ));
+            state.uploads.insert(id.clone(), BTreeMap::new());
+            (
+                200,
+                serde_json::json!({"id": id, "min": state.min_chunk, "max": state.max_chunk})
+                    .to_string(),
+            )
+        }
+        Endpoint::Chunk => {
+            let offset = match segments[3].parse::<usize>() {
+                Ok(o) => o,
+                Err(_) => return (400, "invalid offset".into()),
+            };
+            if body.len() > state.max_chunk {
+                return (400, "chunk too big".into());
+            }
+            if let Some(times) = state.chunk_failures.get_mut(&offset) {
+                if *times > 0 {
+                    *times -= 1;
+                    return (503, "injected failure".into());
+                }
+            }
+            match state.uploads.get_mut(segments[2]) {
+                Some(chunks) => {
+                    chunks.insert(offset, body);
+                    (200, "OK".into())
+                }
+                None => (404, "unknown upload id".into()),
+            }
+        }
+        Endpoint::Finalize => {
+            let chunks = match state.uploads.remove(segments[2]) {
+                Some(c) => c,
+                None => return (404, "unknown upload id".into()),
+            };
+            let mut item = vec![];
+            for (offset, chunk) in chunks {
+                if offset != item.len() {
+                    return (
+                        400,
+                        format!("missing chunk at offset {}", item.len()).into_bytes(),
+                    );
+                }
+                item.extend(chunk);
+            }
+            store_item(&mut state, item)
+        }
+        Endpoint::Received => match state.uploads.get(segments[2]) {
+            Some(chunks) => (
+                200,
+                serde_json::json!(chunks
+                    .iter()
+                    .map(|(offset, chunk)| (offset.to_string(), chunk.len()))
+                    .collect::<Vec<(String, usize)>>())
+                .to_string(),
+            ),
+            None => (404, "unknown upload id".into()),
+        },
+        Endpoint::Balance => match query.strip_prefix("address=") {
+            Some(_) => (
+                200,
+                serde_json::json!({"balance": state.balance.to_string()}).to_string(),
+            ),
+            None => (400, "missing address".into()),
+        },
+        Endpoint::Price => match segments[2].parse::<u128>() {
+            Ok(bytes) => (200, (bytes * state.price_per_byte).to_string()),
+            Err(_) => (400, "invalid size".into()),
+        },
+        Endpoint::Graphql => {
+            // only answers transactions(ids: ["<id>", ...]) queries
+            let query = serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|q| q["query"].as_str().map(String::from))
+                .unwrap_or_default();
+            let ids = match query
+                .split_once("ids: [")
+                .and_then(|(_, rest)| rest.split_once(']'))
+            {
+                Some((ids, _)) => ids
+                    .split(',')
+                    .map(|id| id.trim().trim_matches('"').to_string())
+                    .collect::<Vec<String>>(),
+                None => return (400, "unsupported query".into()),
+            };
+            let edges = ids
+                .iter()
+                .filter_map(|id| {
+                    let item = state.items.get(id)?;
+                    let tags = item_tags(item)?
+                        .into_iter()
+                        .map(|(name, value)| serde_json::json!({"name": name, "value": value}))
+                        .collect::<Vec<serde_json::Value>>();
+                    Some(serde_json::json!({"node": {"id": id, "tags": tags}}))
+                })
+                .collect::<Vec<serde_json::Value>>();
+            (
+                200,
+                serde_json::json!({"data": {"transactions": {"edges": edges}}}).to_string(),
+            )
+        }
+    };
+    (status, response.into_bytes())
+}
+
+// the data of an uploaded item, past its headers
+fn item_data(state: &NodeState, id: &str) -> (u16, Vec<u8>) {
+    let item = match state.items.get(id) {
+        Some(item) => item,
+        None => return (404, b"not found".to_vec()),
+    };
+    let mut data = match data_offset(item) {
+        Ok(offset) => item[offset..].to_vec(),
+        Err(e) => return (500, e.to_string().into_bytes()),
+    };
+    if state.corrupt {
+        if let Some(last) = data.last_mut() {
+            *last = last.wrapping_add(1);
+        }
+    }
+    (200, data)
+}
+
+fn store_item(state: &mut NodeState, item: Vec<u8>) -> (u16, String) {
+    let id = match data_item_id(&item) {
+        Ok(id) => id,
+        Err(e) => return (400, e.to_string()),
+    };
+    let winc = item.len() as u128 * state.price_per_byte;
+    if winc > state.balance {
+        return (402, "Not enough funds to send data".into());
+    }
+    state.balance -= winc;
+    state.items.insert(id.clone(), item);
+    (
+        200,
+        serde_json::json!({
+            "id": id,
+            "timestamp": get_unix_timestamp().as_millis() as u64,
+            "winc": winc.to_string(),
+        })
+        .to_string(),
+    )
+}
+
+pub fn respond(stream: TcpStream, status: u16, body: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    respond_with_headers(stream, status, &[], body)
+}
+
+pub fn respond_with_headers(
+    mut stream: TcpStream,
+    status: u16,
+    headers: &[(&str, String)],
+    body: impl AsRef<[u8]>,
+) -> anyhow::Result<()> {
+    let body = body.as_ref();
+    let content_type = match body.first() {
+        Some(b'{') | Some(b'[') => "application/json",
+        _ => "text/plain",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        status,
+        match status {
+            200 => "OK",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            _ => "Error",
+        },
+        content_type,
+        body.len(),
+        headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value))
+            .collect::<String>()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::{collections::HashSet, sync::Mutex};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use atw::state::{ArchiveRequest, ArchiveSubmission};
+
+use crate::contract::ArchiveContract;
+
+// Contract kept in memory, to run the archiver without a network nor a funded wallet
+#[derive(Debug, Default)]
+pub struct MemoryContract {
+    requests: Mutex<Vec<ArchiveRequest>>,
+    submissions: Mutex<Vec<ArchiveSubmission>>,
+    // number of submissions left to refuse
+    failing_submissions: Mutex<usize>,
+    // urls whose next submission is refused
+    refused_urls: Mutex<HashSet<String>>,
+}
+
+impl MemoryContract {
+    pub fn new(requests: Vec<ArchiveRequest>) -> Self {
+        MemoryContract {
+            requests: Mutex::new(requests),
+            ..MemoryContract::default()
+        }
+    }
+
+    pub fn requests(&self) -> Vec<ArchiveRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn submissions(&self) -> Vec<ArchiveSubmission> {
+        self.submissions.lock().unwrap().clone()
+    }
+
+    // the next submissions are refused
+    pub fn fail_submissions(&self, times: usize) {
+        *self.failing_submissions.lock().unwrap() = times;
+    }
+
+    // the next submission of url is refused
+    pub fn fail_submission_of(&self, url: &str) {
+        self.refused_urls.lock().unwrap().insert(url.into());
+    }
+}
+
+#[async_trait]
+impl ArchiveContract for MemoryContract {
+    async fn archiving_requests_for(&self, address: &str) -> anyhow::Result<Vec<ArchiveRequest>> {
+        Ok(self
+            .requests()
+            .into_iter()
+            .filter(|r| r.uploader_address == address)
+            .collect())
+    }
+
+    async fn submit_archive(&self, archive: &ArchiveSubmission) -> anyhow::Result<()> {
+        let mut failing = self.failing_submissions.lock().unwrap();
+        let refused = if self.refused_urls.lock().unwrap().remove(&archive.full_url) {
+            true
+        } else if *failing > 0 {
+            *failing -= 1;
+            true
+        } else {
+            false
+        };
+        if refused {
+            return Err(anyhow!(
+                "submission of {} refused",
+                archive.archive_request_id
+            ));
+        }
+        self.submissions.lock().unwrap().push(archive.clone());
+        Ok(())
+    }
+
+    async fn delete_archive_request(&self, archive_id: &str) -> anyhow::Result<()> {
+        let mut requests = self.requests.lock().unwrap();
+        let count = requests.len();
+        requests.retain(|r| r.id != archive_id);
+        if requests.len() == count {
+            return Err(anyhow!("no archive request with id {}", archive_id));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    mock_bundlr::{read_request, respond, respond_with_headers},
+    s3::{authorization, S3Config},
+};
+
+#[derive(Default)]
+struct StorageState {
+    // objects by /<bucket>/<key>, as requested
+    objects: HashMap<String, Vec<u8>>,
+    // parts of the multipart uploads in progress by upload id, with the path of the object
+    uploads: HashMap<String, (String, BTreeMap<usize, Vec<u8>>)>,
+    next_upload: usize,
+    // number of puts left to refuse
+    failures: usize,
+    puts: usize,
+}
+
+// Stand-in s3 compatible storage on 127.0.0.1, minio style: objects are addressed path
+// style and kept in memory. Puts are only accepted signed with the credentials of its config
+pub struct MockS3 {
+    config: S3Config,
+    state: Arc<Mutex<StorageState>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockS3 {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock s3 storage");
+        listener.set_nonblocking(true).unwrap();
+        let config = S3Config {
+            endpoint: format!("http://{}", listener.local_addr().unwrap()),
+            access_key_id: "minioadmin".into(),
+            secret_access_key: "minioadmin-secret".into(),
+            ..S3Config::default()
+        };
+        let state = Arc::new(Mutex::new(StorageState::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let state = state.clone();
+            let stop = stop.clone();
+            let config = config.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let state = state.clone();
+                            let config = config.clone();
+                            thread::spawn(move || {
+                                if let Err(e) = serve(stream, &state, &config) {
+                                    warn!("mock s3 storage error {}", e);
+                                }
+                            });
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(5)),
+                    }
+                }
+            })
+        };
+
+        MockS3 {
+            config,
+            state,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    // endpoint and credentials to reach the storage with
+    pub fn config(&self) -> S3Config {
+        self.config.clone()
+    }
+
+    // the next puts are answered with a 500
+    pub fn fail(&self, times: usize) {
+        self.state.lock().unwrap().failures = times;
+    }
+
+    pub fn object(&self, bucket: &str, key: &str) -> Option<Vec<u8>> {
+        let path = format!("/{}/{}", bucket, key);
+        self.state.lock().unwrap().objects.get(&path).cloned()
+    }
+
+    // number of puts received, parts and refused ones included
+    pub fn puts(&self) -> usize {
+        self.state.lock().unwrap().puts
+    }
+
+    // multipart uploads neither completed nor aborted
+    pub fn pending_uploads(&self) -> usize {
+        self.state.lock().unwrap().uploads.len()
+    }
+}
+
+impl Drop for MockS3 {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(stream: TcpStream, state: &Mutex<StorageState>, config: &S3Config) -> anyhow::Result<()> {
+    let request = read_request(&stream)?;
+    let header = |name: &str| request.headers.get(name).cloned().unwrap_or_default();
+    let (target, query) = request
+        .target
+        .split_once('?')
+        .unwrap_or((&request.target, ""));
+    let path = urlencoding::decode(target)?.to_string();
+    let params = query
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k, urlencoding::decode(v).unwrap_or_default().to_string()))
+        .collect::<HashMap<&str, String>>();
+
+    let mut state = state.lock().unwrap();
+    if request.method == "PUT" {
+        state.puts += 1;
+    }
+    let expected = authorization(
+        config,
+        &request.method,
+        &header("host"),
+        target,
+        query,
+        &header("x-amz-content-sha256"),
+        &header("x-amz-date"),
+    );
+    let hash = Sha256::digest(&request.body)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    if request.method == "PUT" && state.failures > 0 {
+        state.failures -= 1;
+        return respond(stream, 500, "injected failure");
+    } else if header("authorization") != expected {
+        return respond(stream, 403, "SignatureDoesNotMatch");
+    } else if header("x-amz-content-sha256") != hash {
+        return respond(stream, 400, "XAmzContentSHA256Mismatch");
+    }
+
+    let upload = params.get("uploadId").cloned().unwrap_or_default();
+    match (request.method.as_str(), params.get("partNumber")) {
+        ("POST", _) if params.contains_key("uploads") => {
+            state.next_upload += 1;
+            let id = format!("upload/{}", state.next_upload);
+            state.uploads.insert(id.clone(), (path, BTreeMap::new()));
+            let response = format!(
+                "<InitiateMultipartUploadResult><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                id
+            );
+            respond(stream, 200, response)
+        }
+        ("PUT", Some(number)) => match state.uploads.get_mut(&upload) {
+            Some((_, parts)) => {
+                let etag = format!("\"{}\"", &hash[..32]);
+                parts.insert(number.parse()?, request.body);
+                respond_with_headers(stream, 200, &[("ETag", etag)], "")
+            }
+            None => respond(stream, 404, "NoSuchUpload"),
+        },
+        ("PUT", None) => {
+            state.objects.insert(path, request.body);
+            respond(stream, 200, "")
+        }
+        // the parts are completed in the order the request lists them, by their etags
+        ("POST", None) => {
+            let (path, parts) = match state.uploads.remove(&upload) {
+                Some(upload) => upload,
+                None => return respond(stream, 404, "NoSuchUpload"),
+            };
+            let listed = String::from_utf8_lossy(&request.body).to_string();
+            let mut object = vec![];
+            for (i, (number, part)) in parts.iter().enumerate() {
+                let hash = Sha256::digest(part)
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>();
+                let expected = format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>\"{}\"</ETag></Part>",
+                    number,
+                    &hash[..32]
+                );
+                if *number != i + 1 || !listed.contains(&expected) {
+                    // answered with a 200 like s3 does once the completion started
+                    return respond(stream, 200, "<Error><Code>InvalidPart</Code></Error>");
+                }
+                object.extend(part);
+            }
+            state.objects.insert(path, object);
+            respond(stream, 200, "<CompleteMultipartUploadResult/>")
+        }
+        ("DELETE", _) => match state.uploads.remove(&upload) {
+            Some(_) => respond(stream, 204, ""),
+            None => respond(stream, 404, "NoSuchUpload"),
+        },
+        _ => respond(stream, 404, "not found"),
+    }
+}
@@ -0,0 +1,127 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::anyhow;
+
+use crate::{
+    browser_controller::PageResponse,
+    fetcher::{FetchedPage, PageFetcher},
+};
+
+// A page of a site graph, it answers with its status once it stopped failing
+#[derive(Debug, Clone)]
+pub struct SitePage {
+    pub title: String,
+    pub links: Vec<String>,
+    pub status: u16,
+    pub retry_after: Option<String>,
+    // number of navigations failing before the page loads, usize::MAX for broken pages
+    pub failures: usize,
+}
+
+impl SitePage {
+    pub fn new(links: &[&str]) -> Self {
+        SitePage {
+            title: String::new(),
+            links: links.iter().map(|l| l.to_string()).collect(),
+            status: 200,
+            retry_after: None,
+            failures: 0,
+        }
+    }
+}
+
+// In-memory site described page by page, to crawl without a browser nor a network.
+// Urls that are not part of the graph fail to load
+#[derive(Debug, Default)]
+pub struct SiteGraph {
+    pages: HashMap<String, SitePage>,
+    navigations: Mutex<HashMap<String, usize>>,
+}
+
+struct SiteGraphPage {
+    page: SitePage,
+    screenshot: bool,
+}
+
+impl SiteGraph {
+    pub fn new() -> Self {
+        SiteGraph::default()
+    }
+
+    pub fn page(self, url: &str, links: &[&str]) -> Self {
+        self.with_page(url, SitePage::new(links))
+    }
+
+    // a page that never loads
+    pub fn broken(self, url: &str) -> Self {
+        self.with_page(
+            url,
+            SitePage {
+                failures: usize::MAX,
+                ..SitePage::new(&[])
+            },
+        )
+    }
+
+    pub fn with_page(mut self, url: &str, page: SitePage) -> Self {
+        self.pages.insert(url.into(), page);
+        self
+    }
+
+    // number of times the url was navigated to
+    pub fn navigations(&self, url: &str) -> usize {
+        self.navigations
+            .lock()
+            .unwrap()
+            .get(url)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl PageFetcher for SiteGraph {
+    fn navigate(&self, url: &str, screenshot: bool) -> anyhow::Result<Box<dyn FetchedPage>> {
+        let attempt = {
+            let mut navigations = self.navigations.lock().unwrap();
+            let count = navigations.entry(url.into()).or_insert(0);
+            *count += 1;
+            *count
+        };
+        let page = match self.pages.get(url) {
+            Some(p) => p,
+            None => return Err(anyhow!("{} is not part of the site", url)),
+        };
+        if attempt <= page.failures {
+            return Err(anyhow!("could not load {}, attempt {}", url, attempt));
+        }
+        Ok(Box::new(SiteGraphPage {
+            page: page.clone(),
+            screenshot,
+        }))
+    }
+}
+
+impl FetchedPage for SiteGraphPage {
+    fn response(&self) -> Option<PageResponse> {
+        Some(PageResponse {
+            status: self.page.status,
+            retry_after: self.page.retry_after.clone(),
+        })
+    }
+
+    fn links(&self) -> Vec<String> {
+        self.page.links.clone()
+    }
+
+    fn title(&self) -> anyhow::Result<String> {
+        Ok(self.page.title.clone())
+    }
+
+    fn screenshot(&self) -> Option<Vec<u8>> {
+        match self.screenshot {
+            // the signature of a png is enough for what reads it
+            true => Some(vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]),
+            false => None,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::{ArchiveFileKind, MANIFEST_SUFFIX, PAGES_SUFFIX};
+
+pub const PATH_MANIFEST_CONTENT_TYPE: &str = "application/x.arweave-manifest+json";
+const PATH_MANIFEST_TYPE: &str = "arweave/paths";
+const PATH_MANIFEST_VERSION: &str = "0.1.0";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PathIndex {
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PathEntry {
+    pub id: String,
+}
+
+// Arweave path manifest, gateways resolve <manifest id>/<path> to the data item of the path
+// https://github.com/ArweaveTeam/arweave/wiki/Path-Manifests
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PathManifest {
+    pub manifest: String,
+    pub version: String,
+    // served for <manifest id> itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<PathIndex>,
+    pub paths: BTreeMap<String, PathEntry>,
+}
+
+impl PathManifest {
+    pub fn new() -> Self {
+        PathManifest {
+            manifest: PATH_MANIFEST_TYPE.into(),
+            version: PATH_MANIFEST_VERSION.into(),
+            index: None,
+            paths: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, path: &str, id: &str) {
+        self.paths.insert(path.into(), PathEntry { id: id.into() });
+    }
+
+    pub fn set_index(&mut self, path: &str) {
+        self.index = Some(PathIndex { path: path.into() });
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+}
+
+impl Default for PathManifest {
+    fn default() -> Self {
+        PathManifest::new()
+    }
+}
+
+// stable path of the nth file of a kind in a crawl, archive.json for the crawl's own manifest.
+// The first warc file is archive.warc.gz, the next ones archive-1.warc.gz, archive-2.warc.gz...
+pub fn crawl_path(kind: Option<&ArchiveFileKind>, n: usize) -> String {
+    let (stem, extension) = match kind {
+        Some(ArchiveFileKind::Warc) => ("archive", "warc.gz"),
+        Some(ArchiveFileKind::Wacz) => ("archive", "wacz"),
+        Some(ArchiveFileKind::Index) => ("index", "cdxj"),
+        Some(ArchiveFileKind::Screenshot) => ("screenshot", "png"),
+        Some(ArchiveFileKind::Pages) => return PAGES_SUFFIX.into(),
+        None => return MANIFEST_SUFFIX.into(),
+    };
+    match n {
+        0 => format!("{}.{}", stem, extension),
+        n => format!("{}-{}.{}", stem, n, extension),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn maps_crawl_paths() {
+        assert_eq!(
+            crawl_path(Some(&ArchiveFileKind::Warc), 0),
+            "archive.warc.gz"
+        );
+        assert_eq!(
+            crawl_path(Some(&ArchiveFileKind::Warc), 2),
+            "archive-2.warc.gz"
+        );
+        assert_eq!(crawl_path(Some(&ArchiveFileKind::Index), 0), "index.cdxj");
+        assert_eq!(crawl_path(Some(&ArchiveFileKind::Pages), 0), "pages.jsonl");
+        assert_eq!(crawl_path(None, 0), "archive.json");
+
+        let mut manifest = PathManifest::new();
+        manifest.add("archive.warc.gz", "warc-id");
+        manifest.add("archive.json", "json-id");
+        manifest.set_index("archive.json");
+        assert_eq!(
+            String::from_utf8(manifest.to_bytes().unwrap()).unwrap(),
+            r#"{"manifest":"arweave/paths","version":"0.1.0","index":{"path":"archive.json"},"paths":{"archive.json":{"id":"json-id"},"archive.warc.gz":{"id":"warc-id"}}}"#
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use tokio::{sync::Notify, time::sleep};
+
+// waiters re-check their host at least that often, in case they missed a release
+const RECHECK_INTERVAL: Duration = Duration::from_millis(200);
+// smallest backoff applied to a host that rate limits us without a Retry-After
+const MIN_BACKOFF: Duration = Duration::from_secs(2);
+// idle hosts are forgotten at most that often, long crawls meet many hosts only once
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Builder, Debug, Clone)]
+#[builder(setter(into))]
+pub struct PolitenessOptions {
+    // maximum number of pages of the same host browsed at once, across crawls
+    #[builder(default = "2")]
+    max_tabs_per_host: usize,
+    // minimum time between two visits of the same host in milliseconds
+    #[builder(default = "1000")]
+    min_host_delay: u64,
+    // slow down the hosts answering 429 or 503, following their Retry-After
+    #[builder(default = "true")]
+    adaptive: bool,
+    // longest time a host is backed off for in seconds
+    #[builder(default = "300")]
+    max_backoff: u64,
+}
+
+impl PolitenessOptions {
+    pub fn default_builder() -> PolitenessOptionsBuilder {
+        PolitenessOptionsBuilder::default()
+    }
+}
+
+#[derive(Debug)]
+struct HostState {
+    active: usize,
+    next_visit: Instant,
+    // asked for by the host's robots.txt
+    crawl_delay: Duration,
+    // grows while the host rate limits us, shrinks back once it answers again
+    backoff: Duration,
+}
+
+// Per host concurrency caps and visit spacing, shared by every crawl of the
+// process so parallel crawls of the same site add up
+#[derive(Debug)]
+pub struct HostPoliteness {
+    options: PolitenessOptions,
+    hosts: Mutex<HashMap<String, HostState>>,
+    released: Notify,
+    pruned_at: Mutex<Instant>,
+}
+
+// Right to browse one page of a host, the slot is freed when it is dropped
+pub struct HostPermit {
+    politeness: Arc<HostPoliteness>,
+    host: String,
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        self.politeness.release(&self.host);
+    }
+}
+
+impl HostPoliteness {
+    pub fn new(options: PolitenessOptions) -> Self {
+        HostPoliteness {
+            options,
+            hosts: Mutex::new(HashMap::new()),
+            released: Notify::new(),
+            pruned_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    // waits for a free slot of the url's host, then for its next visit time
+    pub async fn acquire(self: &Arc<Self>, url: &str) -> HostPermit {
+        let host = host_of(url);
+        loop {
+            if let Some(wait) = self.try_reserve(&host) {
+                if !wait.is_zero() {
+                    debug!("waiting {:?} before visiting {} again", wait, host);
+                    sleep(wait).await;
+                }
+                return HostPermit {
+                    politeness: self.clone(),
+                    host,
+                };
+            }
+            let _ = tokio::time::timeout(RECHECK_INTERVAL, self.released.notified()).await;
+        }
+    }
+
+    pub fn set_crawl_delay(&self, url: &str, delay: Duration) {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(host_of(url))
+            .or_insert_with(HostState::new)
+            .crawl_delay = delay;
+    }
+
+    // adapts the pace of the host to the status of its pages
+    pub fn record_response(&self, url: &str, status: u16, retry_after: Option<&str>) {
+        if !self.options.adaptive {
+            return;
+        }
+        let host = host_of(url);
+        let max_backoff = Duration::from_secs(self.options.max_backoff);
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.clone()).or_insert_with(HostState::new);
+
+        if status == 429 || status == 503 {
+            state.backoff = match retry_after.and_then(parse_retry_after) {
+                Some(d) => d,
+                None => (state.backoff * 2).max(MIN_BACKOFF),
+            }
+            .min(max_backoff);
+            state.next_visit = state.next_visit.max(Instant::now() + state.backoff);
+            warn!(
+                "{} answered {}, backing off for {:?}",
+                host, status, state.backoff
+            );
+        } else if status < 400 && !state.backoff.is_zero() {
+            state.backoff /= 2;
+            if state.backoff < Duration::from_millis(100) {
+                state.backoff = Duration::ZERO;
+            }
+        }
+    }
+
+    fn try_reserve(&self, host: &str) -> Option<Duration> {
+        let min_delay = Duration::from_millis(self.options.min_host_delay);
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.into()).or_insert_with(HostState::new);
+        if state.active >= self.options.max_tabs_per_host.max(1) {
+            return None;
+        }
+
+        let now = Instant::now();
+        let slot = state.next_visit.max(now);
+        state.active += 1;
+        state.next_visit = slot + min_delay.max(state.crawl_delay) + state.backoff;
+        Some(slot - now)
+    }
+
+    fn release(&self, host: &str) {
+        if let Some(state) = self.hosts.lock().unwrap().get_mut(host) {
+            state.active = state.active.saturating_sub(1);
+        }
+        self.released.notify_waiters();
+
+        let mut pruned_at = self.pruned_at.lock().unwrap();
+        if pruned_at.elapsed() >= PRUNE_INTERVAL {
+            *pruned_at = Instant::now();
+            self.prune();
+        }
+    }
+
+    // forgets the hosts nobody browses whose next visit and backoff are over, the
+    // ones with a crawl delay are kept as their robots.txt is only read once
+    fn prune(&self) {
+        let now = Instant::now();
+        let mut hosts = self.hosts.lock().unwrap();
+        let count = hosts.len();
+        hosts.retain(|_, state| {
+            state.active > 0
+                || !state.crawl_delay.is_zero()
+                || state.next_visit + state.backoff > now
+        });
+        debug!("forgot {} idle hosts", count - hosts.len());
+    }
+}
+
+impl HostState {
+    fn new() -> Self {
+        HostState {
+            active: 0,
+            next_visit: Instant::now(),
+            crawl_delay: Duration::ZERO,
+            backoff: Duration::ZERO,
+        }
+    }
+}
+
+fn host_of(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(u) => u.host_str().unwrap_or_default().to_lowercase(),
+        Err(_) => url.into(),
+    }
+}
+
+// Retry-After is either a number of seconds or an http date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn politeness(max_tabs_per_host: usize, min_host_delay: u64) -> Arc<HostPoliteness> {
+        Arc::new(HostPoliteness::new(
+            PolitenessOptions::default_builder()
+                .max_tabs_per_host(max_tabs_per_host)
+                .min_host_delay(min_host_delay)
+                .build()
+                .unwrap(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn caps_the_tabs_per_host() {
+        let p = politeness(2, 0);
+        let first = p.acquire("https://example.com/a").await;
+        let _second = p.acquire("https://example.com/b").await;
+        assert!(p.try_reserve("example.com").is_none());
+        // other hosts are not affected
+        let _other = p.acquire("https://other.com/").await;
+
+        let waiting = tokio::spawn({
+            let p = p.clone();
+            async move {
+                p.acquire("https://example.com/c").await;
+            }
+        });
+        sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn spaces_out_visits() {
+        let p = politeness(10, 1000);
+        assert_eq!(p.try_reserve("example.com"), Some(Duration::ZERO));
+        assert!(p.try_reserve("example.com").unwrap() > Duration::from_millis(900));
+
+        p.set_crawl_delay("https://slow.com/", Duration::from_secs(5));
+        p.try_reserve("slow.com");
+        assert!(p.try_reserve("slow.com").unwrap() > Duration::from_millis(4900));
+    }
+
+    #[tokio::test]
+    async fn backs_off_rate_limiting_hosts() {
+        let p = politeness(10, 0);
+        p.record_response("https://example.com/", 429, Some("30"));
+        assert!(p.try_reserve("example.com").unwrap() > Duration::from_secs(29));
+
+        // the backoff shrinks back once the host answers
+        let p = politeness(10, 0);
+        p.record_response("https://example.com/", 503, None);
+        assert_eq!(p.hosts.lock().unwrap()["example.com"].backoff, MIN_BACKOFF);
+        p.record_response("https://example.com/", 200, None);
+        assert_eq!(
+            p.hosts.lock().unwrap()["example.com"].backoff,
+            MIN_BACKOFF / 2
+        );
+
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn forgets_idle_hosts() {
+        let p = politeness(10, 100);
+        let _active = p.acquire("https://active.com/").await;
+        drop(p.acquire("https://idle.com/").await);
+        drop(p.acquire("https://recent.com/").await);
+        p.set_crawl_delay("https://delayed.com/", Duration::from_secs(5));
+        p.record_response("https://limited.com/", 429, Some("30"));
+
+        sleep(Duration::from_millis(150)).await;
+        drop(p.acquire("https://recent.com/").await);
+        p.prune();
+        let mut hosts = p.hosts.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+        hosts.sort();
+        assert_eq!(
+            hosts,
+            vec!["active.com", "delayed.com", "limited.com", "recent.com"]
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use headless_chrome::{protocol::cdp::types::Event, Tab};
+use rand::Rng;
+
+// time without network activity or DOM mutations after which a page is considered settled
+const QUIET_WINDOW: Duration = Duration::from_millis(500);
+// long-polling and analytics beacons rarely finish, we tolerate a few of them in flight
+const MAX_IDLE_IN_FLIGHT: usize = 2;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadinessStrategy {
+    // sleep a random duration between the min and max wait
+    Fixed,
+    // wait until no requests are in flight
+    NetworkIdle,
+    // wait until the network is idle and the DOM stopped mutating
+    DomStable,
+}
+
+impl FromStr for ReadinessStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(ReadinessStrategy::Fixed),
+            "network-idle" => Ok(ReadinessStrategy::NetworkIdle),
+            "dom-stable" => Ok(ReadinessStrategy::DomStable),
+            _ => Err(anyhow!(
+                "invalid readiness strategy {}, expected one of fixed, network-idle, dom-stable",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReadinessStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ReadinessStrategy::Fixed => "fixed",
+            ReadinessStrategy::NetworkIdle => "network-idle",
+            ReadinessStrategy::DomStable => "dom-stable",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// Decides when a page is ready to be captured. The min and max waits
+// are the lower and upper bounds of every wait, whatever the strategy.
+#[derive(Debug, Clone)]
+pub struct PageReadiness {
+    strategy: ReadinessStrategy,
+    min_wait_secs: u64,
+    max_wait_secs: u64,
+}
+
+impl PageReadiness {
+    pub fn new(strategy: ReadinessStrategy, min_wait_secs: u64, max_wait_secs: u64) -> Self {
+        PageReadiness {
+            strategy,
+            min_wait_secs,
+            max_wait_secs: max_wait_secs.max(min_wait_secs),
+        }
+    }
+
+    pub fn strategy(&self) -> ReadinessStrategy {
+        self.strategy.clone()
+    }
+
+    // the network activity to feed with the events of a tab, none is needed for fixed waits
+    pub fn track(&self) -> Option<Arc<NetworkActivity>> {
+        match self.strategy {
+            ReadinessStrategy::Fixed => None,
+            _ => Some(Arc::new(NetworkActivity::default())),
+        }
+    }
+
+    pub fn wait(
+        &self,
+        tab: &Arc<Tab>,
+        activity: Option<&Arc<NetworkActivity>>,
+    ) -> anyhow::Result<()> {
+        let activity = match (&self.strategy, activity) {
+            (ReadinessStrategy::Fixed, _) | (_, None) => {
+                let rndm = {
+                    let mut rng = rand::thread_rng();
+                    rng.gen_range(self.min_wait_secs..=self.max_wait_secs)
+                };
+                debug!("sleeping for {} seconds", rndm);
+                sleep(Duration::from_secs(rndm));
+                return Ok(());
+            }
+            (_, Some(activity)) => activity,
+        };
+
+        if self.strategy == ReadinessStrategy::DomStable {
+            tab.evaluate(MUTATION_OBSERVER_SCRIPT, false)
+                .context("could not install mutation observer")?;
+        }
+        let start = Instant::now();
+        let settled = self.wait_until(|| match self.strategy {
+            ReadinessStrategy::DomStable => {
+                Ok(activity.is_idle() && Self::dom_quiet_for(tab)? >= QUIET_WINDOW)
+            }
+            _ => Ok(activity.is_idle()),
+        })?;
+        if settled {
+            debug!("page settled after {:?}", start.elapsed());
+        } else {
+            debug!(
+                "page not settled after {:?} ({} requests in flight), moving on",
+                start.elapsed(),
+                activity.in_flight()
+            );
+        }
+        Ok(())
+    }
+
+    // polls until settled, never before the min wait nor after the max wait.
+    // False when the max wait ran out first
+    fn wait_until(
+        &self,
+        mut settled: impl FnMut() -> anyhow::Result<bool>,
+    ) -> anyhow::Result<bool> {
+        let start = Instant::now();
+        sleep(Duration::from_secs(self.min_wait_secs));
+        loop {
+            if start.elapsed() >= Duration::from_secs(self.max_wait_secs) {
+                return Ok(false);
+            }
+            if settled()? {
+                return Ok(true);
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn dom_quiet_for(tab: &Arc<Tab>) -> anyhow::Result<Duration> {
+        let res = tab
+            .evaluate(
+                "window.__atwLastMutation ? Date.now() - window.__atwLastMutation : 0",
+                false,
+            )
+            .context("could not query dom mutations")?;
+        let ms = res.value.and_then(|v| v.as_u64()).unwrap_or(0);
+        Ok(Duration::from_millis(ms))
+    }
+}
+
+// Counts the requests a tab has in flight using the CDP network events
+pub struct NetworkActivity {
+    in_flight: Mutex<HashSet<String>>,
+    last_activity: Mutex<Instant>,
+}
+
+impl Default for NetworkActivity {
+    fn default() -> Self {
+        NetworkActivity {
+            in_flight: Mutex::new(HashSet::new()),
+            last_activity: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl NetworkActivity {
+    pub fn on_event(&self, event: &Event) {
+        match event {
+            Event::NetworkRequestWillBeSent(ev) => self.started(&ev.params.request_id),
+            Event::NetworkLoadingFinished(ev) => self.finished(&ev.params.request_id),
+            Event::NetworkLoadingFailed(ev) => self.finished(&ev.params.request_id),
+            _ => {}
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    // time elapsed since the last request started or completed
+    pub fn idle_for(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+
+    // a few requests may stay in flight, as long as none started or completed lately
+    pub fn is_idle(&self) -> bool {
+        self.in_flight() <= MAX_IDLE_IN_FLIGHT && self.idle_for() >= QUIET_WINDOW
+    }
+
+    fn started(&self, request_id: &str) {
+        self.in_flight
+            .lock()
+            .unwrap()
+            .insert(request_id.to_string());
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    fn finished(&self, request_id: &str) {
+        self.in_flight.lock().unwrap().remove(request_id);
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+}
+
+const MUTATION_OBSERVER_SCRIPT: &str = r#"
+if (!window.__atwObserver) {
+    window.__atwLastMutation = Date.now();
+    window.__atwObserver = new MutationObserver(() => {
+        window.__atwLastMutation = Date.now();
+    });
+    window.__atwObserver.observe(document, {
+        attributes: true,
+        childList: true,
+        subtree: true,
+        characterData: true,
+    });
+}"#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_strategies() {
+        for s in ["fixed", "network-idle", "dom-stable"] {
+            let strategy = ReadinessStrategy::from_str(s).unwrap();
+            assert_eq!(strategy.to_string(), s);
+        }
+        assert!(ReadinessStrategy::from_str("idle").is_err());
+    }
+
+    #[test]
+    fn max_wait_is_never_below_min_wait() {
+        let r = PageReadiness::new(ReadinessStrategy::Fixed, 5, 2);
+        assert_eq!(r.max_wait_secs, 5);
+    }
+
+    #[test]
+    fn counts_requests_in_flight() {
+        let activity = NetworkActivity::default();
+        activity.started("1");
+        activity.started("2");
+        // the same request reported twice counts once
+        activity.started("2");
+        assert_eq!(activity.in_flight(), 2);
+        activity.finished("1");
+        activity.finished("3");
+        assert_eq!(activity.in_flight(), 1);
+        assert!(activity.idle_for() < QUIET_WINDOW);
+    }
+
+    #[test]
+    fn is_idle_after_the_quiet_window() {
+        let activity = NetworkActivity::default();
+        for id in ["1", "2", "3"] {
+            activity.started(id);
+        }
+        sleep(QUIET_WINDOW);
+        assert!(!activity.is_idle());
+
+        // a few requests left in flight do not keep the page busy
+        activity.finished("3");
+        assert!(!activity.is_idle());
+        sleep(QUIET_WINDOW);
+        assert_eq!(activity.in_flight(), MAX_IDLE_IN_FLIGHT);
+        assert!(activity.is_idle());
+
+        activity.started("4");
+        assert!(!activity.is_idle());
+    }
+
+    #[test]
+    fn waits_within_the_min_and_max_waits() {
+        let readiness = PageReadiness::new(ReadinessStrategy::NetworkIdle, 1, 2);
+        let start = Instant::now();
+        assert!(readiness.wait_until(|| Ok(true)).unwrap());
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let start = Instant::now();
+        assert!(!readiness.wait_until(|| Ok(false)).unwrap());
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn waits_for_the_network_to_settle() {
+        let readiness = PageReadiness::new(ReadinessStrategy::NetworkIdle, 0, 5);
+        let activity = Arc::new(NetworkActivity::default());
+        for id in ["1", "2", "3"] {
+            activity.started(id);
+        }
+        let finishing = {
+            let activity = activity.clone();
+            std::thread::spawn(move || {
+                sleep(Duration::from_millis(700));
+                activity.finished("1");
+            })
+        };
+
+        let start = Instant::now();
+        assert!(readiness.wait_until(|| Ok(activity.is_idle())).unwrap());
+        // ready once the request finished and nothing happened for the quiet window
+        assert!(start.elapsed() >= Duration::from_millis(700) + QUIET_WINDOW);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(activity.in_flight(), MAX_IDLE_IN_FLIGHT);
+        finishing.join().unwrap();
+    }
+}
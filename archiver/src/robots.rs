@@ -0,0 +1,537 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use futures::future;
+use reqwest::Url;
+
+use crate::utils::{get_origin, APP_NAME, APP_VERSION};
+
+// crawlers must parse at least 500 KiB of a robots.txt (RFC 9309 2.5)
+const MAX_ROBOTS_SIZE: usize = 500 * 1024;
+// a site asking for longer delays would stall the whole crawl
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(60);
+const ROBOTS_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RobotsPolicy {
+    // every link allowed by the crawl type is archived
+    Ignore,
+    // seeds and discovered links must be allowed by the site's robots.txt
+    Obey,
+    // seeds are always archived, discovered links must be allowed
+    ObeyButArchiveSeed,
+}
+
+impl RobotsPolicy {
+    pub fn obeys(&self) -> bool {
+        *self != RobotsPolicy::Ignore
+    }
+
+    pub fn checks_seeds(&self) -> bool {
+        *self == RobotsPolicy::Obey
+    }
+}
+
+impl FromStr for RobotsPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(RobotsPolicy::Ignore),
+            "obey" => Ok(RobotsPolicy::Obey),
+            "obey-but-archive-seed" => Ok(RobotsPolicy::ObeyButArchiveSeed),
+            _ => Err(anyhow!(
+                "invalid robots policy {}, expected one of ignore, obey, obey-but-archive-seed",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RobotsPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RobotsPolicy::Ignore => "ignore",
+            RobotsPolicy::Obey => "obey",
+            RobotsPolicy::ObeyButArchiveSeed => "obey-but-archive-seed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatternToken {
+    Byte(u8),
+    // *
+    Any,
+    // $ at the end of a pattern
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    allow: bool,
+    pattern: Vec<PatternToken>,
+}
+
+impl Rule {
+    fn new(allow: bool, pattern: &str) -> Self {
+        let bytes = pattern.as_bytes();
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'*' => tokens.push(PatternToken::Any),
+                b'$' if i == bytes.len() - 1 => tokens.push(PatternToken::End),
+                // an encoded * or $ is a literal character, not a special one
+                b'%' if i + 2 < bytes.len() => match decode_hex(&bytes[i + 1..i + 3]) {
+                    Some(b) => {
+                        tokens.push(PatternToken::Byte(b));
+                        i += 2;
+                    }
+                    None => tokens.push(PatternToken::Byte(b'%')),
+                },
+                b => tokens.push(PatternToken::Byte(b)),
+            }
+            i += 1;
+        }
+        Rule {
+            allow,
+            pattern: tokens,
+        }
+    }
+
+    // number of octets of the pattern, the longest matching rule wins
+    fn len(&self) -> usize {
+        self.pattern.len()
+    }
+
+    // patterns match a prefix of the path unless they end with $
+    fn matches(&self, path: &[u8]) -> bool {
+        let mut positions = vec![0];
+        for token in &self.pattern {
+            positions = match token {
+                PatternToken::Any => (positions[0]..=path.len()).collect(),
+                PatternToken::End => positions.into_iter().filter(|p| *p == path.len()).collect(),
+                PatternToken::Byte(b) => positions
+                    .into_iter()
+                    .filter(|p| *p < path.len() && path[*p] == *b)
+                    .map(|p| p + 1)
+                    .collect(),
+            };
+            if positions.is_empty() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Group {
+    user_agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+// The rules of a robots.txt that apply to our user agent
+// https://www.rfc-editor.org/rfc/rfc9309
+#[derive(Debug, Clone, Default)]
+pub struct RobotsTxt {
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+    sitemaps: Vec<String>,
+    disallow_all: bool,
+}
+
+impl RobotsTxt {
+    // used when the robots.txt does not exist
+    pub fn allow_all() -> Self {
+        RobotsTxt::default()
+    }
+
+    // used when the robots.txt is unreachable, the site may be down or rate limiting us
+    pub fn disallow_all() -> Self {
+        RobotsTxt {
+            disallow_all: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn parse(content: &str, user_agent: &str) -> Self {
+        let mut groups: Vec<Group> = vec![];
+        let mut sitemaps = vec![];
+        // consecutive user-agent lines share the rules that follow them
+        let mut in_agents = false;
+
+        for line in content.lines() {
+            let line = match line.find('#') {
+                Some(i) => &line[..i],
+                None => line,
+            };
+            let (key, value) = match line.split_once(':') {
+                Some((k, v)) => (k.trim().to_lowercase(), v.trim()),
+                None => continue,
+            };
+
+            match key.as_str() {
+                "user-agent" | "useragent" | "user agent" => {
+                    if !in_agents || groups.is_empty() {
+                        groups.push(Group::default());
+                    }
+                    in_agents = true;
+                    if let Some(group) = groups.last_mut() {
+                        group.user_agents.push(product_token(value));
+                    }
+                }
+                "allow" | "disallow" => {
+                    in_agents = false;
+                    // an empty disallow allows everything, it is the same as no rule
+                    if value.is_empty() {
+                        continue;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.rules.push(Rule::new(key == "allow", value));
+                    }
+                }
+                "crawl-delay" => {
+                    in_agents = false;
+                    if let (Some(group), Ok(secs)) = (groups.last_mut(), value.parse::<f64>()) {
+                        if secs.is_finite() && secs >= 0.0 {
+                            group.crawl_delay =
+                                Some(Duration::from_secs_f64(secs).min(MAX_CRAWL_DELAY));
+                        }
+                    }
+                }
+                // sitemaps are not part of a group
+                "sitemap" if !value.is_empty() => sitemaps.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        // groups matching our product token are combined, otherwise the * groups are used
+        let agent = product_token(user_agent);
+        let mut matching = groups
+            .iter()
+            .filter(|g| g.user_agents.contains(&agent))
+            .collect::<Vec<&Group>>();
+        if matching.is_empty() {
+            matching = groups
+                .iter()
+                .filter(|g| g.user_agents.iter().any(|a| a == "*"))
+                .collect();
+        }
+
+        RobotsTxt {
+            rules: matching.iter().flat_map(|g| g.rules.clone()).collect(),
+            crawl_delay: matching.iter().find_map(|g| g.crawl_delay),
+            sitemaps,
+            disallow_all: false,
+        }
+    }
+
+    pub fn is_allowed(&self, url: &str) -> bool {
+        let u = match Url::parse(url) {
+            Ok(u) => u,
+            Err(_) => return false,
+        };
+        let path = match u.query() {
+            Some(q) => format!("{}?{}", u.path(), q),
+            None => u.path().to_string(),
+        };
+        self.is_path_allowed(&path)
+    }
+
+    pub fn is_path_allowed(&self, path: &str) -> bool {
+        // the robots.txt itself is always allowed
+        if path == "/robots.txt" {
+            return true;
+        }
+        if self.disallow_all {
+            return false;
+        }
+
+        let path = percent_decode(path);
+        let mut best: Option<&Rule> = None;
+        for rule in self.rules.iter().filter(|r| r.matches(&path)) {
+            best = match best {
+                // allow wins when rules are equally specific
+                Some(b) if b.len() > rule.len() || (b.len() == rule.len() && b.allow) => Some(b),
+                _ => Some(rule),
+            };
+        }
+        best.map(|r| r.allow).unwrap_or(true)
+    }
+
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.crawl_delay
+    }
+
+    pub fn sitemaps(&self) -> Vec<String> {
+        self.sitemaps.clone()
+    }
+}
+
+// Fetches the robots.txt of every origin met during a crawl once
+pub struct RobotsCache {
+    user_agent: String,
+    // built on first use, loading the certificates is slow and most crawls ignore robots.txt
+    client: Option<reqwest::Client>,
+    origins: HashMap<String, Arc<RobotsTxt>>,
+}
+
+impl RobotsCache {
+    pub fn new() -> Self {
+        RobotsCache {
+            user_agent: format!("{}/{}", APP_NAME.as_str(), APP_VERSION.as_str()),
+            client: None,
+            origins: HashMap::new(),
+        }
+    }
+
+    pub async fn get(&mut self, url: &str) -> anyhow::Result<Arc<RobotsTxt>> {
+        let origin = get_origin(url)?;
+        if let Some(robots) = self.origins.get(&origin) {
+            return Ok(robots.clone());
+        }
+
+        let robots = Arc::new(self.fetch(&origin).await);
+        self.origins.insert(origin, robots.clone());
+        Ok(robots)
+    }
+
+    // fetches the robots.txt of the origins not met yet concurrently, so that slow
+    // sites hold the crawl for one timeout at most
+    pub async fn prefetch(&mut self, urls: &[String]) {
+        let origins = urls
+            .iter()
+            .filter_map(|u| get_origin(u).ok())
+            .filter(|o| !self.origins.contains_key(o))
+            .collect::<HashSet<String>>();
+        if origins.is_empty() {
+            return;
+        }
+
+        let client = self.client();
+        let fetches = origins.into_iter().map(|origin| {
+            let client = client.clone();
+            let user_agent = self.user_agent.clone();
+            async move {
+                let robots = fetch_robots(&client, &user_agent, &origin).await;
+                (origin, robots)
+            }
+        });
+        for (origin, robots) in future::join_all(fetches).await {
+            self.origins.insert(origin, Arc::new(robots));
+        }
+    }
+
+    async fn fetch(&mut self, origin: &str) -> RobotsTxt {
+        let client = self.client();
+        fetch_robots(&client, &self.user_agent, origin).await
+    }
+
+    fn client(&mut self) -> reqwest::Client {
+        let user_agent = self.user_agent.clone();
+        self.client
+            .get_or_insert_with(|| {
+                reqwest::Client::builder()
+                    .user_agent(user_agent)
+                    .timeout(ROBOTS_TIMEOUT)
+                    .build()
+                    .unwrap_or_default()
+            })
+            .clone()
+    }
+}
+
+impl Default for RobotsCache {
+    fn default() -> Self {
+        RobotsCache::new()
+    }
+}
+
+async fn fetch_robots(client: &reqwest::Client, user_agent: &str, origin: &str) -> RobotsTxt {
+    let url = format!("{}/robots.txt", origin);
+    let res = match client.get(&url).send().await {
+        Ok(res) => res,
+        Err(e) => {
+            warn!("{} is unreachable, disallowing the whole site {}", url, e);
+            return RobotsTxt::disallow_all();
+        }
+    };
+
+    let status = res.status();
+    if status.is_success() {
+        return match res.bytes().await {
+            Ok(body) => {
+                let body = &body[..body.len().min(MAX_ROBOTS_SIZE)];
+                debug!("fetched {}", url);
+                RobotsTxt::parse(&String::from_utf8_lossy(body), user_agent)
+            }
+            Err(e) => {
+                warn!("could not read {} {}", url, e);
+                RobotsTxt::disallow_all()
+            }
+        };
+    }
+    // a missing robots.txt allows everything, 429 means we are rate limited though
+    if status.is_client_error() && status.as_u16() != 429 {
+        debug!("{} returned {}, allowing the whole site", url, status);
+        return RobotsTxt::allow_all();
+    }
+    warn!("{} returned {}, disallowing the whole site", url, status);
+    RobotsTxt::disallow_all()
+}
+
+// the product token is the name of the crawler without its version
+fn product_token(user_agent: &str) -> String {
+    let user_agent = user_agent.trim();
+    if user_agent == "*" {
+        return "*".into();
+    }
+    user_agent
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn decode_hex(hex: &[u8]) -> Option<u8> {
+    u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+}
+
+// paths and patterns are compared octet by octet once percent-decoded
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(b) = decode_hex(&bytes[i + 1..i + 3]) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // RFC 9309 5.1
+    const SIMPLE_EXAMPLE: &str = "User-Agent: *
+Disallow: *.gif$
+Disallow: /example/
+Allow: /publications/
+
+User-Agent: foobot
+Disallow:/
+Allow:/example/page.html
+Allow:/example/allowed.gif
+
+User-Agent: barbot
+User-Agent: bazbot
+Disallow: /example/page.html
+
+User-Agent: quxbot
+";
+
+    #[test]
+    fn matches_the_rfc_simple_example() {
+        let foobot = RobotsTxt::parse(SIMPLE_EXAMPLE, "FooBot/1.0");
+        assert!(foobot.is_path_allowed("/example/page.html"));
+        assert!(foobot.is_path_allowed("/example/allowed.gif"));
+        assert!(!foobot.is_path_allowed("/example/disallowed.gif"));
+        assert!(!foobot.is_path_allowed("/publications/"));
+
+        for agent in ["barbot", "bazbot"] {
+            let robots = RobotsTxt::parse(SIMPLE_EXAMPLE, agent);
+            assert!(!robots.is_path_allowed("/example/page.html"));
+            assert!(robots.is_path_allowed("/example/"));
+            assert!(robots.is_path_allowed("/image.gif"));
+        }
+
+        let quxbot = RobotsTxt::parse(SIMPLE_EXAMPLE, "quxbot");
+        assert!(quxbot.is_path_allowed("/example/page.html"));
+        assert!(quxbot.is_path_allowed("/image.gif"));
+
+        let other = RobotsTxt::parse(SIMPLE_EXAMPLE, "atw");
+        assert!(!other.is_path_allowed("/image.gif"));
+        assert!(other.is_path_allowed("/image.gif?size=2"));
+        assert!(!other.is_path_allowed("/example/page.html"));
+        assert!(other.is_path_allowed("/publications/"));
+        assert!(other.is_path_allowed("/"));
+    }
+
+    #[test]
+    fn prefers_the_longest_match() {
+        // RFC 9309 5.2
+        let robots = RobotsTxt::parse(
+            "User-Agent: foobot
+Allow: /example/page/
+Disallow: /example/page/disallowed.gif",
+            "foobot",
+        );
+        assert!(robots.is_path_allowed("/example/page/"));
+        assert!(robots.is_path_allowed("/example/page/allowed.gif"));
+        assert!(!robots.is_path_allowed("/example/page/disallowed.gif"));
+
+        // allow wins when both rules are as long
+        let robots = RobotsTxt::parse("User-Agent: *\nDisallow: /page\nAllow: /page", "atw");
+        assert!(robots.is_path_allowed("/page"));
+    }
+
+    #[test]
+    fn matches_special_characters() {
+        // RFC 9309 2.2.3
+        let robots = RobotsTxt::parse(
+            "User-Agent: *
+Allow: /this/path/exactly$
+Disallow: /this/path/
+Disallow: /path/file-with-a-%2A.html
+Disallow: /path/foo-%24
+Disallow: /foo/bar?baz=quz
+Disallow: /foo/bar/ツ # non ascii patterns match encoded paths",
+            "atw",
+        );
+        assert!(robots.is_path_allowed("/this/path/exactly"));
+        assert!(!robots.is_path_allowed("/this/path/exactly/not"));
+        assert!(!robots.is_path_allowed("/path/file-with-a-*.html"));
+        assert!(robots.is_path_allowed("/path/file-with-a-star.html"));
+        assert!(!robots.is_path_allowed("/path/foo-$"));
+        assert!(!robots.is_path_allowed("/foo/bar?baz=quz"));
+        assert!(robots.is_path_allowed("/foo/bar?baz=other"));
+        assert!(!robots.is_allowed("https://example.com/foo/bar/ツ"));
+        assert!(!robots.is_path_allowed("/foo/bar/%E3%83%84"));
+        assert!(robots.is_path_allowed("/robots.txt"));
+    }
+
+    #[test]
+    fn parses_crawl_delays_and_sitemaps() {
+        let robots = RobotsTxt::parse(
+            "Sitemap: https://example.com/sitemap.xml
+user-agent: *
+crawl-delay: 2.5
+disallow:",
+            "atw",
+        );
+        assert_eq!(robots.crawl_delay(), Some(Duration::from_millis(2500)));
+        assert_eq!(robots.sitemaps(), vec!["https://example.com/sitemap.xml"]);
+        assert!(robots.is_path_allowed("/anything"));
+
+        assert!(!RobotsTxt::disallow_all().is_path_allowed("/"));
+        assert!(RobotsTxt::allow_all().is_path_allowed("/"));
+    }
+}
@@ -1,25 +1,51 @@
 use std::{
     path::PathBuf,
     str::FromStr,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 use anyhow::{anyhow, Context};
 use atw::state::CrawlType;
+use chrono::Utc;
 use reqwest::Url;
 use signal_hook::consts::{SIGINT, SIGTERM};
 
 use crate::{
+    browser_pool::{BrowserPool, BrowserPoolOptions},
+    budget::CrawlBudget,
+    capture::CaptureBackend,
+    cdxj::write_warc_index,
+    checkpoint::CrawlCheckpoint,
+    config::ArchiverConfig,
     crawler::Crawler,
-    types::{ArchiveInfo, ArchivingResult, CrawlUploadResult},
+    fetcher::{ChromeFetcher, PageFetcher},
+    manifest::{ArchiveFileKind, ArchiveManifest, MANIFEST_SUFFIX, PAGES_SUFFIX},
+    politeness::{HostPoliteness, PolitenessOptions},
+    readiness::{PageReadiness, ReadinessStrategy},
+    robots::RobotsPolicy,
+    scope::{ScopeRule, ScopeRules},
+    spending::SpendingLedger,
+    storage::StorageBackend,
+    types::{ArchiveInfo, ArchiverError, ArchivingResult, StoredCrawl},
     uploader::Uploader,
-    utils::BASE_URL,
+    utils::{get_origin, BASE_URL},
+    wacz::WaczPackage,
     warc_writer::WarcWriter,
 };
 
 pub struct Runner {
-    uploader: Option<Uploader>,
+    uploader: Option<Arc<Uploader>>,
     warc_writer: WarcWriter,
+    browser_pool: Arc<BrowserPool>,
+    politeness: Arc<HostPoliteness>,
+    // where the crawl state is saved, none when crawls are not resumable
+    checkpoint_path: Option<PathBuf>,
+    // state of an interrupted crawl to continue
+    resume: Option<CrawlCheckpoint>,
     options: RunnerOptions,
     should_terminate: Arc<AtomicBool>,
 }
@@ -52,9 +78,36 @@ pub struct RunnerOptions {
     // maximum wait time after navigation in seconds
     #[builder(default = "7")]
     max_wait_after_navigation: u64,
+    // how we decide a page is ready to be captured, bounded by the min and max waits
+    #[builder(default = "ReadinessStrategy::NetworkIdle")]
+    readiness_strategy: ReadinessStrategy,
+    // whether the robots.txt of the crawled sites is respected
+    #[builder(default = "RobotsPolicy::Ignore")]
+    robots_policy: RobotsPolicy,
+    // queue the urls listed in the sitemaps of the seeds, whatever the crawl type
+    #[builder(default = "false")]
+    seed_from_sitemaps: bool,
+    // maximum number of urls queued from sitemaps per crawl
+    #[builder(default = "1000")]
+    max_sitemap_urls: usize,
+    // ordered include/exclude rules narrowing the crawl type, the first matching one decides
+    #[builder(default = "vec![]")]
+    scope_rules: Vec<ScopeRule>,
+    // the crawl stops, keeping what it archived so far, once it visited that many pages
+    #[builder(default = "None")]
+    max_pages: Option<usize>,
+    // or once its warc files reach that many bytes
+    #[builder(default = "None")]
+    max_bytes: Option<u64>,
+    // or after that many seconds
+    #[builder(default = "None")]
+    max_duration: Option<u64>,
     // browser timeout in seconds
     #[builder(default = "45")]
     timeout: u64,
+    // whether pages are recorded through the pywb proxy or natively
+    #[builder(default = "CaptureBackend::Pywb")]
+    capture_backend: CaptureBackend,
     // base url where the warc writer can be accessed
     #[builder(default = "self.default_base_url()")]
     base_url: String,
@@ -64,12 +117,50 @@ pub struct RunnerOptions {
     // toggle if we want to upload the finished warc files to arweave
     #[builder(default = "false")]
     with_upload: bool,
+    // bundles the warc files, index, pages and screenshot of a crawl in a single wacz file
+    #[builder(default = "false")]
+    with_wacz: bool,
     // path to the arweave keyfile
     #[builder(default = "self.default_arweave_wallet_dir()")]
     arweave_key_dir: PathBuf,
     // currency to pay out for the bundlr service
     #[builder(default = "self.default_currency()")]
     currency: String,
+    // bundlr node the crawl files are uploaded to and the names they are tagged with
+    #[builder(default = "ArchiverConfig::default()")]
+    config: ArchiverConfig,
+    // browsers shared with other runners, a dedicated pool is created if none is given
+    #[builder(default = "None")]
+    browser_pool: Option<Arc<BrowserPool>>,
+    // maximum number of pages of the same host browsed at once
+    #[builder(default = "2")]
+    max_tabs_per_host: usize,
+    // minimum time between two visits of the same host in milliseconds
+    #[builder(default = "1000")]
+    min_host_delay: u64,
+    // host pacing shared with other runners, a dedicated one is created if none is given
+    #[builder(default = "None")]
+    politeness: Option<Arc<HostPoliteness>>,
+    // spending caps shared with other runners, uploads are only checked against
+    // the balance without it
+    #[builder(default = "None")]
+    spending: Option<Arc<SpendingLedger>>,
+    // where the crawls are copied to besides bundlr
+    #[builder(default = "vec![]")]
+    storage: Vec<Arc<dyn StorageBackend>>,
+    // saves the crawl state there so an interrupted crawl continues on restart,
+    // crawls are not resumable without it
+    #[builder(default = "None")]
+    checkpoint_dir: Option<PathBuf>,
+    // archive request being crawled, identifies its saved state
+    #[builder(default = "None")]
+    request_id: Option<String>,
+    // seeds the runner is about to crawl, a saved state is only resumed for the same seeds
+    #[builder(default = "vec![]")]
+    seeds: Vec<String>,
+    // pages are browsed with the chrome browsers of the pool unless a fetcher is given
+    #[builder(default = "None")]
+    fetcher: Option<Arc<dyn PageFetcher>>,
 }
 
 impl RunnerOptions {
@@ -100,19 +191,87 @@ impl RunnerOptionsBuilder {
 }
 
 impl Runner {
-    pub async fn new(lo: RunnerOptions) -> anyhow::Result<Self> {
+    pub async fn new(mut lo: RunnerOptions) -> anyhow::Result<Self> {
+        let checkpoint_path = match &lo.checkpoint_dir {
+            Some(dir) => match lo.request_id.as_ref().or(lo.archive_name.as_ref()) {
+                Some(key) => Some(CrawlCheckpoint::path(dir, key)),
+                None => {
+                    warn!("crawls without a request id nor an archive name can't be resumed");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        // a resumed crawl keeps writing to its collection, with the options it started with
+        let resume = match &checkpoint_path {
+            Some(path) => match CrawlCheckpoint::load(path) {
+                Ok(c) => c.filter(|c| {
+                    if c.seeds != lo.seeds {
+                        info!(
+                            "saved crawl of {:?} is not resumed for other seeds",
+                            c.seeds
+                        );
+                    }
+                    c.seeds == lo.seeds
+                }),
+                Err(e) => {
+                    warn!("could not resume crawl, starting over {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        if let Some(c) = &resume {
+            lo.archive_name = Some(c.archive_name.clone());
+            lo.crawl_depth = c.depth;
+            lo.crawl_type = c.crawl_type.clone();
+            lo.url_retries = c.url_retries;
+        }
+
         let warc_writer = WarcWriter::new(
             lo.writer_port,
             lo.writer_dir.clone(),
             lo.archive_name.clone(),
             lo.writer_debug,
+            lo.capture_backend.clone(),
         )?;
 
+        let browser_pool = match &lo.browser_pool {
+            Some(pool) => pool.clone(),
+            None => {
+                let pool_options = BrowserPoolOptions::default_builder()
+                    .size(lo.concurrent_tabs.max(1) as usize)
+                    .tabs_per_browser(1usize)
+                    .browser_timeout(lo.timeout)
+                    .build()?;
+                Arc::new(BrowserPool::new(pool_options)?)
+            }
+        };
+
+        let politeness = match &lo.politeness {
+            Some(p) => p.clone(),
+            None => Arc::new(HostPoliteness::new(
+                PolitenessOptions::default_builder()
+                    .max_tabs_per_host(lo.max_tabs_per_host)
+                    .min_host_delay(lo.min_host_delay)
+                    .build()?,
+            )),
+        };
+
         let uploader = if lo.with_upload {
-            let u = Uploader::new(lo.arweave_key_dir.clone(), &lo.currency)
+            // chunked uploads are resumed like crawls
+            let session_dir = lo.checkpoint_dir.as_ref().map(|dir| {
+                let mut dir = dir.clone();
+                dir.push("uploads");
+                dir
+            });
+            let u = Uploader::new(lo.arweave_key_dir.clone(), &lo.currency, &lo.config)
                 .await
-                .context("could not instantiate uploader")?;
-            Some(u)
+                .context("could not instantiate uploader")?
+                .with_session_dir(session_dir)
+                .with_spending(lo.spending.clone());
+            Some(Arc::new(u))
         } else {
             None
         };
@@ -124,17 +283,41 @@ impl Runner {
         Ok(Runner {
             uploader,
             warc_writer,
+            browser_pool,
+            politeness,
+            checkpoint_path,
+            resume,
             options: lo,
             should_terminate,
         })
     }
 
+    fn fetcher(&self) -> Arc<dyn PageFetcher> {
+        match &self.options.fetcher {
+            Some(f) => f.clone(),
+            None => Arc::new(ChromeFetcher::new(
+                self.browser_pool.clone(),
+                PageReadiness::new(
+                    self.options.readiness_strategy.clone(),
+                    self.options.min_wait_after_navigation,
+                    self.options.max_wait_after_navigation,
+                ),
+                self.warc_writer.recorder(),
+            )),
+        }
+    }
+
     fn prepare_urls(&self, url: &str) -> anyhow::Result<(String, String, String)> {
         let u = Url::from_str(url).context(format!("url passed is invalid {}", url))?;
         let domain = match u.domain() {
             Some(d) => d,
             None => return Err(anyhow!("url must have a valid domain")),
         };
+
+        if self.options.capture_backend == CaptureBackend::Native {
+            return Ok((get_origin(url)?, url.into(), domain.into()));
+        }
+
         let base_url = format!("{}:{}", self.options.base_url, self.warc_writer.port());
 
         let full_url = format!(
@@ -148,36 +331,154 @@ impl Runner {
     }
 
     pub async fn run_archiving(&self, original_url: &str) -> anyhow::Result<ArchivingResult> {
-        let (base_url, full_url, domain) = self.prepare_urls(original_url)?;
+        self.run_archiving_seeds(&[original_url.to_string()]).await
+    }
+
+    // crawls every seed in a single crawl sharing one visited set and one warc collection,
+    // the files are named after the first seed
+    pub async fn run_archiving_seeds(&self, seeds: &[String]) -> anyhow::Result<ArchivingResult> {
+        let original_url = match seeds.first() {
+            Some(u) => u.as_str(),
+            None => return Err(anyhow!("an archive request needs at least one url")),
+        };
+        let mut full_urls = vec![];
+        let mut base_url = String::new();
+        let mut domain = String::new();
+        for seed in seeds {
+            let (b, f, d) = self.prepare_urls(seed)?;
+            if full_urls.is_empty() {
+                base_url = b;
+                domain = d;
+            }
+            full_urls.push(f);
+        }
+        // only the crawl of the same seeds is resumed
+        let resumed = self.resume.as_ref().filter(|c| c.seeds == seeds);
+        let started_at = match resumed {
+            Some(c) => c.started_at,
+            None => Utc::now().timestamp(),
+        };
 
         info!(
-            "initializing crawl of {} with depth {}, {} browsers, crawl_type: {:?} and {} retries.",
-            original_url,
+            "initializing crawl of {} with depth {}, {} browsers, crawl_type: {:?}, {} retries, readiness {}, {} capture and robots policy {}.",
+            seeds.join(", "),
             self.options.crawl_depth,
             self.options.concurrent_tabs,
             self.options.crawl_type,
-            self.options.url_retries
+            self.options.url_retries,
+            self.options.readiness_strategy,
+            self.options.capture_backend,
+            self.options.robots_policy
         );
         let mut crawler = Crawler::new(
             &base_url,
-            &full_url,
-            original_url,
+            &full_urls,
+            seeds,
             self.options.crawl_depth,
             self.options.crawl_type.clone(),
             self.options.concurrent_tabs,
             self.options.url_retries,
-            self.options.timeout,
-            self.options.min_wait_after_navigation,
-            self.options.max_wait_after_navigation,
+            self.fetcher(),
+            self.warc_writer.recorder(),
+            self.politeness.clone(),
+            self.options.robots_policy.clone(),
+            self.options.seed_from_sitemaps,
+            self.options.max_sitemap_urls,
+            ScopeRules::new(self.options.scope_rules.clone()),
+            CrawlBudget {
+                max_pages: self.options.max_pages,
+                max_bytes: self.options.max_bytes,
+                max_duration: self.options.max_duration.map(Duration::from_secs),
+            },
+            self.warc_writer.archive_dir(),
         );
+        if let Some(path) = &self.checkpoint_path {
+            crawler.checkpoint_to(
+                path.clone(),
+                CrawlCheckpoint::new(
+                    self.options.request_id.clone(),
+                    &self.warc_writer.archive_name(),
+                    seeds,
+                    started_at,
+                    self.options.crawl_depth,
+                    self.options.crawl_type.clone(),
+                    self.options.url_retries,
+                ),
+            );
+        }
+        if let Some(c) = resumed {
+            info!(
+                "resuming crawl of {} in collection {}, {} pages visited and {} left",
+                original_url,
+                c.archive_name,
+                c.visited.len(),
+                c.frontier.len()
+            );
+            crawler.resume(c);
+        }
+
         let crawl = crawler.crawl(self.should_terminate.clone()).await?;
+        // the collection and the saved state are kept for the next run, which is also
+        // the case of a completed crawl until remove_checkpoint is called once it is stored
+        if self.checkpoint_path.is_some() && self.should_terminate.load(Ordering::Relaxed) {
+            return Err(ArchiverError::EarlyTermination.into());
+        }
+        if crawl.visited().is_empty() && crawl.skipped().len() == seeds.len() {
+            return Err(anyhow!(
+                "every seed of {} is disallowed by robots.txt",
+                original_url
+            ));
+        }
+        if let Some(limit) = crawl.budget_exceeded() {
+            warn!(
+                "crawl of {} stopped by its {} budget, the archive is partial",
+                original_url, limit
+            );
+        }
+        for rule in crawl.scope().rules.iter().filter(|r| r.rejected > 0) {
+            info!("scope rule {} rejected {} urls", rule.rule, rule.rejected);
+        }
 
         // we rename the files that the warc writer created for easy retrieval
         let files = self
             .warc_writer
             .rename_warc_files(&domain, self.options.crawl_depth)?;
+        if files.is_empty() {
+            return Err(anyhow!("no warc file was written for {}", original_url));
+        }
 
-        let archive_info = ArchiveInfo::new(&files[0])?;
+        // the crawl is dated like its oldest warc file so the manifest, the file
+        // names and the lookups of the manifest from a warc file all agree
+        let timestamp = match ArchiveInfo::from_name(&files[0]) {
+            Ok(info) => info.unix_ts(),
+            Err(e) => {
+                warn!("could not date the crawl from {:?} {}", files[0], e);
+                started_at
+            }
+        };
+        let mut manifest = ArchiveManifest::new(
+            &domain,
+            original_url,
+            timestamp,
+            self.options.crawl_depth as u8,
+            &format!("{:?}", self.options.crawl_type),
+            &crawl.main_title(),
+        );
+        manifest.seeds = seeds.to_vec();
+        manifest.budget_exceeded = crawl.budget_exceeded();
+        let archive_info = ArchiveInfo::from_manifest(&manifest)?;
+
+        // a missing index can be rebuilt from the warc file, so we don't fail the crawl for it
+        let index_files = files
+            .iter()
+            .filter_map(|f| match write_warc_index(f) {
+                Ok(index) => Some(index),
+                Err(e) => {
+                    warn!("could not index {:?} {}", f, e);
+                    None
+                }
+            })
+            .collect::<Vec<PathBuf>>();
 
         let screenshot_dir = self.warc_writer.process_screenshot(
             &archive_info.string_ts(),
@@ -185,31 +486,145 @@ impl Runner {
             self.options.crawl_depth,
         )?;
 
+        let mut package =
+            WaczPackage::new(&crawl.main_title(), original_url, archive_info.datetime());
+        for file in &files {
+            package.add_warc(file);
+        }
+        package.set_screenshot(&screenshot_dir);
+        package.add_visited(
+            &crawl.visited().into_iter().collect::<Vec<String>>(),
+            archive_info.datetime(),
+            &crawl.main_title(),
+        );
+        // the visited pages are listed next to the warc files whether the crawl is packaged or not
+        let pages_file = self.warc_writer.crawl_file_path(
+            &archive_info.string_ts(),
+            &domain,
+            self.options.crawl_depth,
+            PAGES_SUFFIX,
+        );
+        package.write_pages(&pages_file)?;
+
+        let wacz_file = if self.options.with_wacz {
+            let path = self.warc_writer.wacz_path(
+                &archive_info.string_ts(),
+                &domain,
+                self.options.crawl_depth,
+            );
+            package
+                .write(&path)
+                .context(format!("could not package crawl of {}", original_url))?;
+            Some(path)
+        } else {
+            None
+        };
+
+        for file in &files {
+            manifest.add_file(file, ArchiveFileKind::Warc)?;
+        }
+        for file in &index_files {
+            manifest.add_file(file, ArchiveFileKind::Index)?;
+        }
+        manifest.add_file(&screenshot_dir, ArchiveFileKind::Screenshot)?;
+        manifest.add_file(&pages_file, ArchiveFileKind::Pages)?;
+        if let Some(wacz) = &wacz_file {
+            manifest.add_file(wacz, ArchiveFileKind::Wacz)?;
+        }
+        let manifest_file = self.warc_writer.crawl_file_path(
+            &archive_info.string_ts(),
+            &domain,
+            self.options.crawl_depth,
+            MANIFEST_SUFFIX,
+        );
+        manifest.write(&manifest_file)?;
+
         Ok(ArchivingResult {
             warc_files: files,
+            index_files,
             screenshot_file: screenshot_dir,
+            wacz_file,
+            pages_file,
+            manifest_file,
             archive_info: archive_info,
             title: crawl.main_title(),
             original_url: original_url.into(),
+            seeds: seeds.to_vec(),
+            budget_exceeded: crawl.budget_exceeded(),
         })
     }
 
-    pub async fn run_upload_crawl(
-        &self,
-        crawl: ArchivingResult,
-    ) -> anyhow::Result<CrawlUploadResult> {
-        if !self.options.with_upload {
-            return Err(anyhow!("with_upload is false"));
+    // fails when the balance or the spending caps leave nothing for the upload of the crawl
+    pub async fn check_upload_funds(&self) -> anyhow::Result<()> {
+        match &self.uploader {
+            Some(u) if self.options.with_upload => {
+                u.check_crawl_funds(self.options.max_bytes).await
+            }
+            _ => Ok(()),
         }
+    }
 
-        match &self.uploader {
-            Some(u) => {
-                let ids = u.upload_crawl_files(&crawl).await?;
+    // the crawl won't be resumed anymore, to be called once it is safely stored
+    pub fn remove_checkpoint(&self) -> anyhow::Result<()> {
+        match &self.checkpoint_path {
+            Some(path) => CrawlCheckpoint::remove(path),
+            None => Ok(()),
+        }
+    }
 
-                return Ok(ids);
+    // stores the crawl with every backend, bundlr first when with_upload is set. Those
+    // failing are logged and left out, the crawl only fails when it was not stored
+    // anywhere or its upload was not verified, in which case it is not submitted
+    pub async fn run_store_crawl(&self, crawl: &ArchivingResult) -> anyhow::Result<StoredCrawl> {
+        let mut backends: Vec<Arc<dyn StorageBackend>> = vec![];
+        if self.options.with_upload {
+            match &self.uploader {
+                Some(u) => backends.push(u.clone()),
+                None => return Err(anyhow!("uploader not defined")),
             }
-            None => Err(anyhow!("uploader not defined")),
         }
+        backends.extend(self.options.storage.iter().cloned());
+
+        let mut locations = vec![];
+        for backend in backends {
+            match backend.store_crawl(crawl).await {
+                Ok(location) => {
+                    info!(
+                        "stored crawl of {} with {} at {}",
+                        crawl.original_url,
+                        backend.name(),
+                        location.url
+                    );
+                    locations.push(location);
+                }
+                Err(e) => error!(
+                    "could not store crawl of {} with {} {:?}",
+                    crawl.original_url,
+                    backend.name(),
+                    e
+                ),
+            }
+        }
+        if locations.is_empty() {
+            return Err(anyhow!(
+                "crawl of {} was not stored anywhere",
+                crawl.original_url
+            ));
+        }
+
+        let mut manifest = ArchiveManifest::load(&crawl.manifest_file)?;
+        manifest.storage = locations.clone();
+        manifest.write(&crawl.manifest_file)?;
+
+        let upload = match &self.uploader {
+            Some(u) if self.options.with_upload => {
+                Some(u.take_verified(&crawl.manifest_file).ok_or_else(|| {
+                    anyhow!("upload of crawl of {} was not verified", crawl.original_url)
+                })?)
+            }
+            _ => None,
+        };
+        Ok(StoredCrawl { upload, locations })
     }
 }
 
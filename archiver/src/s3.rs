@@ -0,0 +1,455 @@
+use std::{collections::BTreeMap, fmt, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{header, Body, Client, Response, Url};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    storage::{crawl_files, crawl_name, StorageBackend, StorageLocation},
+    types::ArchivingResult,
+    utils::{hash_file, to_hex},
+};
+
+pub const DEFAULT_S3_ENDPOINT: &str = "https://s3.amazonaws.com";
+pub const DEFAULT_S3_REGION: &str = "us-east-1";
+// a single put takes at most 5GB, larger files are put in parts of that size
+const PART_SIZE: u64 = 64 * 1024 * 1024;
+// limit of the parts of an object, the parts of larger files are made bigger
+const MAX_PARTS: u64 = 10_000;
+const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+// Endpoint and credentials of an s3 compatible storage
+#[derive(Clone, PartialEq, Eq)]
+pub struct S3Config {
+    // https://s3.amazonaws.com, or the url of a minio server such as http://localhost:9000
+    pub endpoint: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: DEFAULT_S3_ENDPOINT.into(),
+            region: DEFAULT_S3_REGION.into(),
+            access_key_id: String::new(),
+            secret_access_key: String::new(),
+        }
+    }
+}
+
+// the secret key is left out of the logs
+impl fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .finish()
+    }
+}
+
+// Puts the files of every crawl in a bucket, as <prefix>/<crawl name>/<path in the crawl>.
+// Objects are addressed path style, <endpoint>/<bucket>/<key>, which minio understands too
+pub struct S3Storage {
+    client: Client,
+    config: S3Config,
+    endpoint: Url,
+    bucket: String,
+    prefix: String,
+    // files larger than a part are put with a multipart upload
+    part_size: u64,
+}
+
+impl S3Storage {
+    pub fn new(config: S3Config, bucket: &str, prefix: &str) -> anyhow::Result<Self> {
+        if config.access_key_id.is_empty() || config.secret_access_key.is_empty() {
+            return Err(anyhow!("missing credentials of s3 bucket {}", bucket));
+        }
+        let endpoint = Url::from_str(&config.endpoint)
+            .context(format!("invalid s3 endpoint {}", config.endpoint))?;
+        if endpoint.host_str().is_none() {
+            return Err(anyhow!("invalid s3 endpoint {}", config.endpoint));
+        }
+        Ok(S3Storage {
+            client: Client::new(),
+            config,
+            endpoint,
+            bucket: bucket.into(),
+            prefix: prefix.trim_matches('/').into(),
+            part_size: PART_SIZE,
+        })
+    }
+
+    fn key(&self, crawl_name: &str, path: &str) -> String {
+        [self.prefix.as_str(), crawl_name, path]
+            .iter()
+            .filter(|p| !p.is_empty())
+            .cloned()
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+
+    // /<bucket>/<key>, every segment uri encoded
+    fn object_path(&self, key: &str) -> String {
+        std::iter::once(self.bucket.as_str())
+            .chain(key.split('/'))
+            .map(|segment| format!("/{}", urlencoding::encode(segment)))
+            .collect()
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!(
+            "{}{}",
+            self.config.endpoint.trim_end_matches('/'),
+            self.object_path(key)
+        )
+    }
+
+    // request to the object signed with the sha256 of its payload, so the storage checks
+    // it received it whole. The query is given in its canonical form, sorted and encoded
+    async fn send(
+        &self,
+        method: &str,
+        key: &str,
+        query: &str,
+        payload_hash: &str,
+        size: u64,
+        body: Body,
+    ) -> anyhow::Result<Response> {
+        let path = self.object_path(key);
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{}", self.endpoint.host_str().unwrap_or_default(), port),
+            None => self.endpoint.host_str().unwrap_or_default().to_string(),
+        };
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = authorization(
+            &self.config,
+            method,
+            &host,
+            &path,
+            query,
+            payload_hash,
+            &amz_date,
+        );
+        let mut url = self.object_url(key);
+        if !query.is_empty() {
+            url = format!("{}?{}", url, query);
+        }
+
+        let res = self
+            .client
+            .request(method.parse()?, url)
+            .header(header::CONTENT_LENGTH, size)
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", &amz_date)
+            .header(header::AUTHORIZATION, authorization)
+            .timeout(Duration::from_secs(600))
+            .body(body)
+            .send()
+            .await
+            .context(format!("could not reach s3 bucket {}", self.bucket))?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(anyhow!(
+                "s3 bucket {} refused {} {}, status {} {}",
+                self.bucket,
+                method,
+                key,
+                status,
+                res.text().await.unwrap_or_default()
+            ));
+        }
+        Ok(res)
+    }
+
+    // the file is streamed from disk with a single put, or put in parts when larger
+    // than a part
+    pub async fn put_object(&self, key: &str, file: &PathBuf) -> anyhow::Result<()> {
+        let size = tokio::fs::metadata(file)
+            .await
+            .context(format!("could not read metadata of {:?}", file))?
+            .len();
+        if size > self.part_size {
+            return self
+                .put_multipart(key, file, size)
+                .await
+                .context(format!("could not put {:?} in parts", file));
+        }
+
+        let (size, payload_hash) = hash_file(file).await?;
+        let reader = tokio::fs::File::open(file)
+            .await
+            .context(format!("could not open {:?}", file))?;
+        self.send(
+            "PUT",
+            key,
+            "",
+            &payload_hash,
+            size,
+            Body::wrap_stream(ReaderStream::new(reader)),
+        )
+        .await
+        .context(format!("could not put {:?}", file))?;
+        Ok(())
+    }
+
+    // https://docs.aws.amazon.com/AmazonS3/latest/userguide/mpuoverview.html
+    // a failed upload is aborted so the storage drops the parts already put
+    async fn put_multipart(&self, key: &str, file: &PathBuf, size: u64) -> anyhow::Result<()> {
+        let res = self
+            .send(
+                "POST",
+                key,
+                "uploads=",
+                EMPTY_PAYLOAD_HASH,
+                0,
+                Body::from(""),
+            )
+            .await?;
+        let upload_id = match xml_text(&res.text().await?, "UploadId") {
+            Some(id) => id,
+            None => return Err(anyhow!("no upload id for {}", key)),
+        };
+        match self.put_parts(key, file, size, &upload_id).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let query = format!("uploadId={}", urlencoding::encode(&upload_id));
+                if let Err(abort) = self
+                    .send("DELETE", key, &query, EMPTY_PAYLOAD_HASH, 0, Body::from(""))
+                    .await
+                {
+                    warn!("could not abort upload {} of {} {}", upload_id, key, abort);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn put_parts(
+        &self,
+        key: &str,
+        file: &PathBuf,
+        size: u64,
+        upload_id: &str,
+    ) -> anyhow::Result<()> {
+        let part_size = self.part_size.max(size.div_ceil(MAX_PARTS));
+        let mut reader = tokio::fs::File::open(file)
+            .await
+            .context(format!("could not open {:?}", file))?;
+        let mut etags = vec![];
+        let mut offset = 0;
+        while offset < size {
+            let mut part = vec![0; part_size.min(size - offset) as usize];
+            reader.seek(std::io::SeekFrom::Start(offset)).await?;
+            reader
+                .read_exact(&mut part)
+                .await
+                .context(format!("could not read {:?}", file))?;
+            let number = etags.len() + 1;
+            let query = format!(
+                "partNumber={}&uploadId={}",
+                number,
+                urlencoding::encode(upload_id)
+            );
+            let hash = to_hex(&Sha256::digest(&part));
+            let len = part.len() as u64;
+            let res = self
+                .send("PUT", key, &query, &hash, len, Body::from(part))
+                .await?;
+            let etag = match res.headers().get(header::ETAG) {
+                Some(etag) => etag.to_str()?.to_string(),
+                None => return Err(anyhow!("no etag for part {} of {}", number, key)),
+            };
+            etags.push(etag);
+            offset += len;
+        }
+
+        let mut body = "<CompleteMultipartUpload>".to_string();
+        for (i, etag) in etags.iter().enumerate() {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                i + 1,
+                etag
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let query = format!("uploadId={}", urlencoding::encode(upload_id));
+        let hash = to_hex(&Sha256::digest(body.as_bytes()));
+        let len = body.len() as u64;
+        let res = self
+            .send("POST", key, &query, &hash, len, Body::from(body))
+            .await?;
+        // the completion can fail after the storage answered with a 200
+        let text = res.text().await?;
+        if xml_text(&text, "Code").is_some() {
+            return Err(anyhow!("could not complete upload of {} {}", key, text));
+        }
+        debug!("put {:?} in {} parts", file, etags.len());
+        Ok(())
+    }
+}
+
+// text of the first <tag> element of an xml response
+fn xml_text(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].to_string())
+}
+
+#[async_trait]
+impl StorageBackend for S3Storage {
+    fn name(&self) -> &str {
+        "s3"
+    }
+
+    async fn store_crawl(&self, crawl: &ArchivingResult) -> anyhow::Result<StorageLocation> {
+        let name = crawl_name(crawl)?;
+        let mut files = BTreeMap::new();
+        for (path, _, file) in crawl_files(crawl) {
+            let key = self.key(&name, &path);
+            self.put_object(&key, file).await?;
+            debug!("put {:?} in s3 bucket {} as {}", file, self.bucket, key);
+            files.insert(path, self.object_url(&key));
+        }
+        Ok(StorageLocation {
+            backend: self.name().into(),
+            url: format!("s3://{}/{}", self.bucket, self.key(&name, "")),
+            files,
+            verified: None,
+        })
+    }
+}
+
+// AWS signature version 4 of a request, signing its host, payload hash and date headers.
+// The query string is the canonical one, its parameters sorted and encoded
+// https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-header-based-auth.html
+pub fn authorization(
+    config: &S3Config,
+    method: &str,
+    host: &str,
+    path: &str,
+    query: &str,
+    payload_hash: &str,
+    amz_date: &str,
+) -> String {
+    let date = &amz_date[..8.min(amz_date.len())];
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        method, path, query, host, payload_hash, amz_date, signed_headers, payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        to_hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(&config.secret_access_key, date, &config.region, "s3");
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        config.access_key_id,
+        scope,
+        signed_headers,
+        to_hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+    )
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // hmac takes keys of any size
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{mock_s3::MockS3, utils::create_random_tmp_folder};
+
+    use super::*;
+
+    #[test]
+    fn derives_signing_keys() {
+        // example of the aws documentation
+        assert_eq!(
+            to_hex(&signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam"
+            )),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn addresses_objects_path_style() {
+        let config = S3Config {
+            endpoint: "http://localhost:9000/".into(),
+            access_key_id: "minio".into(),
+            secret_access_key: "minio123".into(),
+            ..S3Config::default()
+        };
+        let storage = S3Storage::new(config.clone(), "archives", "/crawls/").unwrap();
+        let key = storage.key("archiver_1_example.com_1", "archive.warc.gz");
+        assert_eq!(key, "crawls/archiver_1_example.com_1/archive.warc.gz");
+        assert_eq!(
+            storage.object_url("a b/c"),
+            "http://localhost:9000/archives/a%20b/c"
+        );
+        assert!(S3Storage::new(S3Config::default(), "archives", "").is_err());
+    }
+
+    #[tokio::test]
+    async fn puts_large_files_in_parts() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut file = dir.clone();
+        file.push("archive.warc.gz");
+        let data = (0..2500).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        std::fs::write(&file, &data).unwrap();
+        let mock = MockS3::start();
+        let mut storage = S3Storage::new(mock.config(), "archives", "").unwrap();
+        storage.part_size = 1000;
+
+        storage
+            .put_object("a/archive.warc.gz", &file)
+            .await
+            .unwrap();
+        assert_eq!(mock.puts(), 3);
+        assert_eq!(mock.object("archives", "a/archive.warc.gz").unwrap(), data);
+        assert_eq!(mock.pending_uploads(), 0);
+
+        // the parts of a failed upload are dropped
+        mock.fail(1);
+        assert!(storage
+            .put_object("b/archive.warc.gz", &file)
+            .await
+            .is_err());
+        assert!(mock.object("archives", "b/archive.warc.gz").is_none());
+        assert_eq!(mock.pending_uploads(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_xml_responses() {
+        let xml = "<InitiateMultipartUploadResult><UploadId>abc</UploadId></InitiateMultipartUploadResult>";
+        assert_eq!(xml_text(xml, "UploadId"), Some("abc".into()));
+        assert_eq!(xml_text(xml, "Code"), None);
+    }
+}
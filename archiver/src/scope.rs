@@ -0,0 +1,290 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use regex::Regex;
+use reqwest::Url;
+
+use crate::cdxj::surt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScopeAction {
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone)]
+pub enum ScopePattern {
+    // * matches within a path segment, ** across segments and ? any single character
+    // (the ? of a query included), a trailing /** also matches the directory itself.
+    // Globs starting with / match the path and query, the others the whole url
+    Glob(Regex),
+    // searched anywhere in the url
+    Regex(Regex),
+    // matches the urls whose SURT starts with the prefix, e.g. com,example)/blog
+    SurtPrefix(String),
+}
+
+// A single include or exclude rule, written as <action>:<kind>:<pattern>,
+// e.g. exclude:regex:[?&]date= or include:glob:/blog/**
+#[derive(Debug, Clone)]
+pub struct ScopeRule {
+    action: ScopeAction,
+    pattern: ScopePattern,
+    source: String,
+}
+
+impl ScopeRule {
+    pub fn action(&self) -> ScopeAction {
+        self.action.clone()
+    }
+
+    pub fn matches(&self, url: &str) -> bool {
+        match &self.pattern {
+            ScopePattern::Glob(r) => {
+                // path globs are anchored at the start of the path
+                if r.as_str().starts_with("^/") {
+                    match Url::parse(url) {
+                        Ok(u) => match u.query() {
+                            Some(q) => r.is_match(&format!("{}?{}", u.path(), q)),
+                            None => r.is_match(u.path()),
+                        },
+                        Err(_) => false,
+                    }
+                } else {
+                    r.is_match(url)
+                }
+            }
+            ScopePattern::Regex(r) => r.is_match(url),
+            ScopePattern::SurtPrefix(p) => match surt(url) {
+                Ok(s) => s.starts_with(p),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+impl FromStr for ScopeRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let (action, kind, pattern) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(a), Some(k), Some(p)) if !p.is_empty() => (a, k, p),
+            _ => {
+                return Err(anyhow!(
+                    "invalid scope rule {}, expected <include|exclude>:<glob|regex|surt>:<pattern>",
+                    s
+                ))
+            }
+        };
+
+        let pattern = match kind {
+            "glob" => ScopePattern::Glob(glob_to_regex(pattern)?),
+            "regex" => ScopePattern::Regex(
+                Regex::new(pattern).context(format!("invalid regex in scope rule {}", s))?,
+            ),
+            "surt" => ScopePattern::SurtPrefix(pattern.to_lowercase()),
+            _ => {
+                return Err(anyhow!(
+                    "invalid scope rule kind {}, expected one of glob, regex, surt",
+                    kind
+                ))
+            }
+        };
+        let action = match action {
+            "include" => ScopeAction::Include,
+            "exclude" => ScopeAction::Exclude,
+            _ => {
+                return Err(anyhow!(
+                    "invalid scope rule action {}, expected include or exclude",
+                    action
+                ))
+            }
+        };
+        Ok(ScopeRule {
+            action,
+            pattern,
+            // we keep the rule as it was written for the reports
+            source: s.into(),
+        })
+    }
+}
+
+impl fmt::Display for ScopeRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+// Number of urls a rule kept out of the crawl
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleRejections {
+    pub rule: String,
+    pub rejected: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScopeReport {
+    pub rules: Vec<RuleRejections>,
+    // urls matching no rule while include rules are set
+    pub unmatched: usize,
+}
+
+impl ScopeReport {
+    pub fn total(&self) -> usize {
+        self.rules.iter().map(|r| r.rejected).sum::<usize>() + self.unmatched
+    }
+}
+
+// Ordered include/exclude rules applied to the links found during a crawl on top of
+// its crawl type, the first matching rule decides. When include rules are set, urls
+// matching none of the rules are out of scope. Seeds are always crawled
+#[derive(Debug, Clone, Default)]
+pub struct ScopeRules {
+    rules: Vec<ScopeRule>,
+    rejections: Vec<usize>,
+    unmatched: usize,
+}
+
+impl ScopeRules {
+    pub fn new(rules: Vec<ScopeRule>) -> Self {
+        ScopeRules {
+            rejections: vec![0; rules.len()],
+            rules,
+            unmatched: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // decides whether the url is in scope and counts the rule that rejected it
+    pub fn allows(&mut self, url: &str) -> bool {
+        if let Some(i) = self.rules.iter().position(|r| r.matches(url)) {
+            if self.rules[i].action == ScopeAction::Exclude {
+                self.rejections[i] += 1;
+                return false;
+            }
+            return true;
+        }
+
+        if self.rules.iter().any(|r| r.action == ScopeAction::Include) {
+            self.unmatched += 1;
+            return false;
+        }
+        true
+    }
+
+    pub fn report(&self) -> ScopeReport {
+        ScopeReport {
+            rules: self
+                .rules
+                .iter()
+                .zip(self.rejections.iter())
+                .map(|(rule, rejected)| RuleRejections {
+                    rule: rule.to_string(),
+                    rejected: *rejected,
+                })
+                .collect(),
+            unmatched: self.unmatched,
+        }
+    }
+}
+
+fn glob_to_regex(glob: &str) -> anyhow::Result<Regex> {
+    let mut re = String::from("^");
+    let (glob, directory) = match glob.strip_suffix("/**") {
+        Some(dir) => (dir, true),
+        None => (glob, false),
+    };
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if directory {
+        re.push_str("(/.*)?");
+    }
+    re.push('$');
+    Regex::new(&re).context(format!("invalid glob {}", glob))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> ScopeRules {
+        ScopeRules::new(rules.iter().map(|r| r.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn applies_the_first_matching_rule() {
+        let mut scope = rules(&[
+            "exclude:regex:/logout",
+            "exclude:glob:/search*",
+            "exclude:surt:com,example)/calendar",
+            "include:glob:/blog/**",
+        ]);
+
+        assert!(scope.allows("https://example.com/blog/2023/01/post"));
+        assert!(scope.allows("https://example.com/blog"));
+        assert!(!scope.allows("https://example.com/blog/logout"));
+        assert!(!scope.allows("https://example.com/search?q=rust"));
+        assert!(!scope.allows("https://www.example.com/calendar/2023"));
+        assert!(!scope.allows("https://example.com/about"));
+
+        let report = scope.report();
+        assert_eq!(report.rules[0].rule, "exclude:regex:/logout");
+        assert_eq!(
+            report
+                .rules
+                .iter()
+                .map(|r| r.rejected)
+                .collect::<Vec<usize>>(),
+            vec![1, 1, 1, 0]
+        );
+        assert_eq!(report.unmatched, 1);
+        assert_eq!(report.total(), 4);
+    }
+
+    #[test]
+    fn matches_globs() {
+        let mut scope = rules(&["include:glob:https://example.com/*/page"]);
+        assert!(scope.allows("https://example.com/a/page"));
+        assert!(!scope.allows("https://example.com/a/b/page"));
+
+        // everything is in scope without include rules
+        let mut scope = rules(&["exclude:glob:/*.pdf"]);
+        assert!(!scope.allows("https://example.com/file.pdf"));
+        assert!(scope.allows("https://example.com/dir/file.pdf"));
+        assert!(scope.allows("https://example.com/"));
+
+        // ? stands for any single character, not only the start of a query
+        let mut scope = rules(&["exclude:glob:/page?"]);
+        assert!(!scope.allows("https://example.com/page2"));
+        assert!(!scope.allows("https://example.com/page?"));
+        assert!(scope.allows("https://example.com/page"));
+        assert!(scope.allows("https://example.com/page10"));
+
+        let mut scope = rules(&["exclude:glob:/blog/**"]);
+        assert!(!scope.allows("https://example.com/blog"));
+        assert!(!scope.allows("https://example.com/blog/"));
+        assert!(scope.allows("https://example.com/blogs"));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert!("exclude:regex:(".parse::<ScopeRule>().is_err());
+        assert!("skip:glob:/a".parse::<ScopeRule>().is_err());
+        assert!("exclude:wildcard:/a".parse::<ScopeRule>().is_err());
+        assert!("exclude:glob".parse::<ScopeRule>().is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{BufRead, BufReader, Read},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use flate2::read::GzDecoder;
+use futures::StreamExt;
+use quick_xml::{events::Event, Reader};
+
+use crate::utils::{APP_NAME, APP_VERSION};
+
+// limits of the sitemap protocol https://www.sitemaps.org/protocol.html
+const MAX_SITEMAP_SIZE: u64 = 50 * 1024 * 1024;
+// sitemap indexes may point to each other, we stop following them after that many
+const MAX_SITEMAPS: usize = 50;
+const SITEMAP_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The locations listed by a sitemap, an index lists other sitemaps
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SitemapEntries {
+    pub sitemaps: Vec<String>,
+    pub urls: Vec<String>,
+}
+
+pub fn parse_sitemap<R: BufRead>(reader: R) -> anyhow::Result<SitemapEntries> {
+    let mut reader = Reader::from_reader(reader);
+    reader.trim_text(true);
+
+    let mut entries = SitemapEntries::default();
+    let mut buf = vec![];
+    // <loc> is either in a <sitemap> of an index or in a <url> of a urlset
+    let mut in_sitemap = false;
+    let mut in_loc = false;
+
+    loop {
+        let loc = match reader
+            .read_event_into(&mut buf)
+            .context("invalid sitemap")?
+        {
+            Event::Start(e) => {
+                match e.local_name().as_ref() {
+                    b"sitemap" => in_sitemap = true,
+                    b"url" => in_sitemap = false,
+                    b"loc" => in_loc = true,
+                    _ => {}
+                }
+                None
+            }
+            Event::End(e) => {
+                if e.local_name().as_ref() == b"loc" {
+                    in_loc = false;
+                }
+                None
+            }
+            Event::Text(t) if in_loc => Some(t.unescape()?.trim().to_string()),
+            Event::CData(c) if in_loc => {
+                Some(String::from_utf8_lossy(&c.into_inner()).trim().to_string())
+            }
+            Event::Eof => break,
+            _ => None,
+        };
+
+        match loc {
+            Some(l) if !l.is_empty() && in_sitemap => entries.sitemaps.push(l),
+            Some(l) if !l.is_empty() => entries.urls.push(l),
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(entries)
+}
+
+// Collects the page urls of a site from its sitemaps, following sitemap indexes
+pub struct SitemapFetcher {
+    client: reqwest::Client,
+    max_urls: usize,
+}
+
+impl SitemapFetcher {
+    pub fn new(max_urls: usize) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent(format!("{}/{}", APP_NAME.as_str(), APP_VERSION.as_str()))
+            .timeout(SITEMAP_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        SitemapFetcher { client, max_urls }
+    }
+
+    // sitemaps listed in robots.txt are read first, then the conventional /sitemap.xml
+    pub async fn urls(&self, origin: &str, robots_sitemaps: &[String]) -> Vec<String> {
+        let mut queue = robots_sitemaps
+            .iter()
+            .cloned()
+            .collect::<VecDeque<String>>();
+        queue.push_back(format!("{}/sitemap.xml", origin));
+
+        let mut fetched = HashSet::new();
+        let mut urls = vec![];
+        let mut seen = HashSet::new();
+
+        while let Some(sitemap) = queue.pop_front() {
+            if urls.len() >= self.max_urls || fetched.len() >= MAX_SITEMAPS {
+                break;
+            }
+            if !fetched.insert(sitemap.clone()) {
+                continue;
+            }
+
+            let entries = match self.fetch(&sitemap).await {
+                Ok(e) => e,
+                Err(e) => {
+                    debug!("could not read sitemap {} {}", sitemap, e);
+                    continue;
+                }
+            };
+            debug!(
+                "sitemap {} lists {} urls and {} sitemaps",
+                sitemap,
+                entries.urls.len(),
+                entries.sitemaps.len()
+            );
+
+            queue.extend(entries.sitemaps);
+            for url in entries.urls {
+                if urls.len() >= self.max_urls {
+                    info!("reached the cap of {} sitemap urls", self.max_urls);
+                    break;
+                }
+                if seen.insert(url.clone()) {
+                    urls.push(url);
+                }
+            }
+        }
+        urls
+    }
+
+    async fn fetch(&self, url: &str) -> anyhow::Result<SitemapEntries> {
+        let res = self.client.get(url).send().await?;
+        if !res.status().is_success() {
+            return Err(anyhow!("{} returned {}", url, res.status()));
+        }
+        if res.content_length().unwrap_or_default() > MAX_SITEMAP_SIZE {
+            debug!(
+                "sitemap {} is larger than the protocol allows, truncating it",
+                url
+            );
+        }
+        // read as it arrives so an oversized sitemap is cut short instead of held in memory
+        let mut stream = res.bytes_stream();
+        let mut body = vec![];
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk.context(format!("could not read sitemap {}", url))?);
+            if body.len() as u64 >= MAX_SITEMAP_SIZE {
+                break;
+            }
+        }
+
+        // .xml.gz sitemaps are served as is, without a content encoding
+        if body.starts_with(&[0x1f, 0x8b]) {
+            let reader = GzDecoder::new(&body[..]).take(MAX_SITEMAP_SIZE);
+            return parse_sitemap(BufReader::new(reader));
+        }
+        parse_sitemap(&body[..body.len().min(MAX_SITEMAP_SIZE as usize)])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    #[test]
+    fn parses_urlsets_and_indexes() {
+        let urlset = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url>
+    <loc>https://example.com/</loc>
+    <lastmod>2023-01-01</lastmod>
+  </url>
+  <url>
+    <loc> https://example.com/page?a=1&amp;b=2 </loc>
+  </url>
+  <url>
+    <loc><![CDATA[https://example.com/cdata]]></loc>
+  </url>
+</urlset>"#;
+        let entries = parse_sitemap(urlset.as_bytes()).unwrap();
+        assert_eq!(
+            entries.urls,
+            vec![
+                "https://example.com/",
+                "https://example.com/page?a=1&b=2",
+                "https://example.com/cdata",
+            ]
+        );
+        assert!(entries.sitemaps.is_empty());
+
+        let index = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap>
+    <loc>https://example.com/sitemap1.xml.gz</loc>
+  </sitemap>
+  <sitemap>
+    <loc>https://example.com/sitemap2.xml</loc>
+  </sitemap>
+</sitemapindex>"#;
+        let entries = parse_sitemap(index.as_bytes()).unwrap();
+        assert_eq!(
+            entries.sitemaps,
+            vec![
+                "https://example.com/sitemap1.xml.gz",
+                "https://example.com/sitemap2.xml",
+            ]
+        );
+        assert!(entries.urls.is_empty());
+    }
+
+    #[test]
+    fn parses_gzip_sitemaps() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder
+            .write_all(b"<urlset><url><loc>https://example.com/a</loc></url></urlset>")
+            .unwrap();
+        let data = encoder.finish().unwrap();
+
+        let entries = parse_sitemap(BufReader::new(GzDecoder::new(&data[..]))).unwrap();
+        assert_eq!(entries.urls, vec!["https://example.com/a"]);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    manifest::{COST_SUFFIX, MANIFEST_SUFFIX},
+    types::ArchiverError,
+    utils::{load_json, write_json},
+};
+
+// Caps on what uploads may cost, in winston. None are set by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpendingLimits {
+    // cost of the upload of a single crawl
+    pub max_crawl_cost: Option<u128>,
+    // cost of all the uploads of a UTC day
+    pub max_daily_cost: Option<u128>,
+}
+
+// Estimated and actual cost of an upload in winston, the actual cost is the one the node
+// reports charging, or the estimate when it does not tell
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadCost {
+    pub estimated: u128,
+    pub actual: u128,
+}
+
+impl UploadCost {
+    // <crawl>.archive.json -> <crawl>.cost.json. Kept apart from the manifest, which is
+    // signed and stored before the cost is known, so every copy of it stays the same
+    pub fn path(manifest_file: &Path) -> PathBuf {
+        let name = manifest_file
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let stem = name.trim_end_matches(MANIFEST_SUFFIX).trim_end_matches('.');
+        manifest_file.with_file_name(format!("{}.{}", stem, COST_SUFFIX))
+    }
+
+    pub fn load(path: &PathBuf) -> anyhow::Result<Option<Self>> {
+        load_json(path, "upload cost", None)
+    }
+
+    pub fn write(&self, path: &PathBuf) -> anyhow::Result<()> {
+        write_json(path, "upload cost", self)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+struct DailySpending {
+    // UTC day, YYYY-MM-DD
+    day: String,
+    spent: u128,
+    // estimates of the uploads in flight, not persisted
+    #[serde(skip)]
+    reserved: u128,
+}
+
+// Keeps track of what the uploads of the day cost, shared by every runner of the process.
+// With a path, the spending of the day survives restarts
+#[derive(Debug)]
+pub struct SpendingLedger {
+    limits: SpendingLimits,
+    path: Option<PathBuf>,
+    state: Mutex<DailySpending>,
+}
+
+impl SpendingLedger {
+    pub fn new(limits: SpendingLimits, path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let mut state = match &path {
+            Some(p) => load_json(p, "spending", None)?.unwrap_or_default(),
+            None => DailySpending::default(),
+        };
+        roll_over(&mut state);
+        Ok(SpendingLedger {
+            limits,
+            path,
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn limits(&self) -> &SpendingLimits {
+        &self.limits
+    }
+
+    // winston spent today, uploads in flight aside
+    pub fn spent_today(&self) -> u128 {
+        let mut state = self.state.lock().unwrap();
+        roll_over(&mut state);
+        state.spent
+    }
+
+    // fails when nothing is left of the daily cap, so no crawl is started that could not
+    // be uploaded
+    pub fn check_available(&self) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        roll_over(&mut state);
+        if let Some(max) = self.limits.max_daily_cost {
+            if state.spent + state.reserved >= max {
+                return Err(ArchiverError::SpendingCapExceeded(format!(
+                    "{} winston of the daily {} already spent",
+                    state.spent + state.reserved,
+                    max
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    // sets the estimate of an upload aside if the caps allow for it,
+    // to be settled or released once the upload is over
+    pub fn reserve(&self, estimate: u128) -> anyhow::Result<()> {
+        if let Some(max) = self.limits.max_crawl_cost {
+            if estimate > max {
+                return Err(ArchiverError::SpendingCapExceeded(format!(
+                    "upload estimated at {} winston, crawls may cost {}",
+                    estimate, max
+                ))
+                .into());
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        roll_over(&mut state);
+        if let Some(max) = self.limits.max_daily_cost {
+            if state.spent + state.reserved + estimate > max {
+                return Err(ArchiverError::SpendingCapExceeded(format!(
+                    "upload estimated at {} winston, {} of the daily {} already spent",
+                    estimate,
+                    state.spent + state.reserved,
+                    max
+                ))
+                .into());
+            }
+        }
+        state.reserved += estimate;
+        Ok(())
+    }
+
+    pub fn release(&self, estimate: u128) {
+        let mut state = self.state.lock().unwrap();
+        state.reserved = state.reserved.saturating_sub(estimate);
+    }
+
+    // the upload went through, what it cost counts against the day
+    pub fn settle(&self, cost: &UploadCost) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.reserved = state.reserved.saturating_sub(cost.estimated);
+        roll_over(&mut state);
+        state.spent += cost.actual;
+        if let Some(path) = &self.path {
+            write_json(path, "spending", &*state)?;
+        }
+        Ok(())
+    }
+}
+
+// the spending starts over every UTC day
+fn roll_over(state: &mut DailySpending) {
+    let today = Utc::now().format("%Y-%m-%d").to_string();
+    if state.day != today {
+        state.day = today;
+        state.spent = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::utils::create_random_tmp_folder;
+
+    use super::*;
+
+    #[test]
+    fn keeps_the_cost_next_to_the_manifest() {
+        let manifest = PathBuf::from("/tmp/archiver_20230101000000_example.com_1.archive.json");
+        assert_eq!(
+            UploadCost::path(&manifest),
+            PathBuf::from("/tmp/archiver_20230101000000_example.com_1.cost.json")
+        );
+    }
+
+    #[test]
+    fn enforces_the_spending_caps() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("spending.json");
+        let limits = SpendingLimits {
+            max_crawl_cost: Some(100),
+            max_daily_cost: Some(250),
+        };
+        let ledger = SpendingLedger::new(limits.clone(), Some(path.clone())).unwrap();
+
+        assert!(ledger.reserve(101).is_err());
+        ledger.reserve(100).unwrap();
+        ledger.reserve(100).unwrap();
+        // the uploads in flight count against the day
+        assert!(ledger.reserve(100).is_err());
+        ledger.release(100);
+        ledger
+            .settle(&UploadCost {
+                estimated: 100,
+                actual: 120,
+            })
+            .unwrap();
+        assert_eq!(ledger.spent_today(), 120);
+        assert!(ledger.reserve(100).is_ok());
+        assert!(ledger.reserve(31).is_err());
+        ledger.check_available().unwrap();
+
+        // the spending of the day is kept across restarts
+        let ledger = SpendingLedger::new(limits, Some(path)).unwrap();
+        assert_eq!(ledger.spent_today(), 120);
+        ledger.reserve(100).unwrap();
+        ledger.reserve(30).unwrap();
+        assert!(ledger.check_available().is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+use std::{collections::BTreeMap, fmt, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    manifest::{ArchiveFileKind, MANIFEST_SUFFIX},
+    path_manifest::crawl_path,
+    s3::{S3Config, S3Storage},
+    types::ArchivingResult,
+};
+
+// Where a crawl was stored, recorded in its manifest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StorageLocation {
+    // name of the backend: bundlr, local or s3
+    pub backend: String,
+    // the crawl as a whole: the gateway url of its path manifest, its directory or its s3 prefix
+    pub url: String,
+    // every file of the crawl by its path in the crawl (archive.warc.gz, screenshot.png...),
+    // the id of its data item on bundlr, its path or url elsewhere
+    pub files: BTreeMap<String, String>,
+    // whether the files were fetched back from bundlr as they were uploaded,
+    // other backends are not checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified: Option<bool>,
+}
+
+// Keeps the files of finished crawls
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    // what the locations of the backend are recorded as
+    fn name(&self) -> &str;
+    // stores every file of the crawl, fails unless all of them were stored
+    async fn store_crawl(&self, crawl: &ArchivingResult) -> anyhow::Result<StorageLocation>;
+}
+
+impl fmt::Debug for dyn StorageBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StorageBackend({})", self.name())
+    }
+}
+
+// Where crawls are copied to besides arweave, as given on the command line:
+// s3://<bucket>/<prefix> or the path of a local directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageTarget {
+    Local(PathBuf),
+    S3 { bucket: String, prefix: String },
+}
+
+impl FromStr for StorageTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(rest) = s.strip_prefix("s3://") {
+            let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+            if bucket.is_empty() {
+                return Err(anyhow!(
+                    "invalid storage target {}, expected s3://<bucket>/<prefix>",
+                    s
+                ));
+            }
+            return Ok(StorageTarget::S3 {
+                bucket: bucket.into(),
+                prefix: prefix.trim_matches('/').into(),
+            });
+        }
+        let path = s.strip_prefix("file://").unwrap_or(s);
+        if path.is_empty() {
+            return Err(anyhow!(
+                "invalid storage target {}, expected a directory",
+                s
+            ));
+        }
+        Ok(StorageTarget::Local(PathBuf::from(path)))
+    }
+}
+
+impl StorageTarget {
+    pub fn backend(&self, s3: &S3Config) -> anyhow::Result<Box<dyn StorageBackend>> {
+        match self {
+            StorageTarget::Local(dir) => Ok(Box::new(LocalStorage::new(dir.clone()))),
+            StorageTarget::S3 { bucket, prefix } => {
+                Ok(Box::new(S3Storage::new(s3.clone(), bucket, prefix)?))
+            }
+        }
+    }
+}
+
+// Copies the files of every crawl to <dir>/<crawl name>/<path in the crawl>
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(dir: PathBuf) -> Self {
+        LocalStorage { dir }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    async fn store_crawl(&self, crawl: &ArchivingResult) -> anyhow::Result<StorageLocation> {
+        let mut dir = self.dir.clone();
+        dir.push(crawl_name(crawl)?);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .context(format!("could not create {:?}", dir))?;
+
+        let mut files = BTreeMap::new();
+        for (path, _, file) in crawl_files(crawl) {
+            let mut dest = dir.clone();
+            dest.push(&path);
+            // copied next to its destination then renamed, a copy is never left half written
+            let mut tmp = dir.clone();
+            tmp.push(format!("{}.tmp", path));
+            tokio::fs::copy(file, &tmp)
+                .await
+                .context(format!("could not copy {:?} to {:?}", file, tmp))?;
+            tokio::fs::rename(&tmp, &dest)
+                .await
+                .context(format!("could not move {:?} to {:?}", tmp, dest))?;
+            files.insert(path, dest.to_string_lossy().to_string());
+        }
+        Ok(StorageLocation {
+            backend: self.name().into(),
+            url: dir.to_string_lossy().to_string(),
+            files,
+            verified: None,
+        })
+    }
+}
+
+// every file of the crawl with its path in the crawl, the same files as in its bundle:
+// the wacz, or the warc files and their indexes, then the screenshot, the pages and the manifest
+pub fn crawl_files(crawl: &ArchivingResult) -> Vec<(String, Option<ArchiveFileKind>, &PathBuf)> {
+    let mut files = vec![];
+    match &crawl.wacz_file {
+        // the wacz already contains every warc file and index of the crawl
+        Some(wacz) => files.push((Some(ArchiveFileKind::Wacz), vec![wacz])),
+        None => {
+            files.push((
+                Some(ArchiveFileKind::Warc),
+                crawl.warc_files.iter().collect(),
+            ));
+            files.push((
+                Some(ArchiveFileKind::Index),
+                crawl.index_files.iter().collect(),
+            ));
+        }
+    }
+    files.push((
+        Some(ArchiveFileKind::Screenshot),
+        vec![&crawl.screenshot_file],
+    ));
+    files.push((Some(ArchiveFileKind::Pages), vec![&crawl.pages_file]));
+    files.push((None, vec![&crawl.manifest_file]));
+
+    files
+        .into_iter()
+        .flat_map(|(kind, paths)| {
+            paths
+                .into_iter()
+                .enumerate()
+                .map(move |(n, file)| (crawl_path(kind.as_ref(), n), kind.clone(), file))
+        })
+        .collect()
+}
+
+// archiver_<ts>_<url>_<depth>, what the files of the crawl are named after
+pub fn crawl_name(crawl: &ArchivingResult) -> anyhow::Result<String> {
+    crawl
+        .manifest_file
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .map(|n| match n.strip_suffix(&format!(".{}", MANIFEST_SUFFIX)) {
+            Some(name) => name.to_string(),
+            None => n,
+        })
+        .ok_or_else(|| anyhow!("invalid manifest path {:?}", crawl.manifest_file))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::{
+        manifest::ArchiveManifest, mock_s3::MockS3, types::ArchiveInfo,
+        utils::create_random_tmp_folder,
+    };
+
+    use super::*;
+
+    fn crawl(dir: &Path) -> ArchivingResult {
+        let file = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            path
+        };
+        let manifest =
+            ArchiveManifest::new("example.com", "https://example.com/", 1674662517, 1, "", "");
+        ArchivingResult {
+            warc_files: vec![file("a.warc.gz", b"warc a"), file("b.warc.gz", b"warc b")],
+            index_files: vec![file("a.cdxj", b"index a"), file("b.cdxj", b"index b")],
+            screenshot_file: file("screenshot.png", b"png"),
+            wacz_file: None,
+            pages_file: file("a.pages.jsonl", b"{}"),
+            manifest_file: file("archiver_1_example.com_1.archive.json", b"{}"),
+            archive_info: ArchiveInfo::from_manifest(&manifest).unwrap(),
+            title: String::new(),
+            original_url: "https://example.com/".into(),
+            seeds: vec!["https://example.com/".into()],
+            budget_exceeded: None,
+        }
+    }
+
+    #[test]
+    fn parses_storage_targets() {
+        assert_eq!(
+            StorageTarget::from_str("s3://archives/crawls/2023/").unwrap(),
+            StorageTarget::S3 {
+                bucket: "archives".into(),
+                prefix: "crawls/2023".into()
+            }
+        );
+        assert_eq!(
+            StorageTarget::from_str("s3://archives").unwrap(),
+            StorageTarget::S3 {
+                bucket: "archives".into(),
+                prefix: "".into()
+            }
+        );
+        assert_eq!(
+            StorageTarget::from_str("file:///var/archives").unwrap(),
+            StorageTarget::Local(PathBuf::from("/var/archives"))
+        );
+        assert!(StorageTarget::from_str("s3:///crawls").is_err());
+    }
+
+    #[tokio::test]
+    async fn copies_crawls_to_a_directory() {
+        let dir = create_random_tmp_folder().unwrap();
+        let crawl = crawl(&dir);
+        let mut store = dir.clone();
+        store.push("store");
+
+        let location = LocalStorage::new(store.clone())
+            .store_crawl(&crawl)
+            .await
+            .unwrap();
+        assert_eq!(location.backend, "local");
+        assert!(location.url.ends_with("archiver_1_example.com_1"));
+        assert_eq!(
+            location.files.keys().collect::<Vec<&String>>(),
+            vec![
+                "archive-1.warc.gz",
+                "archive.json",
+                "archive.warc.gz",
+                "index-1.cdxj",
+                "index.cdxj",
+                "pages.jsonl",
+                "screenshot.png"
+            ]
+        );
+        assert_eq!(
+            std::fs::read(&location.files["archive-1.warc.gz"]).unwrap(),
+            b"warc b"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn puts_crawls_in_s3_buckets() {
+        let dir = create_random_tmp_folder().unwrap();
+        let crawl = crawl(&dir);
+        let storage = MockS3::start();
+        let backend = StorageTarget::from_str("s3://archives/crawls")
+            .unwrap()
+            .backend(&storage.config())
+            .unwrap();
+
+        let location = backend.store_crawl(&crawl).await.unwrap();
+        assert_eq!(location.backend, "s3");
+        assert_eq!(
+            location.url,
+            "s3://archives/crawls/archiver_1_example.com_1"
+        );
+        assert_eq!(location.files.len(), 7);
+        assert_eq!(storage.puts(), 7);
+        assert_eq!(
+            location.files["index-1.cdxj"],
+            format!(
+                "{}/archives/crawls/archiver_1_example.com_1/index-1.cdxj",
+                storage.config().endpoint
+            )
+        );
+        assert_eq!(
+            storage
+                .object("archives", "crawls/archiver_1_example.com_1/index-1.cdxj")
+                .unwrap(),
+            b"index b"
+        );
+
+        // puts signed with other credentials are refused
+        let config = S3Config {
+            secret_access_key: "wrong".into(),
+            ..storage.config()
+        };
+        let backend = S3Storage::new(config, "archives", "").unwrap();
+        assert!(backend.store_crawl(&crawl).await.is_err());
+
+        storage.fail(1);
+        assert!(StorageTarget::from_str("s3://archives")
+            .unwrap()
+            .backend(&storage.config())
+            .unwrap()
+            .store_crawl(&crawl)
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+
+use atw::state::ArchiveSubmission;
+use serde::{Deserialize, Serialize};
+use urlencoding::encode;
+
+use crate::utils::{load_json, remove_json, write_json};
+
+pub const SUBMISSIONS_VERSION: u8 = 1;
+
+// Submissions of an uploaded crawl the contract has not accepted yet. They are
+// recorded before submitting so the next run of the request only submits the
+// seeds left over instead of crawling again and submitting twice
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingSubmissions {
+    pub version: u8,
+    pub request_id: String,
+    // one per seed, all pointing to the same crawl files
+    pub submissions: Vec<ArchiveSubmission>,
+}
+
+impl PendingSubmissions {
+    pub fn new(request_id: &str, submissions: Vec<ArchiveSubmission>) -> Self {
+        PendingSubmissions {
+            version: SUBMISSIONS_VERSION,
+            request_id: request_id.into(),
+            submissions,
+        }
+    }
+
+    // <submissions dir>/<request id>.json
+    pub fn path(dir: &Path, request_id: &str) -> PathBuf {
+        dir.join(format!("{}.json", encode(request_id)))
+    }
+
+    // a missing file means every submission went through
+    pub fn load(path: &PathBuf) -> anyhow::Result<Option<Self>> {
+        load_json(path, "submissions", Some(SUBMISSIONS_VERSION))
+    }
+
+    pub fn write(&self, path: &PathBuf) -> anyhow::Result<()> {
+        write_json(path, "submissions", self)
+    }
+
+    pub fn remove(path: &PathBuf) -> anyhow::Result<()> {
+        remove_json(path, "submissions")
+    }
+}
@@ -1,8 +1,14 @@
-use std::{collections::HashSet, path::PathBuf};
-
-use crate::utils::FORMAT_STRING;
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::PathBuf,
+};
+
+use crate::{
+    budget::BudgetLimit, manifest::ArchiveManifest, scope::ScopeReport, spending::UploadCost,
+    storage::StorageLocation, utils::FORMAT_STRING,
+};
 use anyhow::anyhow;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -12,12 +18,44 @@ pub enum ArchiverError {
     ContractInteractionError(String),
     #[error("early_termination")]
     EarlyTermination,
+    #[error("empty_request: archive request {0} has no urls to archive")]
+    EmptyRequest(String),
+    #[error("insufficient_funds: {needed} winston needed, {balance} available")]
+    InsufficientFunds { needed: u128, balance: u128 },
+    #[error("spending_cap_exceeded: {0}")]
+    SpendingCapExceeded(String),
+    #[error("verification_failed: {0}")]
+    VerificationFailed(String),
 }
 
-#[derive(Debug)]
+// Ids of the data items of an uploaded crawl, all nested in one bundle
+#[derive(Debug, Clone)]
 pub struct CrawlUploadResult {
+    // the bundle holding every file of the crawl
+    pub bundle_id: String,
     pub screenshot_id: String,
     pub warc_id: Vec<String>,
+    // cdxj index of each warc file, none when the crawl was packaged
+    pub index_id: Vec<String>,
+    // set when the crawl was packaged, the warc files are then only uploaded within it
+    pub wacz_id: Option<String>,
+    pub pages_id: String,
+    // archive.json describing the crawl
+    pub archive_manifest_id: String,
+    // arweave/paths manifest mapping stable paths to the other items, a crawl is
+    // addressed as <manifest id>/archive.warc.gz on any gateway
+    pub manifest_id: String,
+    pub cost: UploadCost,
+    // size and sha256 of the data of the bundle as it was uploaded, the nested items
+    // are only served within it
+    pub bundle_digest: (u64, String),
+}
+
+// Every location of a stored crawl, with the ids of its upload when it was uploaded to bundlr
+#[derive(Debug, Clone)]
+pub struct StoredCrawl {
+    pub upload: Option<CrawlUploadResult>,
+    pub locations: Vec<StorageLocation>,
 }
 
 #[derive(Debug)]
@@ -26,6 +64,12 @@ pub struct CrawlResult {
     main_title: String,
     visited: HashSet<String>,
     failed: HashSet<String>,
+    // disallowed by robots.txt
+    skipped: HashSet<String>,
+    // how many links each scope rule rejected
+    scope: ScopeReport,
+    // set when the crawl was cut short, it is then partial
+    budget_exceeded: Option<BudgetLimit>,
 }
 
 impl CrawlResult {
@@ -34,12 +78,18 @@ impl CrawlResult {
         main_title: String,
         visited: HashSet<String>,
         failed: HashSet<String>,
+        skipped: HashSet<String>,
+        scope: ScopeReport,
+        budget_exceeded: Option<BudgetLimit>,
     ) -> Self {
         CrawlResult {
             url,
             main_title,
-            visited: visited,
-            failed: failed,
+            visited,
+            failed,
+            skipped,
+            scope,
+            budget_exceeded,
         }
     }
 
@@ -55,15 +105,37 @@ impl CrawlResult {
     pub fn failed(&self) -> HashSet<String> {
         self.failed.clone()
     }
+    pub fn skipped(&self) -> HashSet<String> {
+        self.skipped.clone()
+    }
+    pub fn scope(&self) -> ScopeReport {
+        self.scope.clone()
+    }
+    pub fn budget_exceeded(&self) -> Option<BudgetLimit> {
+        self.budget_exceeded.clone()
+    }
+    pub fn is_partial(&self) -> bool {
+        self.budget_exceeded.is_some()
+    }
 }
 
 #[derive(Debug)]
 pub struct ArchivingResult {
     pub warc_files: Vec<PathBuf>,
+    // sorted cdxj index written next to each warc file
+    pub index_files: Vec<PathBuf>,
     pub screenshot_file: PathBuf,
+    pub wacz_file: Option<PathBuf>,
+    // pages.jsonl of the visited pages
+    pub pages_file: PathBuf,
+    pub manifest_file: PathBuf,
     pub archive_info: ArchiveInfo,
     pub title: String,
     pub original_url: String,
+    // every url the crawl started from, original_url being the first one
+    pub seeds: Vec<String>,
+    // set when a budget ended the crawl before it was complete
+    pub budget_exceeded: Option<BudgetLimit>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -78,6 +150,59 @@ pub struct BundlrUploadID {
     pub max: usize,
 }
 
+// chunks of an ongoing upload the node already holds, as [offset, size] pairs
+// with the offsets either as numbers or strings
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BundlrReceivedChunks(pub Vec<(serde_json::Value, u64)>);
+
+impl BundlrReceivedChunks {
+    pub fn offsets(&self) -> anyhow::Result<BTreeSet<u64>> {
+        self.0
+            .iter()
+            .map(|(offset, _)| match offset {
+                serde_json::Value::Number(n) => n.as_u64(),
+                serde_json::Value::String(s) => s.parse().ok(),
+                _ => None,
+            })
+            .map(|offset| offset.ok_or_else(|| anyhow!("invalid chunk offset")))
+            .collect()
+    }
+}
+
+// Answer of a graphql query for the tags of transactions, as served by
+// bundlr nodes and arweave gateways
+#[derive(Deserialize, Debug)]
+pub struct TransactionsQuery {
+    pub data: TransactionsData,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransactionsData {
+    pub transactions: TransactionConnection,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransactionConnection {
+    pub edges: Vec<TransactionEdge>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransactionEdge {
+    pub node: TransactionNode,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransactionNode {
+    pub id: String,
+    pub tags: Vec<TransactionTag>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct TransactionTag {
+    pub name: String,
+    pub value: String,
+}
+
 #[derive(Debug)]
 pub struct ArchiveInfo {
     depth: u8,
@@ -86,8 +211,27 @@ pub struct ArchiveInfo {
 }
 
 impl ArchiveInfo {
+    // reads the sidecar manifest of a warc file, archives made before
+    // manifests existed only have their file name to go by
     pub fn new(file: &PathBuf) -> anyhow::Result<Self> {
-        Self::get_archive_information_from_name(file)
+        let manifest = ArchiveManifest::sidecar_path(file);
+        if manifest.exists() {
+            return Self::from_manifest(&ArchiveManifest::load(&manifest)?);
+        }
+        debug!("no manifest found for {:?}, parsing its name", file);
+        Self::from_name(file)
+    }
+
+    pub fn from_manifest(manifest: &ArchiveManifest) -> anyhow::Result<Self> {
+        let timestamp = match NaiveDateTime::from_timestamp_opt(manifest.timestamp, 0) {
+            Some(ts) => ts,
+            None => return Err(anyhow!("invalid manifest timestamp {}", manifest.timestamp)),
+        };
+        Ok(ArchiveInfo {
+            depth: manifest.depth,
+            timestamp,
+            url: manifest.url.clone(),
+        })
     }
 
     pub fn depth(&self) -> u8 {
@@ -102,30 +246,42 @@ impl ArchiveInfo {
         self.timestamp.timestamp()
     }
 
+    pub fn datetime(&self) -> DateTime<Utc> {
+        DateTime::<Utc>::from_utc(self.timestamp, Utc)
+    }
+
     pub fn string_ts(&self) -> String {
         self.timestamp.format(FORMAT_STRING).to_string()
     }
 
-    fn get_archive_information_from_name(filename: &PathBuf) -> anyhow::Result<ArchiveInfo> {
+    // archiver_<ts>_<url>_<depth>.<ext>, as named by the warc writer
+    pub fn from_name(filename: &PathBuf) -> anyhow::Result<ArchiveInfo> {
         let file_path = PathBuf::from(filename);
         let name = match file_path.file_name() {
             Some(n) => n.to_str().unwrap(),
             None => return Err(anyhow!("invalid file path {:?}", file_path)),
         };
 
-        //archiver_<ts>_<url>_<depth>.warc.gz
+        //archiver_<ts>_<url>_<depth>.warc.gz, the url may contain underscores
         let elems = name.split("_").collect::<Vec<&str>>();
+        if elems.len() < 4 || elems[0] != "archiver" {
+            return Err(anyhow!("invalid archive file name {}", name));
+        }
 
-        let depth: u8 = elems[3].split_once(".").unwrap().0.parse()?;
+        let last = elems[elems.len() - 1];
+        let depth: u8 = match last.split_once(".") {
+            Some((depth, _)) => depth.parse()?,
+            None => last.parse()?,
+        };
 
         let ts = NaiveDateTime::parse_from_str(elems[1], FORMAT_STRING)?;
 
-        let url = elems[2];
+        let url = elems[2..elems.len() - 1].join("_");
 
         Ok(ArchiveInfo {
             depth: depth,
             timestamp: ts,
-            url: url.into(),
+            url,
         })
     }
 }
@@ -215,3 +371,40 @@ impl BrowsingResult {
         self.links.clone()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{manifest::ArchiveManifest, utils::create_random_tmp_folder};
+
+    use super::*;
+
+    #[test]
+    fn parses_legacy_archive_names() {
+        let info = ArchiveInfo::new(&PathBuf::from(
+            "/tmp/archiver_20230125160157_my_site.example.com_2.warc.gz",
+        ))
+        .unwrap();
+        assert_eq!(info.url(), "my_site.example.com");
+        assert_eq!(info.depth(), 2);
+        assert_eq!(info.string_ts(), "20230125160157");
+
+        assert!(ArchiveInfo::new(&PathBuf::from("/tmp/random.warc.gz")).is_err());
+        assert!(ArchiveInfo::new(&PathBuf::from("/tmp/archiver_2023_a.warc.gz")).is_err());
+    }
+
+    #[test]
+    fn prefers_the_manifest() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut warc = dir.clone();
+        warc.push("archiver_20230125160157_example.com_1.warc.gz");
+        ArchiveManifest::new("example.com", "https://example.com", 0, 3, "DomainOnly", "")
+            .write(&ArchiveManifest::sidecar_path(&warc))
+            .unwrap();
+
+        let info = ArchiveInfo::new(&warc).unwrap();
+        assert_eq!(info.depth(), 3);
+        assert_eq!(info.unix_ts(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
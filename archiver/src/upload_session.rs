@@ -0,0 +1,110 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    ans104::SignedDataItem,
+    utils::{get_unix_timestamp, load_json, remove_json, write_json},
+};
+
+pub const UPLOAD_SESSION_VERSION: u8 = 1;
+
+// A file a data item is read from, as it was when the item was signed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    // modification time in unix seconds
+    pub modified: u64,
+}
+
+// State of the chunked upload of a data item read from files, written as its chunks are
+// acknowledged so an upload cut short continues with the same signed data item instead
+// of starting over
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadSession {
+    pub version: u8,
+    // the signed item, its data is read again from the files
+    pub item: SignedDataItem,
+    pub files: Vec<SignedFile>,
+    // upload id given by the node, none until the first chunk is sent
+    pub upload_id: Option<String>,
+    pub chunk_size: Option<usize>,
+    // offsets of the chunks the node acknowledged
+    pub acknowledged: BTreeSet<u64>,
+    // unix timestamp in seconds of the start of the upload
+    pub started_at: i64,
+    // unix timestamp in seconds of the last write of the session
+    pub updated_at: i64,
+}
+
+impl UploadSession {
+    pub fn new(item: &SignedDataItem) -> anyhow::Result<Self> {
+        let files = item
+            .data()
+            .files()
+            .iter()
+            .map(SignedFile::new)
+            .collect::<anyhow::Result<Vec<SignedFile>>>()?;
+        let now = get_unix_timestamp().as_secs() as i64;
+        Ok(UploadSession {
+            version: UPLOAD_SESSION_VERSION,
+            item: item.clone(),
+            files,
+            upload_id: None,
+            chunk_size: None,
+            acknowledged: BTreeSet::new(),
+            started_at: now,
+            updated_at: now,
+        })
+    }
+
+    // <session dir>/<sha256 of the key>.json, the key being what the upload is of,
+    // such as the path of the file
+    pub fn path(dir: &Path, key: &str) -> PathBuf {
+        let key = URL_SAFE_NO_PAD.encode(Sha256::digest(key.as_bytes()));
+        dir.join(format!("{}.json", key))
+    }
+
+    // a missing session means there is nothing to resume
+    pub fn load(path: &PathBuf) -> anyhow::Result<Option<Self>> {
+        load_json(path, "upload session", Some(UPLOAD_SESSION_VERSION))
+    }
+
+    pub fn write(&mut self, path: &PathBuf) -> anyhow::Result<()> {
+        self.updated_at = get_unix_timestamp().as_secs() as i64;
+        write_json(path, "upload session", self)
+    }
+
+    pub fn remove(path: &PathBuf) -> anyhow::Result<()> {
+        remove_json(path, "upload session")
+    }
+
+    // false once one of the files was changed since the item was signed,
+    // its upload has to start over
+    pub fn is_current(&self) -> bool {
+        self.files
+            .iter()
+            .all(|f| SignedFile::new(&f.path).is_ok_and(|current| current == *f))
+    }
+}
+
+impl SignedFile {
+    fn new(path: &PathBuf) -> anyhow::Result<Self> {
+        let metadata =
+            fs::metadata(path).context(format!("could not read metadata of {:?}", path))?;
+        Ok(SignedFile {
+            path: path.clone(),
+            size: metadata.len(),
+            modified: metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs(),
+        })
+    }
+}
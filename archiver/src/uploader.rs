@@ -2,25 +2,37 @@ use arloader::{
     transaction::{FromUtf8Strs, Tag},
     Arweave,
 };
-use futures::StreamExt;
-use itertools::Itertools;
+use futures::{future, Future, StreamExt, TryFutureExt};
+use sha2::{Digest, Sha256};
 use std::{
-    path::PathBuf,
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::{self, Arc},
+    sync::{self, Arc, Mutex},
     time::Duration,
 };
-use tokio::fs;
-use tokio_retry::{strategy::FixedInterval, Retry};
+use tokio_retry::{strategy::ExponentialBackoff, Retry, RetryIf};
 
 use anyhow::{anyhow, Context};
+use async_trait::async_trait;
 use reqwest::{Client, Url};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    types::{ArchivingResult, BundlrUploadID, CrawlUploadResult},
+    ans104::{DataSource, SignedDataItem, BUNDLE_FORMAT, BUNDLE_VERSION},
+    budget::BudgetLimit,
+    config::ArchiverConfig,
+    manifest::ArchiveFileKind,
+    path_manifest::{crawl_path, PathManifest, PATH_MANIFEST_CONTENT_TYPE},
+    spending::{SpendingLedger, UploadCost},
+    storage::{crawl_files, StorageBackend, StorageLocation},
+    types::{
+        ArchiverError, ArchivingResult, BundlrBalance, BundlrReceivedChunks, BundlrUploadID,
+        CrawlUploadResult, TransactionTag, TransactionsQuery,
+    },
+    upload_session::UploadSession,
     utils::{
-        assert_stream_send, jitter, APP_NAME, APP_VERSION, BUNDLR_URL, CHUNKING_THRESHOLD,
+        assert_stream_send, jitter, to_hex, CHUNKING_THRESHOLD, WACZ_APPLICATION_TYPE,
         WARC_APPLICATION_TYPE,
     },
 };
@@ -29,16 +41,50 @@ pub struct Uploader {
     _currency: String,
     arweave: Arweave,
     client: sync::Arc<reqwest::Client>,
+    // node the data items are posted to and the names they are tagged with
+    config: ArchiverConfig,
+    // data items of that many bytes or more are uploaded in chunks
+    chunking_threshold: usize,
+    // saves the progress of chunked uploads there so they can be resumed,
+    // uploads start over without it
+    session_dir: Option<PathBuf>,
+    // number of times a request is retried
+    retries: usize,
+    // delay before the first retry in milliseconds, doubled on each attempt
+    retry_delay: u64,
+    // spending caps shared with the other uploaders, uploads are only checked
+    // against the balance without it
+    spending: Option<Arc<SpendingLedger>>,
+    // number of times an uploaded item is fetched again before giving up on it,
+    // new items take a while to be served
+    verify_retries: usize,
+    // delay before fetching an item again in milliseconds, doubled on each attempt
+    verify_delay: u64,
+    // ids of the crawls stored and verified, by manifest file, until they are submitted
+    verified: Mutex<HashMap<PathBuf, CrawlUploadResult>>,
 }
 
+// what the locations of the uploads are recorded as
+const BUNDLR_BACKEND: &str = "bundlr";
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+// number of chunks acknowledged between two writes of an upload session
+const SESSION_WRITE_INTERVAL: usize = 10;
+
 #[derive(Serialize, Deserialize, Debug)]
 struct BundlrRes {
     id: String,
     timestamp: u64,
+    // winston charged for the data item
+    #[serde(default)]
+    winc: Option<String>,
 }
 
 impl Uploader {
-    pub async fn new(key_path: PathBuf, currency: &str) -> anyhow::Result<Self> {
+    pub async fn new(
+        key_path: PathBuf,
+        currency: &str,
+        config: &ArchiverConfig,
+    ) -> anyhow::Result<Self> {
         if currency != "arweave" {
             return Err(anyhow!("arweave currently the only supported currency"));
         }
@@ -49,38 +95,229 @@ impl Uploader {
                 key_path.to_str().unwrap()
             ));
         }
-        let arweave = Arweave::from_keypair_path(
-            key_path.clone(),
-            Url::from_str("https://arweave.net").unwrap(),
-        )
-        .await?;
+        let gateway = Url::from_str(&config.gateway_url)
+            .context(format!("invalid arweave gateway {}", config.gateway_url))?;
+        let arweave = Arweave::from_keypair_path(key_path.clone(), gateway).await?;
 
         Ok(Uploader {
             _currency: currency.to_string(),
             arweave,
             client: Arc::new(Client::new()),
+            config: config.clone(),
+            chunking_threshold: CHUNKING_THRESHOLD,
+            session_dir: None,
+            retries: 6,
+            retry_delay: 200,
+            spending: None,
+            verify_retries: 8,
+            verify_delay: 2000,
+            verified: Mutex::new(HashMap::new()),
         })
     }
 
+    pub fn with_chunking_threshold(mut self, chunking_threshold: usize) -> Self {
+        self.chunking_threshold = chunking_threshold;
+        self
+    }
+
+    pub fn with_session_dir(mut self, session_dir: Option<PathBuf>) -> Self {
+        self.session_dir = session_dir;
+        self
+    }
+
+    pub fn with_retries(mut self, retries: usize, retry_delay: u64) -> Self {
+        self.retries = retries;
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn with_verification(mut self, verify_retries: usize, verify_delay: u64) -> Self {
+        self.verify_retries = verify_retries;
+        self.verify_delay = verify_delay;
+        self
+    }
+
+    pub fn with_spending(mut self, spending: Option<Arc<SpendingLedger>>) -> Self {
+        self.spending = spending;
+        self
+    }
+
+    // funds of the wallet on the bundlr node
+    pub async fn balance(&self) -> anyhow::Result<BundlrBalance> {
+        let address = self.arweave.crypto.wallet_address()?.to_string();
+        let res = self
+            .client
+            .get(format!(
+                "{}/account/balance/arweave?address={}",
+                self.config.bundlr_url, address
+            ))
+            .send()
+            .await
+            .context(format!("could not fetch balance of {}", address))?;
+        if !res.status().is_success() {
+            return Err(anyhow!(
+                "could not fetch balance of {}, status {}",
+                address,
+                res.status()
+            ));
+        }
+        res.json::<BundlrBalance>()
+            .await
+            .context("could not parse BundlrBalance")
+    }
+
+    pub async fn balance_winston(&self) -> anyhow::Result<u128> {
+        let balance = self.balance().await?.balance;
+        balance
+            .parse::<u128>()
+            .context(format!("invalid balance {}", balance))
+    }
+
+    // winston the node charges for a data item of that many bytes
+    pub async fn price(&self, bytes: u64) -> anyhow::Result<u128> {
+        let res = self
+            .client
+            .get(format!(
+                "{}/price/arweave/{}",
+                self.config.bundlr_url, bytes
+            ))
+            .send()
+            .await
+            .context(format!("could not fetch price of {} bytes", bytes))?;
+        if !res.status().is_success() {
+            return Err(anyhow!(
+                "could not fetch price of {} bytes, status {}",
+                bytes,
+                res.status()
+            ));
+        }
+        let price = res.text().await?;
+        price
+            .trim()
+            .parse::<u128>()
+            .context(format!("invalid price {}", price))
+    }
+
+    // checked before a crawl so none is started that could not be uploaded,
+    // with max_bytes the balance has to cover an upload of that size
+    pub async fn check_crawl_funds(&self, max_bytes: Option<u64>) -> anyhow::Result<()> {
+        if let Some(spending) = &self.spending {
+            spending.check_available()?;
+        }
+        let needed = match max_bytes {
+            Some(bytes) => self.price(bytes).await?,
+            None => 0,
+        };
+        let balance = self.balance_winston().await?;
+        if balance == 0 || balance < needed {
+            return Err(ArchiverError::InsufficientFunds { needed, balance }.into());
+        }
+        Ok(())
+    }
+
+    // every file of the crawl is a data item nested in a single bundle, uploaded at once
     pub async fn upload_crawl_files(
         &self,
         archiving_result: &ArchivingResult,
     ) -> anyhow::Result<CrawlUploadResult> {
-        let mut warc_file_ids = vec![];
-        // TODO make these recursive bundles
+        let key = archiving_result.manifest_file.to_string_lossy().to_string();
+        let (bundle, cost) = self
+            .upload_resumable(&key, self.sign_crawl_bundle(archiving_result))
+            .await
+            .context(format!(
+                "could not upload crawl of {}",
+                archiving_result.original_url
+            ))?;
+        info!(
+            "uploaded crawl of {} as bundle {}, estimated at {} winston, charged {}",
+            archiving_result.original_url, bundle.id, cost.estimated, cost.actual
+        );
+        if let Err(e) = cost.write(&UploadCost::path(&archiving_result.manifest_file)) {
+            warn!(
+                "could not record the upload cost of {} {}",
+                archiving_result.original_url, e
+            );
+        }
+        let bundle_digest = bundle.data().sha256().await?;
+        crawl_upload_result(&bundle, archiving_result, cost, bundle_digest)
+    }
 
-        for file_path in &archiving_result.warc_files {
-            let file_tx_id = self.upload_warc(file_path, &archiving_result).await?;
-            warc_file_ids.push(file_tx_id);
+    // the crawl is served by the gateway under the id of its path manifest
+    pub fn location(&self, ids: &CrawlUploadResult, crawl: &ArchivingResult) -> StorageLocation {
+        let mut ids_by_file = vec![
+            (&crawl.screenshot_file, &ids.screenshot_id),
+            (&crawl.pages_file, &ids.pages_id),
+            (&crawl.manifest_file, &ids.archive_manifest_id),
+        ];
+        ids_by_file.extend(crawl.warc_files.iter().zip(&ids.warc_id));
+        ids_by_file.extend(crawl.index_files.iter().zip(&ids.index_id));
+        if let (Some(file), Some(id)) = (&crawl.wacz_file, &ids.wacz_id) {
+            ids_by_file.push((file, id));
         }
-        let screenshot_id = self
-            .upload_screenshot(&archiving_result.screenshot_file, &archiving_result)
-            .await?;
+        let files = crawl_files(crawl)
+            .into_iter()
+            .filter_map(|(path, _, file)| {
+                ids_by_file
+                    .iter()
+                    .find(|(f, _)| *f == file)
+                    .map(|(_, id)| (path, id.to_string()))
+            })
+            .collect();
+        StorageLocation {
+            backend: BUNDLR_BACKEND.into(),
+            url: format!(
+                "{}/{}",
+                self.config.gateway_url.trim_end_matches('/'),
+                ids.manifest_id
+            ),
+            files,
+            verified: None,
+        }
+    }
 
-        Ok(CrawlUploadResult {
-            screenshot_id: screenshot_id,
-            warc_id: warc_file_ids,
-        })
+    async fn sign_crawl_bundle(
+        &self,
+        archiving_result: &ArchivingResult,
+    ) -> anyhow::Result<SignedDataItem> {
+        let mut items = vec![];
+        let mut paths = PathManifest::new();
+        for (path, kind, file) in crawl_files(archiving_result) {
+            let tags = self.crawl_tags(content_tags(kind.as_ref()), archiving_result);
+            let item = self.sign_file(file, tags).await?;
+            paths.add(&path, &item.id);
+            items.push(item);
+        }
+        paths.set_index(&crawl_path(None, 0));
+        // the path manifest is nested with the items it points to
+        let tags = self.crawl_tags(
+            vec![
+                Tag::<String>::from_utf8_strs("Content-Type", PATH_MANIFEST_CONTENT_TYPE).unwrap(),
+            ],
+            archiving_result,
+        );
+        let manifest = SignedDataItem::sign(
+            &self.arweave.crypto,
+            DataSource::Bytes(paths.to_bytes()?),
+            &tags,
+        )
+        .await
+        .context("could not sign path manifest")?;
+        items.push(manifest);
+
+        let tags = self.bundle_tags(archiving_result);
+        SignedDataItem::sign(&self.arweave.crypto, DataSource::Bundle(items), &tags)
+            .await
+            .context("could not sign crawl bundle")
+    }
+
+    fn bundle_tags(&self, archiving_result: &ArchivingResult) -> Vec<Tag<String>> {
+        self.crawl_tags(
+            vec![
+                Tag::<String>::from_utf8_strs("Bundle-Format", BUNDLE_FORMAT).unwrap(),
+                Tag::<String>::from_utf8_strs("Bundle-Version", BUNDLE_VERSION).unwrap(),
+            ],
+            archiving_result,
+        )
     }
 
     pub async fn upload_warc(
@@ -88,52 +325,88 @@ impl Uploader {
         file_path: &PathBuf,
         archive_info: &ArchivingResult,
     ) -> anyhow::Result<String> {
-        let data = fs::read(&file_path).await.context(format!(
-            "upload_warc: could not read file at path {:?}",
-            &file_path
-        ))?;
-
-        let tags = Self::append_app_tags(
-            vec![
-                Tag::<String>::from_utf8_strs("Content-Type", WARC_APPLICATION_TYPE).unwrap(),
-                Tag::<String>::from_utf8_strs("Content-Encoding", "gzip").unwrap(),
-            ],
-            &archive_info.archive_info.url(),
-            &archive_info.original_url,
-            archive_info.archive_info.unix_ts(),
-            archive_info.archive_info.depth(),
-        );
-
-        let file_tx_id = self.upload_to_bundlr(data, tags).await?;
+        let tags = self.crawl_tags(content_tags(Some(&ArchiveFileKind::Warc)), archive_info);
+        self.upload_file_to_bundlr(file_path, tags).await
+    }
 
-        return Ok(file_tx_id);
+    pub async fn upload_wacz(
+        &self,
+        file_path: &PathBuf,
+        archive_info: &ArchivingResult,
+    ) -> anyhow::Result<String> {
+        let tags = self.crawl_tags(content_tags(Some(&ArchiveFileKind::Wacz)), archive_info);
+        self.upload_file_to_bundlr(file_path, tags).await
     }
 
-    pub async fn upload_screenshot<'a>(
+    pub async fn upload_screenshot(
         &self,
         file_path: &PathBuf,
         archive_info: &ArchivingResult,
     ) -> anyhow::Result<String> {
-        let screenshot_data = fs::read(&file_path).await.context(format!(
-            "could not read screenshot_data at {:?}",
-            &file_path
-        ))?;
-
-        // first we deploy the screenshot
-        let screenshot_file_tx_id = self
-            .upload_to_bundlr(
-                screenshot_data,
-                Self::append_app_tags(
-                    vec![Tag::<String>::from_utf8_strs("Content-Type", "image/png").unwrap()],
-                    &archive_info.archive_info.url(),
-                    &archive_info.original_url,
-                    archive_info.archive_info.unix_ts(),
-                    archive_info.archive_info.depth(),
-                ),
-            )
+        let tags = self.crawl_tags(
+            content_tags(Some(&ArchiveFileKind::Screenshot)),
+            archive_info,
+        );
+        self.upload_file_to_bundlr(file_path, tags).await
+    }
+
+    // the file is streamed from disk, it is never held in memory as a whole
+    pub async fn upload_file_to_bundlr(
+        &self,
+        file_path: &PathBuf,
+        tags: Vec<Tag<String>>,
+    ) -> anyhow::Result<String> {
+        let key = file_path.to_string_lossy().to_string();
+        let (item, _) = self
+            .upload_resumable(&key, self.sign_file(file_path, tags))
             .await?;
+        Ok(item.id)
+    }
+
+    async fn sign_file(
+        &self,
+        file_path: &PathBuf,
+        tags: Vec<Tag<String>>,
+    ) -> anyhow::Result<SignedDataItem> {
+        SignedDataItem::sign(
+            &self.arweave.crypto,
+            DataSource::File(file_path.clone()),
+            &tags,
+        )
+        .await
+        .context(format!("could not sign data item of {:?}", file_path))
+    }
+
+    // uploads the item of an earlier upload of the key cut short, or the one sign resolves to.
+    // Without a session dir every upload starts over
+    async fn upload_resumable(
+        &self,
+        key: &str,
+        sign: impl Future<Output = anyhow::Result<SignedDataItem>>,
+    ) -> anyhow::Result<(SignedDataItem, UploadCost)> {
+        let session_path = self
+            .session_dir
+            .as_ref()
+            .map(|dir| UploadSession::path(dir, key));
+        let resumed = session_path
+            .as_ref()
+            .and_then(|path| resume_session(path, key));
 
-        return Ok(screenshot_file_tx_id);
+        let (item, session) = match resumed {
+            Some(session) => (session.item.clone(), Some(session)),
+            None => {
+                let item = sign.await?;
+                // only chunked uploads are long enough to be worth resuming
+                let session = match item.len() < self.chunking_threshold as u64 {
+                    true => None,
+                    false => Some(UploadSession::new(&item)?),
+                };
+                (item, session)
+            }
+        };
+
+        let cost = self.upload_paid(&item, session_path.zip(session)).await?;
+        Ok((item, cost))
     }
 
     pub async fn upload_to_bundlr(
@@ -141,103 +414,200 @@ impl Uploader {
         data: Vec<u8>,
         tags: Vec<Tag<String>>,
     ) -> anyhow::Result<String> {
-        let file_tx = self.arweave.create_data_item(data, tags, false)?;
-        let file_tx = self.arweave.sign_data_item(file_tx)?;
-        let file_tx_id = file_tx.id.to_string();
+        let item = SignedDataItem::sign(&self.arweave.crypto, DataSource::Bytes(data), &tags)
+            .await
+            .context("could not sign data item")?;
+        self.upload_paid(&item, None).await?;
+        Ok(item.id)
+    }
 
-        let client = self.client.clone();
+    // refuses the upload when the balance or the spending caps do not cover its price,
+    // what it cost counts against the caps once it went through
+    async fn upload_paid(
+        &self,
+        item: &SignedDataItem,
+        session: Option<(PathBuf, UploadSession)>,
+    ) -> anyhow::Result<UploadCost> {
+        let estimated = self.price(item.len()).await?;
+        let balance = self.balance_winston().await?;
+        if balance < estimated {
+            return Err(ArchiverError::InsufficientFunds {
+                needed: estimated,
+                balance,
+            }
+            .into());
+        }
+        if let Some(spending) = &self.spending {
+            spending.reserve(estimated)?;
+        }
 
-        let data = file_tx
-            .serialize()
-            .context(format!("could not serialize when uploading to bundlr"))?;
-        let size = data.len();
+        let charged = match self.upload_data_item(item, session).await {
+            Ok(charged) => charged,
+            Err(e) => {
+                if let Some(spending) = &self.spending {
+                    spending.release(estimated);
+                }
+                return Err(e);
+            }
+        };
+        let cost = UploadCost {
+            estimated,
+            actual: charged.unwrap_or(estimated),
+        };
+        debug!(
+            "data item {} of {} bytes estimated at {} winston, charged {}",
+            item.id,
+            item.len(),
+            cost.estimated,
+            cost.actual
+        );
+        // the data item is uploaded whatever happens to the ledger
+        if let Some(spending) = &self.spending {
+            if let Err(e) = spending.settle(&cost) {
+                warn!(
+                    "could not record the spending of data item {} {}",
+                    item.id, e
+                );
+            }
+        }
+        Ok(cost)
+    }
+
+    // the session, with the path it is saved to, records the progress of a chunked upload.
+    // Resolves to the winston the node charged, when it tells
+    async fn upload_data_item(
+        &self,
+        item: &SignedDataItem,
+        mut session: Option<(PathBuf, UploadSession)>,
+    ) -> anyhow::Result<Option<u128>> {
+        let file_tx_id = item.id.clone();
+        let client = self.client.clone();
+        let size = item.len();
 
         // if the data size if small, we can send it straight to bundlr
-        if size < CHUNKING_THRESHOLD {
+        if size < self.chunking_threshold as u64 {
             match client
-                .post(format!("{}/tx/arweave", BUNDLR_URL))
+                .post(format!("{}/tx/arweave", self.config.bundlr_url))
                 .header("Content-Type", "application/octet-stream")
-                .body(file_tx.serialize().unwrap())
+                .body(item.to_bytes().await?)
                 .send()
                 .await
             {
                 Ok(res) => {
-                    let res = res.text().await.unwrap();
-                    debug!("{res}")
-                }
-                Err(e) => {
-                    return Err(anyhow!(
-                        "could not send small bundle to bundlr {}",
-                        e.to_string()
-                    ))
+                    let status = res.status();
+                    let res = res.text().await.unwrap_or_default();
+                    if !status.is_success() {
+                        return Err(anyhow!(
+                            "bundlr refused data item {}, status {} {}",
+                            file_tx_id,
+                            status,
+                            res
+                        ));
+                    }
+                    debug!("{res}");
+                    receipt(&res, &file_tx_id)
                 }
+                Err(e) => Err(anyhow!("could not send small bundle to bundlr {}", e)),
             }
-
-            return Ok(file_tx_id);
         } else {
             // otherwise we need to chunk the data and send it
             debug!("sending large bundles to Bundlr, chunking...");
 
-            let upload_info = client
-                .get(format!("{}/chunks/arweave/-1/-1", BUNDLR_URL))
-                .header("x-chunking-version", "2")
-                .send()
-                .await
-                .context(format!("could not get upload id from bundlr"))?;
+            // an upload started earlier continues where the node left it
+            let previous = session
+                .as_ref()
+                .and_then(|(_, s)| s.upload_id.clone().zip(s.chunk_size));
+            let resumed = match previous {
+                Some((upload_id, chunk_size)) => match self.received_chunks(&upload_id).await {
+                    Ok(received) => {
+                        debug!(
+                            "resuming upload ID: {}, {} chunks received",
+                            upload_id,
+                            received.len()
+                        );
+                        Some((upload_id, chunk_size, received))
+                    }
+                    Err(e) => {
+                        warn!(
+                            "could not resume upload {} of {}, starting over {}",
+                            upload_id, file_tx_id, e
+                        );
+                        None
+                    }
+                },
+                None => None,
+            };
 
-            let upload_info = upload_info
-                .json::<BundlrUploadID>()
-                .await
-                .context("could not parse BundlrUploadId")?;
-            let upload_id = upload_info.id;
-            debug!("upload ID: {}", upload_id);
-
-            if size < upload_info.min {
-                return Err(anyhow!(
-                    "chunk size out of allowed range: {} - {}, currently {}",
-                    upload_info.min,
-                    upload_info.max,
-                    size
-                ));
-            }
+            let (upload_id, chunk_size, received) = match resumed {
+                Some(r) => r,
+                None => {
+                    let upload_info = client
+                        .get(format!("{}/chunks/arweave/-1/-1", self.config.bundlr_url))
+                        .header("x-chunking-version", "2")
+                        .send()
+                        .await
+                        .context("could not get upload id from bundlr")?;
 
-            let chunk_size = upload_info.min;
+                    let upload_info = upload_info
+                        .json::<BundlrUploadID>()
+                        .await
+                        .context("could not parse BundlrUploadId")?;
+                    debug!("upload ID: {}", upload_info.id);
 
-            let data = data
-                .into_iter()
-                .chunks(chunk_size)
-                .into_iter()
-                .map(|x| x.collect::<Vec<u8>>())
-                .collect::<Vec<Vec<u8>>>();
+                    if size < upload_info.min as u64 {
+                        return Err(anyhow!(
+                            "chunk size out of allowed range: {} - {}, currently {}",
+                            upload_info.min,
+                            upload_info.max,
+                            size
+                        ));
+                    }
+                    (upload_info.id, upload_info.min, BTreeSet::new())
+                }
+            };
 
+            if let Some((path, s)) = session.as_mut() {
+                s.upload_id = Some(upload_id.clone());
+                s.chunk_size = Some(chunk_size);
+                s.acknowledged = received.clone();
+                s.write(path)?;
+            }
+
+            let offsets = (0..size)
+                .step_by(chunk_size)
+                .filter(|offset| !received.contains(offset))
+                .collect::<Vec<u64>>();
+
+            // chunks are read from the item as they are sent, so only the ones
+            // in flight are held in memory
             // we need to help the compiler with assert_stream_send
             // as we have a stream being awaited in multiple threads
             let mut stream = assert_stream_send(
-                tokio_stream::iter(data.iter().enumerate())
-                    .map(|p| {
-                        let retry_strategy = FixedInterval::from_millis(20)
-                            // add jitter to delays
-                            .map(jitter)
-                            // retry 5 times
-                            .take(5);
-                        let index = p.0;
+                tokio_stream::iter(offsets)
+                    .map(|offset| {
                         let uid = upload_id.clone();
                         let client = client.clone();
-                        Retry::spawn(retry_strategy, move || {
-                            client
-                                .post(format!(
-                                    "{}/chunks/arweave/{}/{}",
-                                    BUNDLR_URL,
-                                    uid,
-                                    // needs to be the offset, not index
-                                    chunk_size * index
-                                ))
-                                .header("Content-Type", "application/octet-stream")
-                                .header("x-chunking-version", "2")
-                                .timeout(Duration::from_secs(20))
-                                .body(p.1.clone())
-                                .send()
-                        })
+                        let bundlr_url = self.config.bundlr_url.clone();
+                        let retry_strategy = backoff(self.retries, self.retry_delay);
+                        async move {
+                            let chunk = item.read_at(offset, chunk_size).await?;
+                            let res = Retry::spawn(retry_strategy, || {
+                                client
+                                    .post(format!(
+                                        "{}/chunks/arweave/{}/{}",
+                                        bundlr_url, uid, offset
+                                    ))
+                                    .header("Content-Type", "application/octet-stream")
+                                    .header("x-chunking-version", "2")
+                                    .timeout(Duration::from_secs(20))
+                                    .body(chunk.clone())
+                                    .send()
+                                    // a chunk the node refused is retried like a lost one
+                                    .and_then(|res| future::ready(res.error_for_status()))
+                            })
+                            .await?;
+                            anyhow::Ok((offset, res))
+                        }
                     })
                     .buffer_unordered(10),
             );
@@ -245,22 +615,41 @@ impl Uploader {
             let mut counter = 0;
             while let Some(result) = stream.next().await {
                 match result {
-                    Ok(res) => {
+                    Ok((offset, res)) => {
                         debug!("{:?}", res.text().await);
                         if counter == 0 {
                             debug!("{}", "started");
                         }
                         counter += 1;
+                        if let Some((path, s)) = session.as_mut() {
+                            s.acknowledged.insert(offset);
+                            // the node tells which chunks it holds on resume,
+                            // the session only needs to be written from time to time
+                            if counter % SESSION_WRITE_INTERVAL == 0 {
+                                s.write(path)?;
+                            }
+                        }
                     }
                     Err(e) => {
+                        if let Some((path, s)) = session.as_mut() {
+                            if let Err(e) = s.write(path) {
+                                warn!("could not save upload session of {} {}", file_tx_id, e);
+                            }
+                        }
                         return Err(anyhow!("could not upload chunk with error: {}", e));
                     }
                 }
             }
             debug!("uploaded {} chunks", counter);
+            if let Some((path, s)) = session.as_mut() {
+                s.write(path)?;
+            }
 
             let finish = client
-                .post(format!("{}/chunks/arweave/{}/-1", BUNDLR_URL, upload_id))
+                .post(format!(
+                    "{}/chunks/arweave/{}/-1",
+                    self.config.bundlr_url, upload_id
+                ))
                 .header("x-chunking-version", "2")
                 .header("Content-Type", "application/octet-stream")
                 .timeout(Duration::from_secs(40))
@@ -282,22 +671,197 @@ impl Uploader {
                 "successfully uploaded tx \n status: {:#} \n response: {:#}",
                 status, res
             );
+            if let Some((path, _)) = &session {
+                UploadSession::remove(path)?;
+            }
 
-            return Ok(file_tx_id);
+            receipt(&res, &file_tx_id)
         }
     }
 
+    // offsets of the chunks of the upload the node already holds,
+    // fails once the node forgot the upload
+    async fn received_chunks(&self, upload_id: &str) -> anyhow::Result<BTreeSet<u64>> {
+        let res = self
+            .client
+            .get(format!(
+                "{}/chunks/arweave/{}/-2",
+                self.config.bundlr_url, upload_id
+            ))
+            .header("x-chunking-version", "2")
+            .send()
+            .await
+            .context(format!("could not fetch chunks of upload {}", upload_id))?
+            .error_for_status()?;
+        res.json::<BundlrReceivedChunks>()
+            .await
+            .context("could not parse BundlrReceivedChunks")?
+            .offsets()
+    }
+
+    // fetches the uploaded bundle back from the verify url, checking it holds the files
+    // that were on disk and carries the tags it was signed with. Nodes and gateways only
+    // serve the bundle under its id, not the items nested in it
+    pub async fn verify_crawl(
+        &self,
+        crawl: &ArchivingResult,
+        ids: &CrawlUploadResult,
+    ) -> anyhow::Result<()> {
+        let (size, sha256) = &ids.bundle_digest;
+        self.verify_data_item(&ids.bundle_id, *size, sha256, &self.bundle_tags(crawl))
+            .await
+            .context(format!(
+                "could not verify the upload of the crawl of {}",
+                crawl.original_url
+            ))?;
+        info!(
+            "verified upload of crawl of {}, bundle {}",
+            crawl.original_url, ids.bundle_id
+        );
+        Ok(())
+    }
+
+    // ids of the crawl once stored and verified, only taken once
+    pub fn take_verified(&self, manifest_file: &Path) -> Option<CrawlUploadResult> {
+        self.verified.lock().unwrap().remove(manifest_file)
+    }
+
+    // items not served or indexed yet are fetched again, an item served with other
+    // data or tags fails right away
+    pub async fn verify_data_item(
+        &self,
+        id: &str,
+        size: u64,
+        sha256: &str,
+        tags: &[Tag<String>],
+    ) -> anyhow::Result<()> {
+        RetryIf::spawn(
+            backoff(self.verify_retries, self.verify_delay),
+            || self.check_data_item(id, size, sha256, tags),
+            |e: &anyhow::Error| {
+                !matches!(
+                    e.downcast_ref::<ArchiverError>(),
+                    Some(ArchiverError::VerificationFailed(_))
+                )
+            },
+        )
+        .await
+    }
+
+    async fn check_data_item(
+        &self,
+        id: &str,
+        size: u64,
+        sha256: &str,
+        tags: &[Tag<String>],
+    ) -> anyhow::Result<()> {
+        let res = self
+            .client
+            .get(format!("{}/{}", self.config.verify_url(), id))
+            .timeout(Duration::from_secs(600))
+            .send()
+            .await
+            .context(format!("could not fetch data item {}", id))?
+            .error_for_status()
+            .context(format!("data item {} is not served", id))?;
+        // hashed as it is received, large items are never held in memory
+        let mut stream = res.bytes_stream();
+        let mut hasher = Sha256::new();
+        let mut served = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.context(format!("could not fetch data item {}", id))?;
+            served += chunk.len() as u64;
+            hasher.update(&chunk);
+        }
+        if served != size {
+            return Err(ArchiverError::VerificationFailed(format!(
+                "data item {} is served with {} bytes, {} uploaded",
+                id, served, size
+            ))
+            .into());
+        }
+        let served_hash = to_hex(&hasher.finalize());
+        if served_hash != sha256 {
+            return Err(ArchiverError::VerificationFailed(format!(
+                "data item {} is served with sha256 {}, {} uploaded",
+                id, served_hash, sha256
+            ))
+            .into());
+        }
+
+        let served_tags = self.data_item_tags(id).await?;
+        for tag in tags {
+            if !served_tags
+                .iter()
+                .any(|t| t.name == tag.name && t.value == tag.value)
+            {
+                return Err(ArchiverError::VerificationFailed(format!(
+                    "data item {} is missing tag {}: {}",
+                    id, tag.name, tag.value
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    // tags of a data item, from the graphql endpoint of the verify url.
+    // Fails until the item is indexed
+    async fn data_item_tags(&self, id: &str) -> anyhow::Result<Vec<TransactionTag>> {
+        let query = format!(
+            r#"query {{ transactions(ids: ["{}"]) {{ edges {{ node {{ id tags {{ name value }} }} }} }} }}"#,
+            id
+        );
+        let res = self
+            .client
+            .post(format!("{}/graphql", self.config.verify_url()))
+            .header("Content-Type", "application/json")
+            .body(serde_json::json!({ "query": query }).to_string())
+            .send()
+            .await
+            .context(format!("could not fetch tags of data item {}", id))?
+            .error_for_status()
+            .context(format!("could not fetch tags of data item {}", id))?
+            .json::<TransactionsQuery>()
+            .await
+            .context("could not parse TransactionsQuery")?;
+        res.data
+            .transactions
+            .edges
+            .into_iter()
+            .find(|edge| edge.node.id == id)
+            .map(|edge| edge.node.tags)
+            .ok_or_else(|| anyhow!("data item {} is not indexed yet", id))
+    }
+
+    fn crawl_tags(
+        &self,
+        tags: Vec<Tag<String>>,
+        archive_info: &ArchivingResult,
+    ) -> Vec<Tag<String>> {
+        self.append_app_tags(
+            tags,
+            &archive_info.archive_info.url(),
+            &archive_info.original_url,
+            archive_info.archive_info.unix_ts(),
+            archive_info.archive_info.depth(),
+            &archive_info.budget_exceeded,
+        )
+    }
+
     fn append_app_tags(
+        &self,
         mut tags: Vec<Tag<String>>,
         url: &str,
         original_url: &str,
         timestamp: i64,
         depth: u8,
+        budget_exceeded: &Option<BudgetLimit>,
     ) -> Vec<Tag<String>> {
         let mut t = vec![
             // App Tags
-            Tag::<String>::from_utf8_strs("App-Name", &APP_NAME).unwrap(),
-            Tag::<String>::from_utf8_strs("App-Version", &APP_VERSION).unwrap(),
+            Tag::<String>::from_utf8_strs("App-Name", &self.config.app_name).unwrap(),
+            Tag::<String>::from_utf8_strs("App-Version", &self.config.app_version).unwrap(),
             Tag::<String>::from_utf8_strs("Url", url.into()).unwrap(),
             Tag::<String>::from_utf8_strs("Original-Url", original_url.into()).unwrap(),
             Tag::<String>::from_utf8_strs("Timestamp", &format!("{}", timestamp)).unwrap(),
@@ -308,7 +872,522 @@ impl Uploader {
             )
             .unwrap(),
         ];
+        // the crawl was cut short by one of its budgets
+        if let Some(limit) = budget_exceeded {
+            t.push(Tag::<String>::from_utf8_strs("Partial-Crawl", &limit.to_string()).unwrap());
+        }
         tags.append(&mut t);
         return tags;
     }
 }
+
+#[async_trait]
+impl StorageBackend for Uploader {
+    fn name(&self) -> &str {
+        BUNDLR_BACKEND
+    }
+
+    // an upload that is not served back intact is still stored, its location is
+    // flagged and its ids are not kept for submission
+    async fn store_crawl(&self, crawl: &ArchivingResult) -> anyhow::Result<StorageLocation> {
+        let ids = self.upload_crawl_files(crawl).await?;
+        let mut location = self.location(&ids, crawl);
+        match self.verify_crawl(crawl, &ids).await {
+            Ok(()) => {
+                location.verified = Some(true);
+                self.verified
+                    .lock()
+                    .unwrap()
+                    .insert(crawl.manifest_file.clone(), ids);
+            }
+            Err(e) => {
+                error!(
+                    "upload of crawl of {} failed verification, it is not submitted {:?}",
+                    crawl.original_url, e
+                );
+                location.verified = Some(false);
+            }
+        }
+        Ok(location)
+    }
+}
+
+// winston charged according to the response of the node to an upload, when it tells.
+// Fails when the node acknowledged another item than the one uploaded
+fn receipt(res: &str, id: &str) -> anyhow::Result<Option<u128>> {
+    let res = match serde_json::from_str::<BundlrRes>(res) {
+        Ok(res) => res,
+        Err(_) => return Ok(None),
+    };
+    if res.id != id {
+        return Err(anyhow!(
+            "bundlr acknowledged data item {} for {}",
+            res.id,
+            id
+        ));
+    }
+    Ok(res.winc.and_then(|winc| winc.parse::<u128>().ok()))
+}
+
+// delays between the attempts of a request, doubling up to MAX_RETRY_DELAY
+fn backoff(retries: usize, delay: u64) -> impl Iterator<Item = Duration> {
+    ExponentialBackoff::from_millis(2)
+        .factor((delay / 2).max(1))
+        .max_delay(MAX_RETRY_DELAY)
+        // add jitter to delays
+        .map(jitter)
+        .take(retries)
+}
+
+// the session of an earlier upload of the key, if it can be resumed
+fn resume_session(path: &PathBuf, key: &str) -> Option<UploadSession> {
+    let session = match UploadSession::load(path) {
+        Ok(Some(s)) => s,
+        Ok(None) => return None,
+        Err(e) => {
+            warn!("could not load upload session, starting over {}", e);
+            return None;
+        }
+    };
+    if !session.is_current() {
+        info!("{} changed since its upload started, starting over", key);
+        return None;
+    }
+    info!(
+        "resuming upload of {} as data item {}",
+        key, session.item.id
+    );
+    Some(session)
+}
+
+fn content_tags(kind: Option<&ArchiveFileKind>) -> Vec<Tag<String>> {
+    let content_type = match kind {
+        Some(ArchiveFileKind::Warc) => WARC_APPLICATION_TYPE,
+        Some(ArchiveFileKind::Wacz) => WACZ_APPLICATION_TYPE,
+        Some(ArchiveFileKind::Index) => "text/plain",
+        Some(ArchiveFileKind::Screenshot) => "image/png",
+        Some(ArchiveFileKind::Pages) => "application/x-ndjson",
+        // the manifest of the crawl
+        None => "application/json",
+    };
+    let mut tags = vec![Tag::<String>::from_utf8_strs("Content-Type", content_type).unwrap()];
+    if kind == Some(&ArchiveFileKind::Warc) {
+        tags.push(Tag::<String>::from_utf8_strs("Content-Encoding", "gzip").unwrap());
+    }
+    tags
+}
+
+// ids of the bundle and of the items nested in it, told apart by the file they were read from
+fn crawl_upload_result(
+    bundle: &SignedDataItem,
+    archiving_result: &ArchivingResult,
+    cost: UploadCost,
+    bundle_digest: (u64, String),
+) -> anyhow::Result<CrawlUploadResult> {
+    let items = match bundle.data() {
+        DataSource::Bundle(items) => items,
+        _ => return Err(anyhow!("data item {} is not a bundle", bundle.id)),
+    };
+    let id_of = |file: &PathBuf| {
+        items
+            .iter()
+            .find(|item| item.data().files().first() == Some(file))
+            .map(|item| item.id.clone())
+            .ok_or_else(|| anyhow!("{:?} is missing from bundle {}", file, bundle.id))
+    };
+    let (warc_id, index_id, wacz_id) = match &archiving_result.wacz_file {
+        Some(wacz) => (vec![], vec![], Some(id_of(wacz)?)),
+        None => (
+            archiving_result
+                .warc_files
+                .iter()
+                .map(id_of)
+                .collect::<anyhow::Result<Vec<String>>>()?,
+            archiving_result
+                .index_files
+                .iter()
+                .map(id_of)
+                .collect::<anyhow::Result<Vec<String>>>()?,
+            None,
+        ),
+    };
+    // the path manifest is the only item that was not read from a file
+    let manifest_id = items
+        .iter()
+        .find(|item| item.data().files().is_empty())
+        .map(|item| item.id.clone())
+        .ok_or_else(|| anyhow!("path manifest is missing from bundle {}", bundle.id))?;
+    Ok(CrawlUploadResult {
+        bundle_id: bundle.id.clone(),
+        screenshot_id: id_of(&archiving_result.screenshot_file)?,
+        warc_id,
+        index_id,
+        wacz_id,
+        pages_id: id_of(&archiving_result.pages_file)?,
+        archive_manifest_id: id_of(&archiving_result.manifest_file)?,
+        manifest_id,
+        cost,
+        bundle_digest,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        manifest::ArchiveManifest,
+        mock_bundlr::{data_offset, Endpoint, MockBundlr, TEST_WALLET},
+        spending::SpendingLimits,
+        types::ArchiveInfo,
+        utils::create_random_tmp_folder,
+    };
+
+    use super::*;
+
+    async fn uploader(node: &MockBundlr) -> Uploader {
+        let config = ArchiverConfig {
+            bundlr_url: node.url(),
+            ..ArchiverConfig::default()
+        };
+        Uploader::new(PathBuf::from(TEST_WALLET), "arweave", &config)
+            .await
+            .unwrap()
+            .with_chunking_threshold(10_000)
+            .with_retries(5, 2)
+    }
+
+    fn tags() -> Vec<Tag<String>> {
+        vec![Tag::<String>::from_utf8_strs("Content-Type", "text/plain").unwrap()]
+    }
+
+    fn crawl(dir: &Path) -> ArchivingResult {
+        let file = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            path
+        };
+        let manifest =
+            ArchiveManifest::new("example.com", "https://example.com/", 1674662517, 1, "", "");
+        ArchivingResult {
+            warc_files: vec![file("a.warc.gz", b"warc a"), file("b.warc.gz", b"warc b")],
+            index_files: vec![file("a.cdxj", b"index a"), file("b.cdxj", b"index b")],
+            screenshot_file: file("screenshot.png", b"png"),
+            wacz_file: None,
+            pages_file: file("a.pages.jsonl", b"{}"),
+            manifest_file: file("a.archive.json", b"{}"),
+            archive_info: ArchiveInfo::from_manifest(&manifest).unwrap(),
+            title: String::new(),
+            original_url: "https://example.com/".into(),
+            seeds: vec!["https://example.com/".into()],
+            budget_exceeded: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn uploads_small_data_items() {
+        let node = MockBundlr::start();
+        let uploader = uploader(&node).await;
+        let data = vec![7u8; 1_000];
+
+        let id = uploader
+            .upload_to_bundlr(data.clone(), tags())
+            .await
+            .unwrap();
+        assert!(node.item(&id).unwrap().ends_with(&data));
+        assert_eq!(node.requests(Endpoint::Upload), 1);
+        assert_eq!(node.requests(Endpoint::Chunk), 0);
+
+        node.fail(Endpoint::Upload, 402, 1);
+        assert!(uploader.upload_to_bundlr(data, tags()).await.is_err());
+        assert_eq!(node.item_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn uploads_large_data_items_in_chunks() {
+        let node = MockBundlr::start();
+        node.set_chunk_range(4_000, 100_000);
+        let uploader = uploader(&node).await;
+        let data = (0..50_000).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+
+        let id = uploader
+            .upload_to_bundlr(data.clone(), tags())
+            .await
+            .unwrap();
+        let item = node.item(&id).unwrap();
+        assert!(item.ends_with(&data));
+        assert_eq!(node.requests(Endpoint::Chunk), item.len().div_ceil(4_000));
+        assert_eq!(node.requests(Endpoint::Upload), 0);
+    }
+
+    #[tokio::test]
+    async fn streams_files_from_disk() {
+        let node = MockBundlr::start();
+        node.set_chunk_range(4_000, 100_000);
+        let uploader = uploader(&node).await;
+        let data = (0..50_000).map(|i| (i % 241) as u8).collect::<Vec<u8>>();
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("archive.warc.gz");
+        std::fs::write(&path, &data).unwrap();
+
+        let id = uploader.upload_file_to_bundlr(&path, tags()).await.unwrap();
+        let item = node.item(&id).unwrap();
+        assert!(item.ends_with(&data));
+        // the same data and tags from memory serialize to an item of the same size
+        let from_memory = uploader.upload_to_bundlr(data, tags()).await.unwrap();
+        assert_eq!(node.item(&from_memory).unwrap().len(), item.len());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn uploads_crawls_as_one_bundle() {
+        let node = MockBundlr::start();
+        node.set_chunk_range(4_000, 100_000);
+        let uploader = uploader(&node).await;
+        let dir = create_random_tmp_folder().unwrap();
+        let result = crawl(&dir);
+
+        let ids = uploader.upload_crawl_files(&result).await.unwrap();
+        assert_eq!(node.item_count(), 1);
+        assert!(node.item(&ids.bundle_id).is_some());
+        assert!(node.item(&ids.warc_id[1]).is_none());
+        assert!(node
+            .nested_item(&ids.warc_id[1])
+            .unwrap()
+            .ends_with(b"warc b"));
+        assert!(node
+            .nested_item(&ids.index_id[0])
+            .unwrap()
+            .ends_with(b"index a"));
+
+        // the path manifest maps stable paths to the nested items
+        let item = node.nested_item(&ids.manifest_id).unwrap();
+        let paths: PathManifest =
+            serde_json::from_slice(&item[data_offset(&item).unwrap()..]).unwrap();
+        for (path, id) in [
+            ("archive.warc.gz", &ids.warc_id[0]),
+            ("archive-1.warc.gz", &ids.warc_id[1]),
+            ("index.cdxj", &ids.index_id[0]),
+            ("screenshot.png", &ids.screenshot_id),
+            ("pages.jsonl", &ids.pages_id),
+            ("archive.json", &ids.archive_manifest_id),
+        ] {
+            assert_eq!(&paths.paths[path].id, id);
+        }
+        assert_eq!(paths.index.unwrap().path, "archive.json");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn verifies_uploaded_crawls() {
+        let node = MockBundlr::start();
+        node.set_chunk_range(4_000, 100_000);
+        let uploader = uploader(&node).await.with_verification(3, 2);
+        let dir = create_random_tmp_folder().unwrap();
+        let result = crawl(&dir);
+        let ids = uploader.upload_crawl_files(&result).await.unwrap();
+        uploader.verify_crawl(&result, &ids).await.unwrap();
+        // only the bundle is fetched, its nested items are not served by their ids
+        assert_eq!(node.requests(Endpoint::Data), 1);
+        let bundle = node.item(&ids.bundle_id).unwrap();
+        assert_eq!(
+            ids.bundle_digest.0,
+            (bundle.len() - data_offset(&bundle).unwrap()) as u64
+        );
+
+        // the files are checked as they were uploaded, not as they are now
+        std::fs::write(&result.manifest_file, b"{\"updated\": true}").unwrap();
+        uploader.verify_crawl(&result, &ids).await.unwrap();
+
+        // items not served yet are fetched again
+        node.fail(Endpoint::Data, 404, 2);
+        uploader.verify_crawl(&result, &ids).await.unwrap();
+
+        // items served with other data are not
+        node.corrupt(true);
+        let fetched = node.requests(Endpoint::Data);
+        let err = uploader.verify_crawl(&result, &ids).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ArchiverError>(),
+            Some(ArchiverError::VerificationFailed(_))
+        ));
+        assert_eq!(node.requests(Endpoint::Data), fetched + 1);
+        node.corrupt(false);
+
+        // nor items missing one of the tags of the crawl
+        let config = ArchiverConfig {
+            bundlr_url: node.url(),
+            app_name: "another-archiver".into(),
+            ..ArchiverConfig::default()
+        };
+        let other = Uploader::new(PathBuf::from(TEST_WALLET), "arweave", &config)
+            .await
+            .unwrap();
+        let err = other.verify_crawl(&result, &ids).await.unwrap_err();
+        assert!(format!("{:?}", err).contains("missing tag App-Name"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_interrupted_uploads() {
+        let node = MockBundlr::start();
+        node.set_chunk_range(4_000, 100_000);
+        let data = (0..50_000).map(|i| (i % 239) as u8).collect::<Vec<u8>>();
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("archive.warc.gz");
+        std::fs::write(&path, &data).unwrap();
+        let mut session_dir = dir.clone();
+        session_dir.push("uploads");
+        let session_path = UploadSession::path(&session_dir, &path.to_string_lossy());
+
+        // the chunk at 8000 is refused past every retry
+        node.fail_chunk(8_000, 100);
+        let first = uploader(&node)
+            .await
+            .with_session_dir(Some(session_dir.clone()));
+        assert!(first.upload_file_to_bundlr(&path, tags()).await.is_err());
+        let session = UploadSession::load(&session_path).unwrap().unwrap();
+        assert!(!session.acknowledged.contains(&8_000));
+        assert_eq!(node.item_count(), 0);
+
+        // a restarted uploader sends the same data item, without the chunks the node holds
+        node.fail_chunk(8_000, 0);
+        let sent = node.requests(Endpoint::Chunk);
+        let second = uploader(&node)
+            .await
+            .with_session_dir(Some(session_dir.clone()));
+        let id = second.upload_file_to_bundlr(&path, tags()).await.unwrap();
+        assert_eq!(id, session.item.id);
+        assert!(node.item(&id).unwrap().ends_with(&data));
+        assert_eq!(node.requests(Endpoint::Received), 1);
+        assert_eq!(node.requests(Endpoint::UploadId), 1);
+        let total = node.item(&id).unwrap().len().div_ceil(4_000);
+        assert!(node.requests(Endpoint::Chunk) - sent < total);
+        assert!(UploadSession::load(&session_path).unwrap().is_none());
+
+        // a changed file is signed and uploaded again
+        node.fail(Endpoint::Finalize, 500, 1);
+        assert!(second.upload_file_to_bundlr(&path, tags()).await.is_err());
+        let session = UploadSession::load(&session_path).unwrap().unwrap();
+        std::fs::write(&path, &data[1..]).unwrap();
+        let id = second.upload_file_to_bundlr(&path, tags()).await.unwrap();
+        assert_ne!(id, session.item.id);
+        assert_eq!(node.requests(Endpoint::UploadId), 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn retries_refused_chunks() {
+        let node = MockBundlr::start();
+        node.set_chunk_range(4_000, 100_000);
+        node.fail(Endpoint::Chunk, 503, 2);
+        let uploader = uploader(&node).await;
+        let data = vec![1u8; 20_000];
+
+        let id = uploader
+            .upload_to_bundlr(data.clone(), tags())
+            .await
+            .unwrap();
+        let item = node.item(&id).unwrap();
+        assert!(item.ends_with(&data));
+        assert_eq!(
+            node.requests(Endpoint::Chunk),
+            item.len().div_ceil(4_000) + 2
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_upload_is_not_finalized() {
+        let node = MockBundlr::start();
+        node.set_chunk_range(4_000, 100_000);
+        node.fail(Endpoint::Finalize, 500, 1);
+        let uploader = uploader(&node).await;
+
+        assert!(uploader
+            .upload_to_bundlr(vec![1u8; 20_000], tags())
+            .await
+            .is_err());
+        assert_eq!(node.item_count(), 0);
+
+        // too small for the chunks the node accepts
+        node.set_chunk_range(100_000, 200_000);
+        assert!(uploader
+            .upload_to_bundlr(vec![1u8; 20_000], tags())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn pays_for_uploads_within_the_balance_and_caps() {
+        let node = MockBundlr::start();
+        node.set_price_per_byte(2);
+        node.set_balance(100_000);
+        let spending = Arc::new(
+            SpendingLedger::new(
+                SpendingLimits {
+                    max_crawl_cost: Some(5_000),
+                    max_daily_cost: Some(6_000),
+                },
+                None,
+            )
+            .unwrap(),
+        );
+        let uploader = uploader(&node).await.with_spending(Some(spending.clone()));
+
+        let id = uploader
+            .upload_to_bundlr(vec![7u8; 1_000], tags())
+            .await
+            .unwrap();
+        let cost = node.item(&id).unwrap().len() as u128 * 2;
+        assert_eq!(node.balance(), 100_000 - cost);
+        assert_eq!(spending.spent_today(), cost);
+
+        // over the cap of a crawl
+        let err = uploader
+            .upload_to_bundlr(vec![7u8; 2_500], tags())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ArchiverError>(),
+            Some(ArchiverError::SpendingCapExceeded(_))
+        ));
+        // over what is left of the daily cap
+        assert!(uploader
+            .upload_to_bundlr(vec![7u8; 500], tags())
+            .await
+            .is_err());
+        assert_eq!(node.requests(Endpoint::Upload), 1);
+
+        // over the balance
+        let uploader = uploader.with_spending(None);
+        node.set_balance(1_000);
+        let err = uploader
+            .upload_to_bundlr(vec![7u8; 1_000], tags())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ArchiverError>(),
+            Some(ArchiverError::InsufficientFunds { .. })
+        ));
+        assert!(uploader.check_crawl_funds(Some(1_000)).await.is_err());
+        uploader.check_crawl_funds(Some(100)).await.unwrap();
+        node.set_balance(0);
+        assert!(uploader.check_crawl_funds(None).await.is_err());
+        assert_eq!(node.requests(Endpoint::Upload), 1);
+    }
+
+    #[tokio::test]
+    async fn fetches_the_balance() {
+        let node = MockBundlr::start();
+        node.set_balance(42);
+        let uploader = uploader(&node).await;
+        assert_eq!(uploader.balance().await.unwrap().balance, "42");
+
+        node.fail(Endpoint::Balance, 500, 1);
+        assert!(uploader.balance().await.is_err());
+    }
+}
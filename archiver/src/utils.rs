@@ -1,33 +1,30 @@
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use futures::Stream;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
     fs,
     path::PathBuf,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tokio::io::AsyncReadExt;
 
 use crate::types::UrlInfo;
 
 pub const ARCHIVE_DIR: &str = "archiver";
 pub const BASE_URL: &str = "http://localhost";
 pub const WARC_APPLICATION_TYPE: &str = "application/warc";
+pub const WACZ_APPLICATION_TYPE: &str = "application/wacz";
 pub const FORMAT_STRING: &str = "%Y%m%d%H%M%S";
-pub const BUNDLR_URL: &str = "https://node1.bundlr.network";
 pub const CHUNKING_THRESHOLD: usize = 50_000_000;
+pub const VERSION: &str = "0.0.1";
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
 
+// name and version the archiver identifies itself with in user agents and warc files,
+// the tags of the uploads come from the configuration
 lazy_static! {
-    pub static ref CONTRACT_ADDRESS: String = {
-        let env = match std::env::var("ENVIRONMENT") {
-            Ok(e) => e,
-            Err(_) => "".into(),
-        };
-        if env == "production" {
-            return "dD1DuvgM_Vigtnv4vl2H1IYn9CgLvYuhbEWPOL-_4Mw".into();
-        }
-        "51M3s8hKonwD1-7NKnpyf2gcwha9ql1wCz2BOnxRGwI".into()
-    };
     pub static ref APP_NAME: String = {
         let env = match std::env::var("ENVIRONMENT") {
             Ok(e) => e,
@@ -43,11 +40,10 @@ lazy_static! {
             Ok(e) => e,
             Err(_) => "".into(),
         };
-        let v = "0.0.1";
         if env == "production" {
-            return v.into();
+            return VERSION.into();
         }
-        format!("{}_dev", v)
+        format!("{}_dev", VERSION)
     };
 }
 
@@ -129,11 +125,21 @@ pub fn jitter(duration: Duration) -> Duration {
     duration.mul_f64(rndm)
 }
 
+// returns the archived url of a pywb proxy url, urls captured natively are returned as is
 pub fn extract_url(url: &str) -> String {
     if url.contains("/mp_/") {
         return url.split("record/mp_/").nth(1).unwrap().to_string();
     }
-    url.split("record/").nth(1).unwrap().to_string()
+    match url.split_once("record/") {
+        Some((_, u)) if u.starts_with("http") => u.to_string(),
+        _ => url.to_string(),
+    }
+}
+
+// scheme, host and port of a url, used to resolve relative links
+pub fn get_origin(url: &str) -> anyhow::Result<String> {
+    let u = Url::parse(url)?;
+    Ok(u.origin().ascii_serialization())
 }
 
 fn standardize_url(url: &str) -> String {
@@ -168,6 +174,83 @@ pub fn create_random_tmp_folder() -> anyhow::Result<PathBuf> {
     Ok(path)
 }
 
+// size and hex encoded sha256 of the file, read a buffer at a time
+pub async fn hash_file(file: &PathBuf) -> anyhow::Result<(u64, String)> {
+    let mut reader = tokio::fs::File::open(file)
+        .await
+        .context(format!("could not open {:?}", file))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let n = reader
+            .read(&mut buffer)
+            .await
+            .context(format!("could not hash {:?}", file))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        size += n as u64;
+    }
+    Ok((size, to_hex(&hasher.finalize())))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Deserialize)]
+struct JsonVersion {
+    version: u8,
+}
+
+// State files kept as json, such as checkpoints and upload sessions. A missing file
+// loads as none, and one written by a newer archiver than max_version is refused
+pub fn load_json<T: DeserializeOwned>(
+    path: &PathBuf,
+    what: &str,
+    max_version: Option<u8>,
+) -> anyhow::Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read(path).context(format!("could not read {} {:?}", what, path))?;
+    if let Some(max) = max_version {
+        let version: JsonVersion =
+            serde_json::from_slice(&data).context(format!("invalid {} {:?}", what, path))?;
+        if version.version > max {
+            return Err(anyhow!(
+                "unsupported {} version {} in {:?}",
+                what,
+                version.version,
+                path
+            ));
+        }
+    }
+    let value = serde_json::from_slice(&data).context(format!("invalid {} {:?}", what, path))?;
+    Ok(Some(value))
+}
+
+// written next to its destination then renamed, a crash never leaves half a file
+pub fn write_json<T: Serialize>(path: &PathBuf, what: &str, value: &T) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!("could not create {:?}", dir))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec(value)?)
+        .context(format!("could not write {} {:?}", what, tmp))?;
+    fs::rename(&tmp, path).context(format!("could not move {} to {:?}", what, path))?;
+    Ok(())
+}
+
+pub fn remove_json(path: &PathBuf, what: &str) -> anyhow::Result<()> {
+    if path.exists() {
+        fs::remove_file(path).context(format!("could not remove {} {:?}", what, path))?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -227,6 +310,22 @@ mod test {
         }
     }
 
+    #[test]
+    fn extract_url_test() {
+        assert_eq!(
+            extract_url("http://localhost:8080/aaaa/record/https://example.com/a"),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            extract_url("https://example.com/record/a"),
+            "https://example.com/record/a"
+        );
+        assert_eq!(
+            get_origin("https://example.com:8443/a?b").unwrap(),
+            "https://example.com:8443"
+        );
+    }
+
     #[test]
     fn extract_collection_name_test() {
         let s = extract_collection_name(
@@ -241,4 +340,37 @@ mod test {
         assert!(p.exists());
         fs::remove_dir(p).unwrap();
     }
+
+    #[test]
+    fn writes_and_loads_json() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct State {
+            version: u8,
+            name: String,
+        }
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("nested/state.json");
+        assert!(load_json::<State>(&path, "state", Some(1))
+            .unwrap()
+            .is_none());
+
+        let state = State {
+            version: 1,
+            name: "a".into(),
+        };
+        write_json(&path, "state", &state).unwrap();
+        assert!(!path.with_extension("json.tmp").exists());
+        assert_eq!(
+            load_json::<State>(&path, "state", Some(1)).unwrap(),
+            Some(state)
+        );
+        // written by a newer archiver
+        assert!(load_json::<State>(&path, "state", Some(0)).is_err());
+
+        remove_json(&path, "state").unwrap();
+        assert!(!path.exists());
+        remove_json(&path, "state").unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
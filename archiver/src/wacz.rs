@@ -0,0 +1,352 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    cdxj::{index_warcs, to_cdxj},
+    utils::{extract_url, get_random_string, APP_NAME, APP_VERSION},
+};
+
+pub const WACZ_VERSION: &str = "1.1.1";
+const PAGES_HEADER: &str = r#"{"format":"json-pages-1.0","id":"pages","title":"All Pages"}"#;
+
+// A page entry of pages/pages.jsonl
+#[derive(Debug, Clone)]
+pub struct WaczPage {
+    pub url: String,
+    pub ts: DateTime<Utc>,
+    pub title: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DataPackageResource {
+    pub name: String,
+    pub path: String,
+    pub hash: String,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DataPackage {
+    pub profile: String,
+    pub wacz_version: String,
+    pub title: String,
+    pub created: String,
+    pub software: String,
+    #[serde(rename = "mainPageUrl")]
+    pub main_page_url: String,
+    #[serde(rename = "mainPageDate")]
+    pub main_page_date: String,
+    pub resources: Vec<DataPackageResource>,
+}
+
+// Bundles the WARC files of a crawl along with their index, the list of
+// pages and the screenshot into a single WACZ 1.1 file
+// https://specs.webrecorder.net/wacz/1.1.1/
+#[derive(Debug, Clone)]
+pub struct WaczPackage {
+    title: String,
+    main_page_url: String,
+    main_page_date: DateTime<Utc>,
+    warc_files: Vec<PathBuf>,
+    pages: Vec<WaczPage>,
+    screenshot: Option<PathBuf>,
+}
+
+impl WaczPackage {
+    pub fn new(title: &str, main_page_url: &str, main_page_date: DateTime<Utc>) -> Self {
+        WaczPackage {
+            title: title.into(),
+            main_page_url: main_page_url.into(),
+            main_page_date,
+            warc_files: vec![],
+            pages: vec![],
+            screenshot: None,
+        }
+    }
+
+    pub fn add_warc(&mut self, path: &Path) {
+        self.warc_files.push(path.to_path_buf());
+    }
+
+    pub fn add_page(&mut self, page: WaczPage) {
+        self.pages.push(page);
+    }
+
+    // adds every visited url of a crawl, the proxy prefix is removed for pywb crawls
+    pub fn add_visited(&mut self, visited: &[String], ts: DateTime<Utc>, main_title: &str) {
+        let mut urls = visited
+            .iter()
+            .map(|u| extract_url(u))
+            .collect::<Vec<String>>();
+        // the main page comes first, replay tools open it by default
+        urls.sort_by_key(|u| (u != &self.main_page_url, u.clone()));
+        urls.dedup();
+        for url in urls {
+            let title = if url == self.main_page_url && !main_title.is_empty() {
+                Some(main_title.to_string())
+            } else {
+                None
+            };
+            self.add_page(WaczPage { url, ts, title });
+        }
+    }
+
+    pub fn set_screenshot(&mut self, path: &Path) {
+        self.screenshot = Some(path.to_path_buf());
+    }
+
+    pub fn write(&self, dest: &PathBuf) -> anyhow::Result<DataPackage> {
+        if self.warc_files.is_empty() {
+            return Err(anyhow!("a wacz needs at least one warc file"));
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).context(format!("could not create dir {:?}", parent))?;
+        }
+
+        let file = File::create(dest).context(format!("could not create wacz {:?}", dest))?;
+        let mut zip = ZipWriter::new(file);
+        let mut resources = vec![];
+
+        // warcs and images are already compressed, the wacz spec recommends storing them as is
+        for warc in &self.warc_files {
+            resources.push(Self::add_file(&mut zip, "archive", warc)?);
+        }
+        if let Some(screenshot) = &self.screenshot {
+            resources.push(Self::add_file(&mut zip, "screenshots", screenshot)?);
+        }
+
+        let index = to_cdxj(&index_warcs(&self.warc_files)?);
+        resources.push(Self::add_bytes(
+            &mut zip,
+            "indexes/index.cdx",
+            index.as_bytes(),
+        )?);
+        resources.push(Self::add_bytes(
+            &mut zip,
+            "pages/pages.jsonl",
+            self.pages_jsonl().as_bytes(),
+        )?);
+
+        let package = DataPackage {
+            profile: "data-package".into(),
+            wacz_version: WACZ_VERSION.into(),
+            title: self.title.clone(),
+            created: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            software: format!("{}/{}", APP_NAME.as_str(), APP_VERSION.as_str()),
+            main_page_url: self.main_page_url.clone(),
+            main_page_date: self
+                .main_page_date
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            resources,
+        };
+        let package_json = serde_json::to_vec_pretty(&package)?;
+        Self::add_bytes(&mut zip, "datapackage.json", &package_json)?;
+
+        let package_digest = json!({
+            "path": "datapackage.json",
+            "hash": format!("sha256:{}", hex(&Sha256::digest(&package_json))),
+        });
+        Self::add_bytes(
+            &mut zip,
+            "datapackage-digest.json",
+            package_digest.to_string().as_bytes(),
+        )?;
+
+        zip.finish()
+            .context(format!("could not finish writing wacz {:?}", dest))?;
+        debug!("wacz written to {:?}", dest);
+        Ok(package)
+    }
+
+    // the pages.jsonl of the package on its own, for crawls that are not packaged
+    pub fn write_pages(&self, dest: &PathBuf) -> anyhow::Result<()> {
+        fs::write(dest, self.pages_jsonl()).context(format!("could not write pages {:?}", dest))
+    }
+
+    fn pages_jsonl(&self) -> String {
+        let mut lines = vec![PAGES_HEADER.to_string()];
+        for page in &self.pages {
+            let mut entry = json!({
+                "id": get_random_string(22),
+                "url": page.url,
+                "ts": page.ts.to_rfc3339_opts(SecondsFormat::Secs, true),
+            });
+            if let Some(title) = &page.title {
+                entry["title"] = json!(title);
+            }
+            lines.push(entry.to_string());
+        }
+        lines.join("\n") + "\n"
+    }
+
+    fn add_file(
+        zip: &mut ZipWriter<File>,
+        dir: &str,
+        path: &PathBuf,
+    ) -> anyhow::Result<DataPackageResource> {
+        let name = match path.file_name() {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => return Err(anyhow!("invalid file path {:?}", path)),
+        };
+        let zip_path = format!("{}/{}", dir, name);
+        let bytes = fs::metadata(path)
+            .context(format!("could not read metadata of {:?}", path))?
+            .len();
+
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(bytes >= u32::MAX as u64);
+        zip.start_file(&zip_path, options)?;
+
+        let mut reader = HashingReader {
+            inner: File::open(path).context(format!("could not open {:?}", path))?,
+            hasher: Sha256::new(),
+        };
+        io::copy(&mut reader, zip).context(format!("could not add {:?} to wacz", path))?;
+
+        Ok(DataPackageResource {
+            name,
+            path: zip_path,
+            hash: format!("sha256:{}", hex(&reader.hasher.finalize())),
+            bytes,
+        })
+    }
+
+    fn add_bytes(
+        zip: &mut ZipWriter<File>,
+        zip_path: &str,
+        data: &[u8],
+    ) -> anyhow::Result<DataPackageResource> {
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file(zip_path, options)?;
+        zip.write_all(data)
+            .context(format!("could not add {} to wacz", zip_path))?;
+
+        Ok(DataPackageResource {
+            name: zip_path.rsplit('/').next().unwrap_or(zip_path).into(),
+            path: zip_path.into(),
+            hash: format!("sha256:{}", hex(&Sha256::digest(data))),
+            bytes: data.len() as u64,
+        })
+    }
+}
+
+// hashes the content as it is copied into the archive
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use zip::ZipArchive;
+
+    use crate::{
+        utils::create_random_tmp_folder,
+        warc::{WarcFile, WarcRecord},
+    };
+
+    use super::*;
+
+    #[test]
+    fn packages_a_crawl() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut warc = dir.clone();
+        warc.push("archiver_20230125160157_example.com_1.warc.gz");
+        let mut file = WarcFile::create(&warc).unwrap();
+        file.write(&WarcRecord::response(
+            "https://example.com/",
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html",
+            b"<html></html>",
+        ))
+        .unwrap();
+        file.flush().unwrap();
+
+        let mut screenshot = dir.clone();
+        screenshot.push("archiver_20230125160157_example.com_1.png");
+        fs::write(&screenshot, b"png").unwrap();
+
+        let mut package = WaczPackage::new("example", "https://example.com/", Utc::now());
+        package.add_warc(&warc);
+        package.set_screenshot(&screenshot);
+        package.add_visited(
+            &[
+                "http://localhost:8080/coll/record/https://example.com/".to_string(),
+                "https://example.com/about".to_string(),
+            ],
+            Utc::now(),
+            "Example",
+        );
+
+        let mut dest = dir.clone();
+        dest.push("example.wacz");
+        let data_package = package.write(&dest).unwrap();
+
+        let paths = data_package
+            .resources
+            .iter()
+            .map(|r| r.path.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            paths,
+            vec![
+                "archive/archiver_20230125160157_example.com_1.warc.gz",
+                "screenshots/archiver_20230125160157_example.com_1.png",
+                "indexes/index.cdx",
+                "pages/pages.jsonl",
+            ]
+        );
+        assert_eq!(
+            data_package.resources[1].hash,
+            format!("sha256:{}", hex(&Sha256::digest(b"png")))
+        );
+
+        let mut archive = ZipArchive::new(File::open(&dest).unwrap()).unwrap();
+        let mut pages = String::new();
+        archive
+            .by_name("pages/pages.jsonl")
+            .unwrap()
+            .read_to_string(&mut pages)
+            .unwrap();
+        let lines = pages.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], PAGES_HEADER);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains(r#""title":"Example""#));
+        assert!(lines[1].contains(r#""url":"https://example.com/""#));
+        assert!(lines[2].contains(r#""url":"https://example.com/about""#));
+
+        let mut index = String::new();
+        archive
+            .by_name("indexes/index.cdx")
+            .unwrap()
+            .read_to_string(&mut index)
+            .unwrap();
+        assert!(index.starts_with("com,example)/ "));
+        assert!(archive.by_name("datapackage.json").is_ok());
+        assert!(archive.by_name("datapackage-digest.json").is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
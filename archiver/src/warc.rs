@@ -0,0 +1,330 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+pub const WARC_VERSION: &str = "WARC/1.1";
+const WARC_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6fZ";
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarcRecordType {
+    Warcinfo,
+    Request,
+    Response,
+    Metadata,
+}
+
+impl WarcRecordType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WarcRecordType::Warcinfo => "warcinfo",
+            WarcRecordType::Request => "request",
+            WarcRecordType::Response => "response",
+            WarcRecordType::Metadata => "metadata",
+        }
+    }
+}
+
+// A single WARC 1.1 record, the mandatory WARC-Record-ID, WARC-Date,
+// Content-Length and digest headers are computed when serializing
+#[derive(Debug, Clone)]
+pub struct WarcRecord {
+    record_type: WarcRecordType,
+    id: String,
+    date: DateTime<Utc>,
+    headers: Vec<(String, String)>,
+    block: Vec<u8>,
+}
+
+impl WarcRecord {
+    pub fn new(record_type: WarcRecordType, content_type: &str, block: Vec<u8>) -> Self {
+        WarcRecord {
+            record_type,
+            id: new_record_id(),
+            date: Utc::now(),
+            headers: vec![("Content-Type".into(), content_type.into())],
+            block,
+        }
+    }
+
+    pub fn warcinfo(filename: &str, fields: &[(&str, &str)]) -> Self {
+        let mut record = WarcRecord::new(
+            WarcRecordType::Warcinfo,
+            "application/warc-fields",
+            warc_fields(fields),
+        );
+        record.add_header("WARC-Filename", filename);
+        record
+    }
+
+    // http_head is the status/request line and headers, without the trailing blank line
+    pub fn request(target_uri: &str, http_head: &str, body: &[u8]) -> Self {
+        let mut record = WarcRecord::new(
+            WarcRecordType::Request,
+            "application/http; msgtype=request",
+            http_message(http_head, body),
+        );
+        record.add_header("WARC-Target-URI", target_uri);
+        record
+    }
+
+    pub fn response(target_uri: &str, http_head: &str, body: &[u8]) -> Self {
+        let mut record = WarcRecord::new(
+            WarcRecordType::Response,
+            "application/http; msgtype=response",
+            http_message(http_head, body),
+        );
+        record.add_header("WARC-Target-URI", target_uri);
+        record.add_header("WARC-Payload-Digest", &digest(body));
+        record
+    }
+
+    pub fn metadata(target_uri: &str, fields: &[(&str, &str)]) -> Self {
+        let mut record = WarcRecord::new(
+            WarcRecordType::Metadata,
+            "application/warc-fields",
+            warc_fields(fields),
+        );
+        record.add_header("WARC-Target-URI", target_uri);
+        record
+    }
+
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.into(), value.into()));
+    }
+
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn record_type(&self) -> WarcRecordType {
+        self.record_type.clone()
+    }
+
+    pub fn date(&self) -> DateTime<Utc> {
+        self.date
+    }
+
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.clone())
+    }
+
+    pub fn block(&self) -> &[u8] {
+        &self.block
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("{}\r\n", WARC_VERSION);
+        head.push_str(&format!("WARC-Type: {}\r\n", self.record_type.as_str()));
+        head.push_str(&format!("WARC-Record-ID: {}\r\n", self.id));
+        head.push_str(&format!(
+            "WARC-Date: {}\r\n",
+            self.date.format(WARC_DATE_FORMAT)
+        ));
+        for (name, value) in &self.headers {
+            // header values can't contain line breaks
+            let value = value.replace(['\r', '\n'], " ");
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("WARC-Block-Digest: {}\r\n", digest(&self.block)));
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.block.len()));
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.block);
+        bytes.extend_from_slice(b"\r\n\r\n");
+        bytes
+    }
+}
+
+// Appends records to a .warc.gz file, each record being its own gzip member
+// so that records can be read independently given their offset
+pub struct WarcFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    offset: u64,
+}
+
+impl WarcFile {
+    pub fn create(path: &PathBuf) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("could not open warc file {:?}", path))?;
+        let offset = file.metadata()?.len();
+
+        Ok(WarcFile {
+            path: path.clone(),
+            writer: BufWriter::new(file),
+            offset,
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    // returns the offset and compressed length of the record
+    pub fn write(&mut self, record: &WarcRecord) -> anyhow::Result<(u64, u64)> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&record.to_bytes())?;
+        let member = encoder.finish()?;
+
+        self.writer
+            .write_all(&member)
+            .context(format!("could not write record to {:?}", self.path))?;
+
+        let offset = self.offset;
+        self.offset += member.len() as u64;
+        Ok((offset, member.len() as u64))
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer
+            .flush()
+            .context(format!("could not flush warc file {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+// digest in the <algorithm>:<base32> form used by WARC headers
+pub fn digest(data: &[u8]) -> String {
+    let hash = Sha256::digest(data);
+    format!("sha256:{}", base32(&hash))
+}
+
+pub fn base32(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    for chunk in data.chunks(5) {
+        let mut buf = [0u8; 5];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let bits = u64::from_be_bytes([0, 0, 0, buf[0], buf[1], buf[2], buf[3], buf[4]]);
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for i in 0..8 {
+            if i < chars {
+                let idx = ((bits >> (35 - i * 5)) & 0x1f) as usize;
+                out.push(BASE32_ALPHABET[idx] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn new_record_id() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    // uuid v4
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!(
+        "<urn:uuid:{}-{}-{}-{}-{}>",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+fn warc_fields(fields: &[(&str, &str)]) -> Vec<u8> {
+    fields
+        .iter()
+        .map(|(k, v)| format!("{}: {}\r\n", k, v.replace(['\r', '\n'], " ")))
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn http_message(http_head: &str, body: &[u8]) -> Vec<u8> {
+    let mut block = format!("{}\r\n\r\n", http_head.trim_end()).into_bytes();
+    block.extend_from_slice(body);
+    block
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use flate2::read::MultiGzDecoder;
+
+    use crate::utils::create_random_tmp_folder;
+
+    use super::*;
+
+    #[test]
+    fn encodes_base32() {
+        assert_eq!(base32(b""), "");
+        assert_eq!(base32(b"f"), "MY======");
+        assert_eq!(base32(b"fo"), "MZXQ====");
+        assert_eq!(base32(b"foob"), "MZXW6YQ=");
+        assert_eq!(base32(b"fooba"), "MZXW6YTB");
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI======");
+    }
+
+    #[test]
+    fn serializes_response_record() {
+        let record = WarcRecord::response(
+            "https://example.com/",
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html",
+            b"<html></html>",
+        );
+        let bytes = String::from_utf8(record.to_bytes()).unwrap();
+
+        assert!(bytes.starts_with("WARC/1.1\r\nWARC-Type: response\r\n"));
+        assert!(bytes.contains("WARC-Target-URI: https://example.com/\r\n"));
+        assert!(bytes.contains("Content-Type: application/http; msgtype=response\r\n"));
+        assert!(bytes.contains(&format!(
+            "WARC-Payload-Digest: {}\r\n",
+            digest(b"<html></html>")
+        )));
+        let block = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<html></html>";
+        assert!(bytes.contains(&format!("Content-Length: {}\r\n\r\n", block.len())));
+        assert!(bytes.ends_with(&format!("{}\r\n\r\n", block)));
+    }
+
+    #[test]
+    fn writes_one_gzip_member_per_record() {
+        let dir = create_random_tmp_folder().unwrap();
+        let mut path = dir.clone();
+        path.push("test.warc.gz");
+
+        let mut file = WarcFile::create(&path).unwrap();
+        let info = WarcRecord::warcinfo("test.warc.gz", &[("software", "archiver")]);
+        let (first_offset, first_len) = file.write(&info).unwrap();
+        let (second_offset, _) = file
+            .write(&WarcRecord::metadata(
+                "https://example.com/",
+                &[("outlink", "a")],
+            ))
+            .unwrap();
+        file.flush().unwrap();
+
+        assert_eq!(first_offset, 0);
+        assert_eq!(second_offset, first_len);
+
+        let mut content = String::new();
+        MultiGzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content.matches("WARC/1.1\r\n").count(), 2);
+        assert!(content.contains("WARC-Type: warcinfo"));
+        assert!(content.contains("outlink: a\r\n"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
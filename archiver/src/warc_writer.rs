@@ -7,7 +7,7 @@ use std::{
     net,
     path::PathBuf,
     process::{self, Command, Stdio},
-    sync::mpsc::sync_channel,
+    sync::{mpsc::sync_channel, Arc},
     thread::{self},
 };
 
@@ -17,11 +17,17 @@ use anyhow::{anyhow, Context};
 use redis::Commands;
 use sysinfo::{PidExt, System, SystemExt};
 
-use crate::utils::{create_random_tmp_folder, get_random_string, get_tmp_screenshot_dir};
+use crate::{
+    capture::{CaptureBackend, WarcRecorder},
+    utils::{create_random_tmp_folder, get_random_string, get_tmp_screenshot_dir},
+};
 
 pub struct WarcWriter {
+    // port of the pywb proxy, 0 when capturing natively
     port: u16,
-    process: std::process::Child,
+    process: Option<std::process::Child>,
+    recorder: Option<Arc<WarcRecorder>>,
+    backend: CaptureBackend,
     archive_dir: PathBuf,
     archive_name: String,
 }
@@ -32,6 +38,7 @@ impl WarcWriter {
         parent_dir: Option<PathBuf>,
         archive_name: Option<String>,
         debug: bool,
+        backend: CaptureBackend,
     ) -> anyhow::Result<Self> {
         let archive_name = if let Some(n) = archive_name {
             debug!("archive name chosen: {}", n);
@@ -52,6 +59,27 @@ impl WarcWriter {
             d
         };
 
+        let mut archive_dir = parent_dir.clone();
+        archive_dir.push("collections");
+        archive_dir.push(archive_name.clone());
+        archive_dir.push("archive");
+
+        if backend == CaptureBackend::Native {
+            Self::setup_native_dir(&archive_dir)
+                .context("could not setup necessary directories")?;
+            let recorder = WarcRecorder::new(&archive_dir, &archive_name)
+                .context("could not create native warc recorder")?;
+
+            return Ok(WarcWriter {
+                port: 0,
+                process: None,
+                recorder: Some(Arc::new(recorder)),
+                backend,
+                archive_dir,
+                archive_name,
+            });
+        }
+
         Self::init_wayback_config(&parent_dir).context("could not initialize wayback configs")?;
 
         Self::setup_dir(&archive_name, &parent_dir)
@@ -129,14 +157,11 @@ impl WarcWriter {
             return Err(anyhow!("wayback error: process is not running"));
         }
 
-        let mut archive_dir = parent_dir.clone();
-        archive_dir.push("collections");
-        archive_dir.push(archive_name.clone());
-        archive_dir.push("archive");
-
         Ok(WarcWriter {
             port,
-            process,
+            process: Some(process),
+            recorder: None,
+            backend,
             archive_dir,
             archive_name,
         })
@@ -154,6 +179,15 @@ impl WarcWriter {
         self.archive_name.clone()
     }
 
+    pub fn backend(&self) -> CaptureBackend {
+        self.backend.clone()
+    }
+
+    // the recorder browsers write to, only set with the native backend
+    pub fn recorder(&self) -> Option<Arc<WarcRecorder>> {
+        self.recorder.clone()
+    }
+
     fn fetch_all_warcs(&self) -> anyhow::Result<Vec<DirEntry>> {
         let dir = fs::read_dir(self.archive_dir())
             .context(format!("could not read_dir {:?}", self.archive_dir()))?;
@@ -178,9 +212,14 @@ impl WarcWriter {
     }
 
     pub fn rename_warc_files(&self, new_name: &str, depth: i32) -> anyhow::Result<Vec<PathBuf>> {
+        if let Some(recorder) = &self.recorder {
+            recorder
+                .finish()
+                .context("could not close native warc file")?;
+        }
         let warcs = self.fetch_all_warcs()?;
 
-        let filenames = warcs
+        let mut filenames = warcs
             .iter()
             .filter_map(|x| {
                 let file_name = x.file_name();
@@ -212,9 +251,15 @@ impl WarcWriter {
                         }
                     }
                 }
+                // renamed by an earlier run of a resumed crawl
+                if file_name.starts_with("archiver_") && file_name.ends_with(".warc.gz") {
+                    return Some(x.path());
+                }
                 None
             })
             .collect::<Vec<PathBuf>>();
+        // the first file is the oldest one, the crawl is dated after it
+        filenames.sort();
 
         Ok(filenames)
     }
@@ -230,6 +275,10 @@ impl WarcWriter {
         dir.push("screenshots");
         dir.push(format!("archiver_{}_{}_{}.png", ts, encode(domain), depth));
         let screenshot_dir = get_tmp_screenshot_dir(&self.archive_name);
+        // the screenshot of a resumed crawl was moved by the run that took it
+        if !PathBuf::from(&screenshot_dir).exists() && dir.exists() {
+            return Ok(dir);
+        }
         fs::copy(&screenshot_dir, &dir).context(format!(
             "could not copy screenshot from {:?} to {:?}",
             &screenshot_dir, &dir
@@ -239,9 +288,35 @@ impl WarcWriter {
         Ok(dir)
     }
 
+    // files describing the whole crawl (manifest, pages) sit next to its warc files
+    pub fn crawl_file_path(&self, ts: &str, domain: &str, depth: i32, suffix: &str) -> PathBuf {
+        let mut path = self.archive_dir.clone();
+        path.push(format!(
+            "archiver_{}_{}_{}.{}",
+            ts,
+            encode(domain),
+            depth,
+            suffix
+        ));
+        path
+    }
+
+    pub fn wacz_path(&self, ts: &str, domain: &str, depth: i32) -> PathBuf {
+        let mut path = self.archive_dir.clone();
+        path.pop();
+        path.push("wacz");
+        path.push(format!("archiver_{}_{}_{}.wacz", ts, encode(domain), depth));
+        path
+    }
+
     pub fn terminate(&mut self) -> anyhow::Result<()> {
-        debug!("killing warc writer process with id {}", self.process.id());
-        self.process.kill()?;
+        if let Some(recorder) = &self.recorder {
+            recorder.finish()?;
+        }
+        if let Some(process) = &mut self.process {
+            debug!("killing warc writer process with id {}", process.id());
+            process.kill()?;
+        }
         Ok(())
     }
 
@@ -311,6 +386,18 @@ impl WarcWriter {
         Ok(())
     }
 
+    // same layout as a wb-manager collection, without needing pywb installed
+    fn setup_native_dir(archive_dir: &PathBuf) -> anyhow::Result<()> {
+        fs::create_dir_all(archive_dir)
+            .context(format!("could not create dir {:?}", archive_dir))?;
+        let mut screenshots = archive_dir.clone();
+        screenshots.pop();
+        screenshots.push("screenshots");
+        fs::create_dir_all(&screenshots)
+            .context(format!("could not create dir {:?}", screenshots))?;
+        Ok(())
+    }
+
     // Wayback config necessary for the application to work as desired
     fn init_wayback_config(path: &PathBuf) -> anyhow::Result<()> {
         let cfg = r#"
@@ -344,7 +431,11 @@ impl WarcWriter {
 
 #[cfg(test)]
 mod test {
-    use crate::utils::create_random_tmp_folder;
+    use crate::{
+        manifest::{ArchiveManifest, MANIFEST_SUFFIX},
+        types::ArchiveInfo,
+        utils::create_random_tmp_folder,
+    };
 
     use super::*;
 
@@ -354,4 +445,31 @@ mod test {
         WarcWriter::setup_dir("example".into(), &p).unwrap();
         fs::remove_dir_all(p).unwrap();
     }
+
+    #[test]
+    fn writes_native_warcs_without_pywb() {
+        let p = create_random_tmp_folder().unwrap();
+        let mut writer = WarcWriter::new(
+            None,
+            Some(p.clone()),
+            Some("example".into()),
+            false,
+            CaptureBackend::Native,
+        )
+        .unwrap();
+        assert!(writer.recorder().is_some());
+
+        let files = writer.rename_warc_files("example.com", 1).unwrap();
+        assert_eq!(files.len(), 1);
+        let name = files[0].file_name().unwrap().to_str().unwrap().to_string();
+        assert!(name.starts_with("archiver_") && name.ends_with("_example.com_1.warc.gz"));
+
+        // the manifest is named after the crawl, which is dated like its warc file
+        let info = ArchiveInfo::from_name(&files[0]).unwrap();
+        let manifest = writer.crawl_file_path(&info.string_ts(), "example.com", 1, MANIFEST_SUFFIX);
+        assert_eq!(manifest, ArchiveManifest::sidecar_path(&files[0]));
+
+        writer.terminate().unwrap();
+        fs::remove_dir_all(p).unwrap();
+    }
 }
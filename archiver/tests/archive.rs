@@ -0,0 +1,345 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
+};
+
+use archiver::{
+    browser_pool::{BrowserPool, BrowserPoolOptions},
+    budget::CrawlBudget,
+    capture::CaptureBackend,
+    cdxj::{index_warcs, CdxjIndex},
+    crawler::Crawler,
+    fetcher::ChromeFetcher,
+    politeness::{HostPoliteness, PolitenessOptions},
+    readiness::{PageReadiness, ReadinessStrategy},
+    robots::{RobotsCache, RobotsPolicy},
+    runner::{Runner, RunnerOptions},
+    scope::ScopeRules,
+    utils::{create_random_tmp_folder, get_tmp_screenshot_dir},
+    warc_writer::WarcWriter,
+};
+use atw::state::CrawlType;
+use support::{Fixture, FixtureServer, FixtureSite};
+
+mod support;
+
+macro_rules! aw {
+    ($e:expr) => {
+        tokio_test::block_on($e)
+    };
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// a home page linking to every kind of fixture, other.localhost is a second site
+fn start_fixture_server() -> FixtureServer {
+    let site = FixtureSite::new()
+        .page(
+            "/",
+            "Fixture home",
+            &[
+                "/a",
+                "/redirect",
+                "/doc.pdf",
+                "/slow",
+                "/missing",
+                "/error",
+                "/busy",
+                "other.localhost/b",
+                // nothing listens there
+                "http://localhost:1/",
+            ],
+        )
+        .page("/a", "Page A", &["/"])
+        .redirect("/redirect", "/target")
+        .page("/target", "Redirect target", &[])
+        .pdf("/doc.pdf")
+        .slow(
+            "/slow",
+            Duration::from_secs(2),
+            Fixture::Html("<html><head><title>Slow</title></head></html>".into()),
+        )
+        .status("/error", 500)
+        .unavailable("/busy", 1)
+        .page("other.localhost/b", "Page B", &[]);
+    FixtureServer::start(site)
+}
+
+fn index(warc_files: &[PathBuf]) -> CdxjIndex {
+    CdxjIndex::new(index_warcs(warc_files).expect("could not index the warc files"))
+}
+
+fn status_of(index: &CdxjIndex, url: &str) -> Option<String> {
+    index
+        .lookup(url)
+        .unwrap()
+        .first()
+        .and_then(|r| r.status.clone())
+}
+
+#[test]
+fn serves_the_fixture_site() -> anyhow::Result<()> {
+    let server = start_fixture_server();
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve("other.localhost", server.addr())
+        .build()?;
+
+    let home = client.get(server.url("/")).send()?;
+    assert_eq!(home.status(), 200);
+    let html = home.text()?;
+    assert!(html.contains("<title>Fixture home</title>"));
+    assert!(html.contains(&server.url("other.localhost/b")));
+
+    let redirect = client.get(server.url("/redirect")).send()?;
+    assert_eq!(redirect.status(), 302);
+    assert_eq!(redirect.headers()["Location"], "/target");
+
+    let pdf = client.get(server.url("/doc.pdf")).send()?;
+    assert_eq!(pdf.headers()["Content-Type"], "application/pdf");
+    assert!(pdf.bytes()?.starts_with(b"%PDF"));
+
+    assert_eq!(client.get(server.url("/missing")).send()?.status(), 404);
+    assert_eq!(client.get(server.url("/error")).send()?.status(), 500);
+    let busy = client.get(server.url("/busy")).send()?;
+    assert_eq!(busy.status(), 503);
+    assert_eq!(busy.headers()["Retry-After"], "1");
+
+    // routed by the host header, the path alone is not part of localhost
+    let other = client.get(server.url("other.localhost/b")).send()?;
+    assert!(other.text()?.contains("<title>Page B</title>"));
+    assert_eq!(client.get(server.url("/b")).send()?.status(), 404);
+
+    let start = Instant::now();
+    assert_eq!(client.get(server.url("/slow")).send()?.status(), 200);
+    assert!(start.elapsed() >= Duration::from_secs(2));
+
+    assert!(server
+        .requests()
+        .contains(&String::from("other.localhost/b")));
+    Ok(())
+}
+
+#[test]
+fn fetches_robots_txt_by_status() -> anyhow::Result<()> {
+    let robots = "User-agent: *\nDisallow: /private\n";
+    let server =
+        FixtureServer::start(FixtureSite::new().route("/robots.txt", Fixture::Html(robots.into())));
+    let mut cache = RobotsCache::new();
+    let robots = aw!(cache.get(&server.url("/")))?;
+    assert!(robots.is_allowed(&server.url("/public")));
+    assert!(!robots.is_allowed(&server.url("/private")));
+
+    // a missing or forbidden robots.txt allows everything, errors and rate limits
+    // disallow everything
+    for (status, allowed) in [
+        (404, true),
+        (403, true),
+        (429, false),
+        (500, false),
+        (503, false),
+    ] {
+        let server = FixtureServer::start(FixtureSite::new().status("/robots.txt", status));
+        let robots = aw!(cache.get(&server.url("/")))?;
+        assert_eq!(
+            robots.is_allowed(&server.url("/page")),
+            allowed,
+            "{}",
+            status
+        );
+    }
+
+    // nothing listens on the port once the listener is dropped
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let url = format!("http://localhost:{}/page", port);
+    assert!(!aw!(cache.get(&url))?.is_allowed(&url));
+    Ok(())
+}
+
+#[test]
+fn prefetches_robots_txt_concurrently() -> anyhow::Result<()> {
+    let delay = Duration::from_secs(1);
+    let slow_robots = || {
+        FixtureServer::start(FixtureSite::new().slow("/robots.txt", delay, Fixture::Status(404)))
+    };
+    let servers = [slow_robots(), slow_robots(), slow_robots()];
+    let urls = servers
+        .iter()
+        .map(|s| s.url("/page"))
+        .collect::<Vec<String>>();
+
+    let mut cache = RobotsCache::new();
+    let start = Instant::now();
+    aw!(cache.prefetch(&urls));
+    assert!(start.elapsed() < delay * 2);
+
+    // every origin is cached, allowed pages are answered right away
+    let start = Instant::now();
+    for url in urls.iter() {
+        assert!(aw!(cache.get(url))?.is_allowed(url));
+    }
+    assert!(start.elapsed() < delay);
+    for server in servers.iter() {
+        assert_eq!(
+            server.requests(),
+            vec![String::from("localhost/robots.txt")]
+        );
+    }
+    Ok(())
+}
+
+/*
+cargo test --package archiver --test archive -- --ignored
+ */
+#[test]
+#[ignore = "needs chrome"]
+fn crawls_the_fixture_site_with_chrome() -> anyhow::Result<()> {
+    let server = start_fixture_server();
+    let dir = create_random_tmp_folder()?;
+    let collection = String::from("fixture");
+    let writer = WarcWriter::new(
+        None,
+        Some(dir.clone()),
+        Some(collection.clone()),
+        false,
+        CaptureBackend::Native,
+    )?;
+    let browser_pool = Arc::new(BrowserPool::new(
+        BrowserPoolOptions::default_builder().size(1usize).build()?,
+    )?);
+    let seed = server.url("/");
+    let seeds = vec![seed.clone()];
+    let mut crawler = Crawler::new(
+        &format!("http://localhost:{}", server.port()),
+        &seeds,
+        &seeds,
+        1,
+        CrawlType::DomainAndLinks,
+        4,
+        1,
+        Arc::new(ChromeFetcher::new(
+            browser_pool,
+            PageReadiness::new(ReadinessStrategy::NetworkIdle, 1, 5),
+            writer.recorder(),
+        )),
+        writer.recorder(),
+        Arc::new(HostPoliteness::new(
+            PolitenessOptions::default_builder()
+                .max_tabs_per_host(4usize)
+                .min_host_delay(0u64)
+                .build()?,
+        )),
+        RobotsPolicy::Ignore,
+        false,
+        0,
+        ScopeRules::default(),
+        CrawlBudget::default(),
+        writer.archive_dir(),
+    );
+    let crawl = aw!(crawler.crawl(Arc::new(AtomicBool::new(false))))?;
+
+    assert_eq!(crawl.main_title(), "Fixture home");
+    let visited = crawl.visited();
+    // error pages are still pages, they are archived as they were served
+    for route in [
+        "/",
+        "/a",
+        "/redirect",
+        "/doc.pdf",
+        "/slow",
+        "/missing",
+        "/error",
+        "other.localhost/b",
+    ] {
+        assert!(
+            visited.contains(&server.url(route)),
+            "{} was not visited in {:?}",
+            route,
+            visited
+        );
+    }
+    // a host asking to come back later and one that never answers
+    assert_eq!(
+        crawl.failed(),
+        HashSet::from([server.url("/busy"), String::from("http://localhost:1/")])
+    );
+
+    let screenshot = get_tmp_screenshot_dir(&collection);
+    assert!(fs::read(&screenshot)?.starts_with(&PNG_SIGNATURE));
+    fs::remove_file(screenshot)?;
+
+    let warc_files = writer.rename_warc_files("localhost", 1)?;
+    assert_eq!(warc_files.len(), 1);
+    let index = index(&warc_files);
+    assert_eq!(status_of(&index, &server.url("/")), Some("200".into()));
+    assert_eq!(
+        status_of(&index, &server.url("/redirect")),
+        Some("302".into())
+    );
+    assert_eq!(
+        status_of(&index, &server.url("/target")),
+        Some("200".into())
+    );
+    assert_eq!(
+        status_of(&index, &server.url("/missing")),
+        Some("404".into())
+    );
+    assert_eq!(status_of(&index, &server.url("/error")), Some("500".into()));
+    assert_eq!(
+        index.lookup(&server.url("/doc.pdf"))?[0].mime,
+        "application/pdf"
+    );
+    assert!(!index.lookup(&server.url("other.localhost/b"))?.is_empty());
+
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs chrome"]
+fn archives_the_fixture_site() -> anyhow::Result<()> {
+    let server = start_fixture_server();
+    let dir = create_random_tmp_folder()?;
+    let options = RunnerOptions::default_builder()
+        .writer_dir(Some(dir.clone()))
+        .writer_port(None)
+        .capture_backend(CaptureBackend::Native)
+        .crawl_depth(1)
+        .crawl_type(CrawlType::DomainOnly)
+        .url_retries(0)
+        .min_wait_after_navigation(1u64)
+        .max_wait_after_navigation(5u64)
+        .min_host_delay(0u64)
+        .archive_name(Some(String::from("fixture")))
+        .build()?;
+    let runner = aw!(Runner::new(options))?;
+    let result = aw!(runner.run_archiving(&server.url("/")))?;
+
+    assert_eq!(result.title, "Fixture home");
+    assert_eq!(result.warc_files.len(), 1);
+    assert_eq!(result.index_files.len(), 1);
+    let index = CdxjIndex::load_all(&result.index_files)?;
+    for route in ["/", "/a", "/target", "/doc.pdf", "/slow"] {
+        assert!(
+            !index.lookup(&server.url(route))?.is_empty(),
+            "{} was not archived",
+            route
+        );
+    }
+    // the crawl keeps to its domain
+    assert!(index.lookup(&server.url("other.localhost/b"))?.is_empty());
+    assert!(!server
+        .requests()
+        .contains(&String::from("other.localhost/b")));
+
+    assert!(fs::read(&result.screenshot_file)?.starts_with(&PNG_SIGNATURE));
+    assert!(result.manifest_file.exists());
+
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
@@ -0,0 +1,85 @@
+#!/usr/bin/env python3
+# Writes data_item.bin, an ANS-104 data item signed with test_wallet.json, for the
+# known-answer test of src/ans104.rs. It follows the serialization and deep hash of
+# arbundles (ArweaveSigner, no target nor anchor) independently of the crate code.
+#
+#   python3 tests/fixtures/data_item.py
+import base64
+import hashlib
+import json
+import os
+
+from cryptography.hazmat.primitives import hashes
+from cryptography.hazmat.primitives.asymmetric import padding, rsa
+
+DIR = os.path.dirname(os.path.abspath(__file__))
+DATA = b"archived by the archiver\n"
+TAGS = [(b"Content-Type", b"text/plain"), (b"App-Name", b"archiver-fixture")]
+
+
+def b64(value):
+    return int.from_bytes(base64.urlsafe_b64decode(value + "=" * (-len(value) % 4)), "big")
+
+
+def avro_long(n):
+    n = (n << 1) ^ (n >> 63)
+    out = b""
+    while n & ~0x7F:
+        out += bytes([(n & 0x7F) | 0x80])
+        n >>= 7
+    return out + bytes([n])
+
+
+def serialize_tags(tags):
+    if not tags:
+        return b""
+    out = avro_long(len(tags))
+    for name, value in tags:
+        out += avro_long(len(name)) + name + avro_long(len(value)) + value
+    return out + avro_long(0)
+
+
+def deep_hash(data):
+    if isinstance(data, list):
+        acc = hashlib.sha384(b"list" + str(len(data)).encode()).digest()
+        for item in data:
+            acc = hashlib.sha384(acc + deep_hash(item)).digest()
+        return acc
+    tag = hashlib.sha384(b"blob" + str(len(data)).encode()).digest()
+    return hashlib.sha384(tag + hashlib.sha384(data).digest()).digest()
+
+
+with open(os.path.join(DIR, "test_wallet.json")) as f:
+    jwk = json.load(f)
+key = rsa.RSAPrivateNumbers(
+    p=b64(jwk["p"]),
+    q=b64(jwk["q"]),
+    d=b64(jwk["d"]),
+    dmp1=b64(jwk["dp"]),
+    dmq1=b64(jwk["dq"]),
+    iqmp=b64(jwk["qi"]),
+    public_numbers=rsa.RSAPublicNumbers(b64(jwk["e"]), b64(jwk["n"])),
+).private_key()
+
+owner = b64(jwk["n"]).to_bytes(512, "big")
+tags = serialize_tags(TAGS)
+message = deep_hash([b"dataitem", b"1", b"1", owner, b"", b"", tags, DATA])
+signature = key.sign(
+    message,
+    padding.PSS(mgf=padding.MGF1(hashes.SHA256()), salt_length=32),
+    hashes.SHA256(),
+)
+
+item = (
+    (1).to_bytes(2, "little")
+    + signature
+    + owner
+    + b"\x00\x00"
+    + len(TAGS).to_bytes(8, "little")
+    + len(tags).to_bytes(8, "little")
+    + tags
+    + DATA
+)
+with open(os.path.join(DIR, "data_item.bin"), "wb") as f:
+    f.write(item)
+print(base64.urlsafe_b64encode(hashlib.sha256(signature).digest()).rstrip(b"=").decode())
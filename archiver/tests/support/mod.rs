@@ -0,0 +1,290 @@
+// Local fixture site for end-to-end tests, served over plain HTTP on 127.0.0.1.
+// Pages are routed by host and path: paths starting with / belong to localhost, the
+// others name their host, e.g. other.localhost/page. Chrome resolves every subdomain
+// of localhost to the loopback address, so they act as other sites of the crawl.
+// Links between pages are written as routes too, they get the port of the server
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// the smallest valid pdf document
+pub const PDF: &[u8] = b"%PDF-1.1\n1 0 obj<</Type/Catalog/Pages 2 0 R>>endobj\n2 0 obj<</Type/Pages/Kids[]/Count 0>>endobj\ntrailer<</Root 1 0 R>>\n%%EOF\n";
+
+#[derive(Debug, Clone)]
+pub enum Fixture {
+    // an html page linking to routes or absolute urls
+    Page { title: String, links: Vec<String> },
+    Html(String),
+    Pdf(Vec<u8>),
+    // 302 to the location
+    Redirect(String),
+    // an empty page with the status
+    Status(u16),
+    // 503 asking to come back after that many seconds
+    Unavailable(u64),
+    // answers the fixture after the delay
+    Slow(Duration, Box<Fixture>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FixtureSite {
+    routes: HashMap<String, Fixture>,
+}
+
+impl FixtureSite {
+    pub fn new() -> Self {
+        FixtureSite::default()
+    }
+
+    // an html page with a title and links
+    pub fn page(self, route: &str, title: &str, links: &[&str]) -> Self {
+        self.route(
+            route,
+            Fixture::Page {
+                title: title.into(),
+                links: links.iter().map(|l| l.to_string()).collect(),
+            },
+        )
+    }
+
+    pub fn redirect(self, route: &str, location: &str) -> Self {
+        self.route(route, Fixture::Redirect(location.into()))
+    }
+
+    pub fn pdf(self, route: &str) -> Self {
+        self.route(route, Fixture::Pdf(PDF.to_vec()))
+    }
+
+    pub fn status(self, route: &str, status: u16) -> Self {
+        self.route(route, Fixture::Status(status))
+    }
+
+    pub fn unavailable(self, route: &str, retry_after: u64) -> Self {
+        self.route(route, Fixture::Unavailable(retry_after))
+    }
+
+    pub fn slow(self, route: &str, delay: Duration, fixture: Fixture) -> Self {
+        self.route(route, Fixture::Slow(delay, Box::new(fixture)))
+    }
+
+    pub fn route(mut self, route: &str, fixture: Fixture) -> Self {
+        self.routes.insert(route_key(route), fixture);
+        self
+    }
+}
+
+// /path -> localhost/path, hosts are kept as they are
+fn route_key(route: &str) -> String {
+    match route.starts_with('/') {
+        true => format!("localhost{}", route),
+        false => route.into(),
+    }
+}
+
+// http://<host>:<port>/<path> of a route
+fn route_url(route: &str, port: u16) -> String {
+    let key = route_key(route);
+    let (host, path) = key.split_at(key.find('/').unwrap_or(key.len()));
+    let path = match path.is_empty() {
+        true => "/",
+        false => path,
+    };
+    format!("http://{}:{}{}", host, port, path)
+}
+
+fn render_page(title: &str, links: &[String], port: u16) -> String {
+    let links = links
+        .iter()
+        .map(|l| match l.starts_with("http") {
+            true => l.clone(),
+            false => route_url(l, port),
+        })
+        .map(|l| format!("<li><a href=\"{}\">{}</a></li>", l, l))
+        .collect::<String>();
+    format!(
+        "<!DOCTYPE html><html><head><title>{}</title></head><body><h1>{}</h1><ul>{}</ul></body></html>",
+        title, title, links
+    )
+}
+
+// Serves a fixture site until it is dropped, every request is logged
+pub struct FixtureServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FixtureServer {
+    pub fn start(site: FixtureSite) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind fixture server");
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let site = Arc::new(site);
+        let requests = Arc::new(Mutex::new(vec![]));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let requests = requests.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let site = site.clone();
+                            let requests = requests.clone();
+                            thread::spawn(move || {
+                                if let Err(e) = serve(stream, &site, &requests) {
+                                    eprintln!("fixture server error {}", e);
+                                }
+                            });
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(5)),
+                    }
+                }
+            })
+        };
+
+        FixtureServer {
+            addr,
+            requests,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // url of a route of the site, e.g. /a or other.localhost/a
+    pub fn url(&self, route: &str) -> String {
+        route_url(route, self.port())
+    }
+
+    // host and path of every request received so far
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for FixtureServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(
+    stream: TcpStream,
+    site: &FixtureSite,
+    requests: &Mutex<Vec<String>>,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("/")
+        .to_string();
+
+    let mut host = String::from("localhost");
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("host") {
+                // the port is the same for every host
+                host = value.trim().split(':').next().unwrap_or_default().into();
+            }
+        }
+    }
+
+    let key = format!("{}{}", host, path);
+    requests.lock().unwrap().push(key.clone());
+    let fixture = site
+        .routes
+        .get(&key)
+        .cloned()
+        .unwrap_or(Fixture::Status(404));
+    let port = stream.local_addr()?.port();
+    respond(stream, &fixture, port)
+}
+
+fn respond(mut stream: TcpStream, fixture: &Fixture, port: u16) -> std::io::Result<()> {
+    let (status, headers, body): (u16, Vec<(&str, String)>, Vec<u8>) = match fixture {
+        Fixture::Page { title, links } => (
+            200,
+            vec![("Content-Type", "text/html; charset=utf-8".into())],
+            render_page(title, links, port).into_bytes(),
+        ),
+        Fixture::Html(html) => (
+            200,
+            vec![("Content-Type", "text/html; charset=utf-8".into())],
+            html.as_bytes().to_vec(),
+        ),
+        Fixture::Pdf(pdf) => (
+            200,
+            vec![("Content-Type", "application/pdf".into())],
+            pdf.clone(),
+        ),
+        Fixture::Redirect(location) => (302, vec![("Location", location.clone())], vec![]),
+        Fixture::Status(status) => (
+            *status,
+            vec![("Content-Type", "text/html; charset=utf-8".into())],
+            format!("<html><body>{}</body></html>", status).into_bytes(),
+        ),
+        Fixture::Unavailable(retry_after) => {
+            (503, vec![("Retry-After", retry_after.to_string())], vec![])
+        }
+        Fixture::Slow(delay, fixture) => {
+            thread::sleep(*delay);
+            return respond(stream, fixture, port);
+        }
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        reason(status),
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        302 => "Found",
+        404 => "Not Found",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}